@@ -0,0 +1,173 @@
+use crate::interpreter::{Interpreter, RuntimeError, panic_message};
+use crate::builtins::channel::receive;
+use crate::sync::Actor;
+use crate::types::{Value, TypeUtils};
+
+use std::panic::{self, AssertUnwindSafe};
+
+
+pub fn register(interpreter: &mut Interpreter) {
+    interpreter.add_native_function("spawn_actor", spawn_actor);
+    interpreter.add_native_function("tell", tell);
+    interpreter.add_native_function("stop_actor", stop_actor);
+    interpreter.add_native_function("supervise", supervise);
+}
+
+fn get_actor<'a>(function_name: &str, value: &'a Value) -> &'a Actor {
+    match value {
+        Value::Actor(actor) => actor,
+        other => panic!("{} expects an actor but was given a value of type {}", function_name, other.get_type()),
+    }
+}
+
+/// This handles messages until the mailbox is closed and empty.
+/// A stateful actor passes what its handler returns on to the next message.
+/// If the handler fails the actor stops and every supervisor is sent `(actor, reason)`.
+fn run_actor(interpreter: &mut Interpreter, actor: Actor, handler: Value, mut state: Option<Value>) -> Result<Value, RuntimeError> {
+    let name = format!("actor {}", actor.id());
+    loop {
+        let message = match receive(actor.mailbox()) {
+            Ok(message) => message,
+            Err(RuntimeError::ChannelClosed) => return Ok(Value::Tuple(Vec::new())),
+            Err(error) => return Err(error),
+        };
+        let arguments = match state.take() {
+            Some(state) => vec![state, message],
+            None => vec![message],
+        };
+        let stateful = arguments.len() == 2;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| interpreter.call_function_value(&name, &handler, arguments)));
+        let reason = match result {
+            Ok(Ok(next_state)) => {
+                if stateful {
+                    state = Some(next_state);
+                }
+                continue;
+            },
+            Ok(Err(error)) => error.to_string(),
+            Err(payload) => panic_message(payload),
+        };
+
+        actor.stop();
+        for supervisor in actor.supervisors() {
+            let _ = supervisor.tell(Value::Tuple(vec![Value::Actor(actor.clone()), Value::string(&reason)]));
+        }
+        return Err(RuntimeError::ThreadPanicked(name, reason));
+    }
+}
+
+/// `fn spawn_actor((a) -> (), ...) -> Actor a`
+/// `fn spawn_actor((s, a) -> s, s) -> Actor a`
+/// The second form keeps state between messages, starting from the second argument.
+fn spawn_actor(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let (handler, state) = match arguments.as_slice() {
+        [handler @ Value::Function(..)] => (handler.clone(), None),
+        [handler @ Value::Function(..), state] => (handler.clone(), Some(state.clone())),
+        _ => panic!("spawn_actor expects a function and optionally a starting state"),
+    };
+    if !handler.is_sendable() || state.as_ref().is_some_and(|state| !state.is_sendable()) {
+        panic!("Tried to move a function into spawn_actor but it holds a reference to a mutable variable");
+    }
+    let actor = Actor::new();
+    let worker_actor = actor.clone();
+    let promise = interpreter.spawn_thread(&format!("mil-actor-{}", actor.id()), move |interpreter| {
+        run_actor(interpreter, worker_actor, handler, state)
+    });
+    interpreter.add_actor(actor.clone(), promise);
+    Ok(Value::Actor(actor))
+}
+
+/// `fn tell(Actor a, a) -> ()`
+/// Telling an actor that has stopped does nothing and telling it a message of the wrong type is an error.
+fn tell(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [actor, message] => {
+            let message = message.get_immutable();
+            if !message.is_sendable() {
+                panic!("Tried to send a value that holds a reference to a mutable variable");
+            }
+            get_actor("tell", actor).tell(message)?;
+        },
+        _ => panic!("tell takes 2 arguments but was given {}", arguments.len()),
+    }
+    Ok(Value::Tuple(Vec::new()))
+}
+
+/// `fn stop_actor(Actor a) -> ()`
+/// The actor handles the messages it already has before it stops.
+fn stop_actor(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [actor] => get_actor("stop_actor", actor).stop(),
+        _ => panic!("stop_actor takes 1 argument but was given {}", arguments.len()),
+    }
+    Ok(Value::Tuple(Vec::new()))
+}
+
+/// `fn supervise(Actor (Actor a, String), Actor a) -> ()`
+/// The supervisor is told `(actor, reason)` when the actor fails.
+fn supervise(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [supervisor, actor] => get_actor("supervise", actor).add_supervisor(get_actor("supervise", supervisor).clone()),
+        _ => panic!("supervise takes 2 arguments but was given {}", arguments.len()),
+    }
+    Ok(Value::Tuple(Vec::new()))
+}
+
+
+#[cfg(test)]
+mod actor_tests {
+    use super::*;
+    use crate::parser::file_parser::file_parser_helper;
+
+    use std::collections::HashMap;
+
+    fn add(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        match arguments.as_slice() {
+            [Value::UInt(x), Value::UInt(y)] => Ok(Value::UInt(x + y)),
+            _ => panic!("add expects two UInts"),
+        }
+    }
+
+    fn run(program: &str) -> Interpreter {
+        let mut interpreter = Interpreter::with_worker_count(2);
+        interpreter.add_native_function("add", add);
+        file_parser_helper(program, &mut interpreter);
+        interpreter.start_program().unwrap();
+        interpreter
+    }
+
+    fn load(interpreter: &Interpreter, name: &str) -> Value {
+        match interpreter.get_value(name, &HashMap::new()).unwrap() {
+            Some(Value::Atomic(atomic)) => atomic.load(),
+            other => panic!("{} was {:?}", name, other),
+        }
+    }
+
+    #[test]
+    fn test_messages_are_handled_in_order() {
+        let interpreter = run("@Atomic\ntotal := 0u;\n\
+                               fn count(sum: UInt, n: UInt) -> UInt { store(total, add(sum, n)); add(sum, n) }\n\
+                               fn main() -> () { counter = spawn_actor(count, 0u); tell(counter, 1u); tell(counter, 2u); tell(counter, 3u); }");
+
+        assert!(matches!(load(&interpreter, "total"), Value::UInt(6)), "Actor did not handle every message before main ended");
+    }
+
+    #[test]
+    fn test_supervisor_is_told_about_failures() {
+        let interpreter = run("@Atomic\nfailures := 0u;\n\
+                               fn crash(n: UInt) -> () { missing(n) }\n\
+                               fn watch(failure) -> () { fetch_add(failures, 1u); }\n\
+                               fn main() -> () { supervisor = spawn_actor(watch); worker = spawn_actor(crash); supervise(supervisor, worker); tell(worker, 1u); tell(worker, 2u); }");
+
+        assert!(matches!(load(&interpreter, "failures"), Value::UInt(1)), "Supervisor was not told exactly once");
+    }
+
+    #[test]
+    fn test_telling_a_stopped_actor() {
+        let interpreter = run("@Atomic\nseen := 0u;\n\
+                               fn note(n: UInt) -> () { fetch_add(seen, 1u); }\n\
+                               fn main() -> () { actor = spawn_actor(note); tell(actor, 1u); stop_actor(actor); tell(actor, 2u); }");
+
+        assert!(matches!(load(&interpreter, "seen"), Value::UInt(1)), "Stopped actor handled a message");
+    }
+}
@@ -0,0 +1,119 @@
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::types::{Value, AtomicValue, TypeUtils};
+
+
+pub fn register(interpreter: &mut Interpreter) {
+    interpreter.add_native_function("load", load);
+    interpreter.add_native_function("store", store);
+    interpreter.add_native_function("swap", swap);
+    interpreter.add_native_function("fetch_add", fetch_add);
+    interpreter.add_native_function("fetch_sub", fetch_sub);
+    interpreter.add_native_function("compare_exchange", compare_exchange);
+}
+
+fn get_atomic<'a>(function_name: &str, arguments: &'a [Value], count: usize) -> &'a AtomicValue {
+    if arguments.len() != count {
+        panic!("{} takes {} arguments but was given {}", function_name, count, arguments.len());
+    }
+    match &arguments[0] {
+        Value::Atomic(atomic) => atomic,
+        other => panic!("{} expects an atomic global variable but was given a value of type {}", function_name, other.get_type()),
+    }
+}
+
+/// `fn load(Atomic a) -> a`
+fn load(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    Ok(get_atomic("load", &arguments, 1).load())
+}
+
+/// `fn store(Atomic a, a) -> ()`
+fn store(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    get_atomic("store", &arguments, 2).store(&arguments[1]);
+    Ok(Value::Tuple(Vec::new()))
+}
+
+/// `fn swap(Atomic a, a) -> a`
+fn swap(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    Ok(get_atomic("swap", &arguments, 2).swap(&arguments[1]))
+}
+
+/// `fn fetch_add(Atomic a, a) -> a`
+fn fetch_add(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    Ok(get_atomic("fetch_add", &arguments, 2).fetch_add(&arguments[1]))
+}
+
+/// `fn fetch_sub(Atomic a, a) -> a`
+fn fetch_sub(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    Ok(get_atomic("fetch_sub", &arguments, 2).fetch_sub(&arguments[1]))
+}
+
+/// `fn compare_exchange(Atomic a, a, a) -> a`
+fn compare_exchange(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    Ok(get_atomic("compare_exchange", &arguments, 3).compare_exchange(&arguments[1], &arguments[2]))
+}
+
+
+#[cfg(test)]
+mod atomic_tests {
+    use super::*;
+    use crate::parser::file_parser::file_parser_helper;
+
+    use std::collections::HashMap;
+    use std::thread;
+
+    #[test]
+    fn test_atomic_global() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("@Atomic\ncounter := 41;", &mut interpreter);
+
+        let counter = interpreter.get_value("counter", &HashMap::new()).unwrap().unwrap();
+
+        assert!(matches!(counter, Value::Atomic(AtomicValue::Int(_))), "counter is not an atomic Int");
+        assert!(matches!(counter.get_immutable(), Value::Int(41)), "counter did not load its value");
+    }
+
+    #[test]
+    fn test_fetch_add_across_threads() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("@Atomic\ncounter := 0u;", &mut interpreter);
+
+        let workers: Vec<_> = (0..8).map(|_| {
+            let mut interpreter = interpreter.new_for_thread();
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    let counter = interpreter.get_value("counter", &HashMap::new()).unwrap().unwrap();
+                    interpreter.call_function("fetch_add", vec![counter, Value::Int(1)], HashMap::new()).unwrap();
+                }
+            })
+        }).collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let counter = interpreter.get_value("counter", &HashMap::new()).unwrap().unwrap();
+        assert!(matches!(interpreter.call_function("load", vec![counter], HashMap::new()).unwrap(), Value::UInt(8000)), "Some increments were lost");
+    }
+
+    #[test]
+    fn test_compare_exchange_and_swap() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("@Atomic\nflag := 0;", &mut interpreter);
+        let flag = interpreter.get_value("flag", &HashMap::new()).unwrap().unwrap();
+
+        let failed = interpreter.call_function("compare_exchange", vec![flag.clone(), Value::Int(1), Value::Int(2)], HashMap::new()).unwrap();
+        let succeeded = interpreter.call_function("compare_exchange", vec![flag.clone(), Value::Int(0), Value::Int(2)], HashMap::new()).unwrap();
+        let swapped = interpreter.call_function("swap", vec![flag.clone(), Value::Int(3)], HashMap::new()).unwrap();
+
+        assert!(matches!(failed, Value::Int(0)), "compare_exchange should have failed and returned the current value");
+        assert!(matches!(succeeded, Value::Int(0)), "compare_exchange should have succeeded");
+        assert!(matches!(swapped, Value::Int(2)), "swap did not return the previous value");
+        assert!(matches!(flag.get_immutable(), Value::Int(3)), "swap did not store the new value");
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit in an atomic Byte")]
+    fn test_byte_out_of_range() {
+        let atomic = AtomicValue::new(Value::Byte(0)).unwrap();
+        atomic.fetch_add(&Value::Int(256));
+    }
+}
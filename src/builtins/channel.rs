@@ -0,0 +1,171 @@
+use chumsky::prelude::*;
+
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::parser::lexer::lexer;
+use crate::parser::type_parser::type_parser;
+use crate::sync::{Channel, ChannelError};
+use crate::thread_pool::wait_helping;
+use crate::types::{Type, Value, TypeUtils};
+
+use std::time::Duration;
+
+
+pub fn register(interpreter: &mut Interpreter) {
+    interpreter.add_native_function("channel", channel);
+    interpreter.add_native_function("bounded_channel", bounded_channel);
+    interpreter.add_native_function("send", send);
+    interpreter.add_native_function("recv", recv);
+    interpreter.add_native_function("try_recv", try_recv);
+    interpreter.add_native_function("close", close);
+}
+
+fn get_channel<'a>(function_name: &str, arguments: &'a [Value], count: usize) -> &'a Channel {
+    if arguments.len() != count {
+        panic!("{} takes {} arguments but was given {}", function_name, count, arguments.len());
+    }
+    match &arguments[0] {
+        Value::Channel(channel) => channel,
+        other => panic!("{} expects a channel but was given a value of type {}", function_name, other.get_type()),
+    }
+}
+
+/// This receives from a channel without tying up a worker of the thread pool.
+/// It is also what `for x in channel` uses.
+pub fn receive(channel: &Channel) -> Result<Value, RuntimeError> {
+    let result = wait_helping(None, || channel.poll_recv()).expect("Waiting without a deadline never times out");
+    match result {
+        Err(ChannelError::Closed) => Err(RuntimeError::ChannelClosed),
+        Err(error) => unreachable!("Receiving without a deadline failed with {:?}", error),
+        Ok(value) => Ok(value),
+    }
+}
+
+/// This reads the name of the type a channel holds, such as `"UInt"` or `"Maybe Int"`.
+fn element_type(function_name: &str, name: &Value) -> Type {
+    let name = name.as_string().unwrap_or_else(|| panic!("{} expects the name of a type but was given a value of type {}", function_name, name.get_type()));
+    lexer(&name).ok()
+        .and_then(|tokens| type_parser().then_ignore(end()).parse(tokens).ok())
+        .unwrap_or_else(|| panic!("{} was given {:?} which is not a type", function_name, name))
+}
+
+fn capacity(value: &Value) -> usize {
+    match value {
+        Value::UInt(capacity) => *capacity as usize,
+        Value::Int(capacity) if *capacity > 0 => *capacity as usize,
+        _ => panic!("bounded_channel expects a positive capacity"),
+    }
+}
+
+/// `fn channel() -> Channel a`
+/// `fn channel(String) -> Channel a`
+/// The second form names the type of the values, otherwise the first value that is sent decides it.
+fn channel(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [] => Ok(Value::Channel(Channel::new(None, None))),
+        [name] => Ok(Value::Channel(Channel::new(None, Some(element_type("channel", name))))),
+        _ => panic!("channel takes at most 1 argument but was given {}", arguments.len()),
+    }
+}
+
+/// `fn bounded_channel(UInt) -> Channel a`
+/// `fn bounded_channel(String, UInt) -> Channel a`
+fn bounded_channel(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [size] => Ok(Value::Channel(Channel::new(Some(capacity(size)), None))),
+        [name, size] => Ok(Value::Channel(Channel::new(Some(capacity(size)), Some(element_type("bounded_channel", name))))),
+        _ => panic!("bounded_channel takes 1 or 2 arguments but was given {}", arguments.len()),
+    }
+}
+
+/// `fn send(Channel a, a) -> ()`
+fn send(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let channel = get_channel("send", &arguments, 2);
+    let value = arguments[1].get_immutable();
+    if !value.is_sendable() {
+        panic!("Tried to send a value that holds a reference to a mutable variable");
+    }
+    let value_type = value.get_type();
+    let mut value = Some(value);
+    let result = wait_helping(None, || channel.poll_send(&mut value)).expect("Waiting without a deadline never times out");
+    match result {
+        Err(ChannelError::Closed) => Err(RuntimeError::ChannelClosed),
+        Err(ChannelError::WrongType(element_type)) => Err(RuntimeError::ChannelTypeMismatch(element_type, value_type)),
+        Err(ChannelError::TimedOut) => unreachable!("Sending without a deadline timed out"),
+        Ok(()) => Ok(Value::Tuple(Vec::new())),
+    }
+}
+
+/// `fn recv(Channel a) -> a`
+fn recv(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    receive(get_channel("recv", &arguments, 1))
+}
+
+/// `fn try_recv(Channel a) -> Maybe a`
+fn try_recv(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let channel = get_channel("try_recv", &arguments, 1);
+    match channel.recv(Some(Duration::ZERO)) {
+        Ok(value) => Ok(Value::just(value)),
+        Err(_) => Ok(Value::nothing(channel.element_type())),
+    }
+}
+
+/// `fn close(Channel a) -> ()`
+fn close(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    get_channel("close", &arguments, 1).close();
+    Ok(Value::Tuple(Vec::new()))
+}
+
+
+#[cfg(test)]
+mod channel_tests {
+    use super::*;
+    use crate::parser::file_parser::file_parser_helper;
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_producer_and_consumer() {
+        let mut interpreter = Interpreter::with_worker_count(2);
+        let program = "@ThreadShared\njobs = bounded_channel(2u);\n@Atomic\ntotal := 0u;\n\
+                       @ThreadSpawn\nfn produce() -> () { send(jobs, 1u); send(jobs, 2u); send(jobs, 3u); send(jobs, 4u); close(jobs) }\n\
+                       fn main() -> UInt { produce(); for x in jobs { fetch_add(total, x); } load(total) }";
+        file_parser_helper(program, &mut interpreter);
+
+        let result = interpreter.call_function("main", vec![], HashMap::new());
+
+        assert!(matches!(result, Ok(Value::UInt(10))), "Not every value made it through the channel");
+    }
+
+    #[test]
+    fn test_try_recv_and_closed_channel() {
+        let mut interpreter = Interpreter::new();
+        let chan = interpreter.call_function("channel", vec![], HashMap::new()).unwrap();
+
+        let empty = interpreter.call_function("try_recv", vec![chan.clone()], HashMap::new()).unwrap();
+        interpreter.call_function("send", vec![chan.clone(), Value::Int(5)], HashMap::new()).unwrap();
+        let full = interpreter.call_function("try_recv", vec![chan.clone()], HashMap::new()).unwrap();
+        interpreter.call_function("close", vec![chan.clone()], HashMap::new()).unwrap();
+
+        assert!(matches!(empty, Value::Algebraic{ref values, ..} if values.contains_key(&crate::types::Type::Single("Nothing".to_string()))), "Empty channel should give Nothing");
+        assert!(matches!(full, Value::Algebraic{ref values, ..} if matches!(values.get(&crate::types::Type::Single("Just".to_string())), Some(Value::Int(5)))), "try_recv did not give back the value");
+        assert_eq!(interpreter.call_function("recv", vec![chan.clone()], HashMap::new()).err(), Some(RuntimeError::ChannelClosed));
+        assert_eq!(interpreter.call_function("send", vec![chan, Value::Int(6)], HashMap::new()).err(), Some(RuntimeError::ChannelClosed));
+    }
+
+    #[test]
+    fn test_typed_channels() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("fn make() -> Any { numbers = bounded_channel(\"UInt\", 2u); empty = try_recv(numbers); send(numbers, 1u); (numbers, empty) }", &mut interpreter);
+
+        let made = interpreter.call_function("make", vec![], HashMap::new()).unwrap();
+        let (numbers, empty) = match made {
+            Value::Tuple(values) => (values[0].clone(), values[1].clone()),
+            other => panic!("Expected a tuple but got {:?}", other),
+        };
+        let wrong = interpreter.call_function("send", vec![numbers.clone(), Value::Char('a')], HashMap::new());
+
+        assert!(matches!(empty, Value::Algebraic{ref values, ..} if values.contains_key(&Type::Single("Nothing".to_string()))), "A typed channel should start empty");
+        assert_eq!(wrong.err(), Some(RuntimeError::ChannelTypeMismatch(Type::Single("UInt".to_string()), Type::Single("Char".to_string()))));
+        assert!(matches!(interpreter.call_function("recv", vec![numbers], HashMap::new()), Ok(Value::UInt(1))), "The value sent before the mismatch was lost");
+    }
+}
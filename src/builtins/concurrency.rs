@@ -0,0 +1,196 @@
+use crate::interpreter::{Interpreter, RuntimeError, await_value};
+use crate::sync::{CancelToken, Promise};
+use crate::thread_pool::wait_helping;
+use crate::types::{Type, Value, TypeUtils};
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+
+pub fn register(interpreter: &mut Interpreter) {
+    interpreter.add_native_function("all", all);
+    interpreter.add_native_function("race", race);
+    interpreter.add_promise_function("timeout", timeout);
+    interpreter.add_native_function("sleep", sleep);
+    interpreter.add_native_function("cancel_token", cancel_token);
+    interpreter.add_native_function("cancel", cancel);
+    interpreter.add_native_function("is_cancelled", is_cancelled);
+    interpreter.add_native_function("check", check);
+}
+
+fn milliseconds(function_name: &str, value: &Value) -> Duration {
+    match value {
+        Value::UInt(milliseconds) => Duration::from_millis(*milliseconds),
+        Value::Int(milliseconds) if *milliseconds >= 0 => Duration::from_millis(*milliseconds as u64),
+        other => panic!("{} expects a number of milliseconds but was given a value of type {}", function_name, other.get_type()),
+    }
+}
+
+fn get_list<'a>(function_name: &str, arguments: &'a [Value]) -> &'a [Value] {
+    match arguments {
+        [Value::List(values, _)] => values,
+        _ => panic!("{} expects a list of promises", function_name),
+    }
+}
+
+fn get_token<'a>(function_name: &str, arguments: &'a [Value]) -> &'a CancelToken {
+    match arguments {
+        [Value::CancelToken(token)] => token,
+        _ => panic!("{} expects a cancel token", function_name),
+    }
+}
+
+/// `fn all([Promise a]) -> [a]`
+fn all(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let mut values = Vec::new();
+    for promise in get_list("all", &arguments) {
+        values.push(await_value(promise.clone())?);
+    }
+    let element_type = values.first().map(|value| value.get_type()).unwrap_or(Type::Single("a".to_string()));
+    Ok(Value::List(values.into(), element_type))
+}
+
+/// `fn race([Promise a]) -> a`
+/// The promises that lose keep running, so pass them a cancel token if they should stop.
+fn race(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let mut promises = Vec::new();
+    for value in get_list("race", &arguments) {
+        match value {
+            Value::Promise(promise, _) => promises.push(promise.clone()),
+            // A value that isn't a promise has already finished so it wins
+            finished => return Ok(finished.clone()),
+        }
+    }
+    if promises.is_empty() {
+        panic!("race needs at least one promise");
+    }
+    let first = wait_helping(None, || promises.iter().find_map(Promise::try_result)).expect("Waiting without a deadline never times out");
+    await_value(first?)
+}
+
+/// `fn timeout(Promise a, UInt) -> Maybe a`
+fn timeout(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    if arguments.len() != 2 {
+        panic!("timeout takes 2 arguments but was given {}", arguments.len());
+    }
+    let deadline = Instant::now() + milliseconds("timeout", &await_value(arguments[1].clone())?);
+    let (promise, the_type) = match &arguments[0] {
+        Value::Promise(promise, the_type) => (promise, the_type),
+        finished => return Ok(Value::just(finished.clone())),
+    };
+    match wait_helping(Some(deadline), || promise.try_result()) {
+        Some(result) => Ok(Value::just(await_value(result?)?)),
+        None => Ok(Value::nothing(the_type.clone())),
+    }
+}
+
+/// `fn sleep(UInt) -> ()`
+fn sleep(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [duration] => thread::sleep(milliseconds("sleep", duration)),
+        _ => panic!("sleep takes 1 argument but was given {}", arguments.len()),
+    }
+    Ok(Value::Tuple(Vec::new()))
+}
+
+/// `fn cancel_token() -> CancelToken`
+fn cancel_token(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    if !arguments.is_empty() {
+        panic!("cancel_token takes 0 arguments but was given {}", arguments.len());
+    }
+    Ok(Value::CancelToken(CancelToken::new()))
+}
+
+/// `fn cancel(CancelToken) -> ()`
+fn cancel(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    get_token("cancel", &arguments).cancel();
+    Ok(Value::Tuple(Vec::new()))
+}
+
+/// `fn is_cancelled(CancelToken) -> Bool`
+fn is_cancelled(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    Ok(Value::bool(get_token("is_cancelled", &arguments).is_cancelled()))
+}
+
+/// `fn check(Bool) -> ()`
+/// Inside of an `atomically` block this retries the transaction until the condition holds.
+fn check(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [Value::Algebraic{name, values, ..}] if name == "Bool" => {
+            if values.contains_key(&Type::Single("True".to_string())) {
+                Ok(Value::Tuple(Vec::new()))
+            }
+            else {
+                Err(RuntimeError::TransactionRetry)
+            }
+        },
+        _ => panic!("check expects a Bool"),
+    }
+}
+
+
+#[cfg(test)]
+mod concurrency_tests {
+    use super::*;
+    use crate::parser::file_parser::file_parser_helper;
+
+    use std::collections::HashMap;
+
+    const TASKS: &str = "@Atomic\ndone := 0u;\n\
+                         @ThreadSpawn\nfn work() -> UInt { sleep(20u); fetch_add(done, 1u) }\n\
+                         @ThreadSpawn\nfn parent() -> UInt { work(); work(); 0u }\n\
+                         @ThreadSpawn\nfn slow() -> UInt { sleep(500u); 1u }\n\
+                         @ThreadSpawn\nfn fast() -> UInt { 2u }\n\
+                         @ThreadSpawn\nfn boom() -> UInt { missing() }\n";
+
+    fn run(return_type: &str, body: &str) -> Result<Value, RuntimeError> {
+        let mut interpreter = Interpreter::with_worker_count(4);
+        file_parser_helper(&format!("{}fn main() -> {} {{ {} }}", TASKS, return_type, body), &mut interpreter);
+        interpreter.call_function("main", vec![], HashMap::new())
+    }
+
+    fn is_case(value: &Value, case: &str) -> bool {
+        matches!(value, Value::Algebraic{values, ..} if values.contains_key(&Type::Single(case.to_string())))
+    }
+
+    #[test]
+    fn test_scope_waits_for_nested_tasks() {
+        let result = run("UInt", "scope { work(); parent(); } load(done)");
+
+        assert!(matches!(result, Ok(Value::UInt(3))), "Scope ended before its tasks finished: {:?}", result);
+    }
+
+    #[test]
+    fn test_scope_reports_task_errors() {
+        let result = run("UInt", "scope { work(); boom(); } load(done)");
+
+        assert!(matches!(result, Err(RuntimeError::ThreadPanicked(ref function, _)) if function == "boom"), "Scope did not report the failed task");
+    }
+
+    #[test]
+    fn test_all_and_race() {
+        let all = run("(List UInt)", "all([fast(), work()])");
+        let race = run("UInt", "race([slow(), fast()])");
+
+        assert!(matches!(all, Ok(Value::List(ref values, _)) if matches!(values[..], [Value::UInt(2), Value::UInt(0)])), "all did not wait for every promise: {:?}", all);
+        assert!(matches!(race, Ok(Value::UInt(2))), "race did not return the first promise to finish");
+    }
+
+    #[test]
+    fn test_timeout() {
+        let expired = run("(Maybe UInt)", "timeout(slow(), 10u)").unwrap();
+        let finished = run("(Maybe UInt)", "timeout(fast(), 1000u)").unwrap();
+
+        assert!(is_case(&expired, "Nothing"), "Slow promise should have timed out");
+        assert!(is_case(&finished, "Just"), "Fast promise should have finished in time");
+    }
+
+    #[test]
+    fn test_cancel_token() {
+        let before = run("Bool", "{ t = cancel_token(); is_cancelled(t) }").unwrap();
+        let after = run("Bool", "{ t = cancel_token(); cancel(t); is_cancelled(t) }").unwrap();
+
+        assert!(is_case(&before, "False"), "New token should not be cancelled");
+        assert!(is_case(&after, "True"), "Token was not cancelled");
+    }
+}
@@ -0,0 +1,293 @@
+use lazy_static::lazy_static;
+
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::builtins::parse_prelude;
+use crate::parser::file_parser::{TopLevelStatement, register_statements};
+use crate::types::{Type, Value, TypeUtils, AlgebraicType};
+
+use std::io::{self, BufRead, Write};
+
+
+/// Programs can give their types a `Show` instance, and everything else is shown by its structure.
+const PRELUDE: &str = "
+class (Show a) {
+    fn show(a) -> String
+}
+";
+
+lazy_static! {
+    static ref PRELUDE_STATEMENTS: Vec<TopLevelStatement> = parse_prelude("console", PRELUDE);
+}
+
+/// The console functions perform the `Console` effect, which the interpreter provides instead of a handler.
+pub fn register(interpreter: &mut Interpreter) {
+    interpreter.add_native_function("show", show);
+    interpreter.add_native_function("print", print);
+    interpreter.add_native_function("println", println);
+    interpreter.add_native_function("eprint", eprint);
+    interpreter.add_native_function("eprintln", eprintln);
+    interpreter.add_native_function("readline", readline);
+    for name in ["print", "println", "eprint", "eprintln"] {
+        interpreter.declare_native_effects(name, &["Console"]);
+    }
+    interpreter.declare_native_effects("readline", &["Console", "exn"]);
+    register_statements(PRELUDE_STATEMENTS.clone(), interpreter);
+}
+
+/// This is the text of a value, using its `Show` instance if it has one.
+/// Without an instance a string or character on its own is shown as it is, but inside of another value it is quoted.
+pub fn show_value(interpreter: &mut Interpreter, value: &Value) -> Result<String, RuntimeError> {
+    if let Some(text) = show_instance(interpreter, value)? {
+        return Ok(text);
+    }
+    match value {
+        Value::Char(c) => Ok(c.to_string()),
+        value => match text_of(value) {
+            Some(text) => Ok(text),
+            None => show_nested(interpreter, value),
+        },
+    }
+}
+
+/// An empty list is only a string if it was made as one, so that `[]` isn't shown as nothing at all.
+fn text_of(value: &Value) -> Option<String> {
+    match value {
+        Value::List(values, Type::Single(element)) if values.is_empty() && element == "Char" => Some(String::new()),
+        Value::List(values, _) if values.is_empty() => None,
+        value => value.as_string(),
+    }
+}
+
+fn show_instance(interpreter: &mut Interpreter, value: &Value) -> Result<Option<String>, RuntimeError> {
+    match interpreter.typeclass_function("show", &value.get_type()) {
+        Some(function) => {
+            let text = interpreter.call_function_value("show", &function, vec![value.clone()])?;
+            Ok(Some(text.as_string().unwrap_or_else(|| panic!("show has to give back a String but gave back a value of type {}", text.get_type()))))
+        },
+        None => Ok(None),
+    }
+}
+
+fn show_nested(interpreter: &mut Interpreter, value: &Value) -> Result<String, RuntimeError> {
+    if let Some(text) = show_instance(interpreter, value)? {
+        return Ok(text);
+    }
+
+    let show_all = |interpreter: &mut Interpreter, values: &mut dyn Iterator<Item = &Value>| -> Result<String, RuntimeError> {
+        let mut texts = Vec::new();
+        for value in values {
+            texts.push(show_nested(interpreter, value)?);
+        }
+        Ok(texts.join(", "))
+    };
+
+    Ok(match value {
+        Value::Int(i) => i.to_string(),
+        Value::UInt(i) => i.to_string(),
+        Value::Float(f) => format!("{:?}", f),
+        Value::Byte(b) => b.to_string(),
+        Value::Char(c) => format!("{:?}", c),
+        Value::List(values, _) => match text_of(value) {
+            Some(text) => format!("{:?}", text),
+            None => format!("[{}]", show_all(interpreter, &mut values.iter())?),
+        },
+        Value::HList(values) => format!("[{}]", show_all(interpreter, &mut values.iter())?),
+        Value::Tuple(values) => format!("({})", show_all(interpreter, &mut values.iter())?),
+        Value::Algebraic{agb_type: AlgebraicType::Sum, values, ..} => {
+            let (case, payload) = values.iter().next().expect("A sum type value always has a case");
+            match payload {
+                Value::Tuple(fields) if fields.is_empty() => case.to_string(),
+                payload => format!("{}({})", case, show_nested(interpreter, payload)?),
+            }
+        },
+        Value::Algebraic{agb_type: AlgebraicType::Product, name, values, ..} => {
+            let mut fields: Vec<_> = values.iter().collect();
+            fields.sort_by_key(|(field, _)| field.to_string());
+            let mut texts = Vec::new();
+            for (field, value) in fields {
+                texts.push(format!("{}: {}", field, show_nested(interpreter, value)?));
+            }
+            format!("{} {{ {} }}", name, texts.join(", "))
+        },
+        Value::Alias{value, ..} => show_nested(interpreter, value)?,
+        Value::Ref(reference) => {
+            let value = reference.borrow().clone();
+            show_nested(interpreter, &value)?
+        },
+        Value::Atomic(atomic) => show_nested(interpreter, &atomic.load())?,
+        Value::File(file) => format!("<File {}>", file.path()),
+        // Anything else can't be looked inside of so only its type is shown
+        other => format!("<{}>", other.get_type()),
+    })
+}
+
+fn shown_argument(interpreter: &mut Interpreter, function_name: &str, arguments: &[Value]) -> Result<String, RuntimeError> {
+    match arguments {
+        [value] => show_value(interpreter, value),
+        _ => panic!("{} takes 1 argument but was given {}", function_name, arguments.len()),
+    }
+}
+
+fn write_stdout(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let mut stdout = io::stdout().lock();
+    for argument in arguments {
+        let text = argument.as_string().expect("Only strings are written to stdout");
+        stdout.write_all(text.as_bytes()).expect("Unable to write to stdout");
+    }
+    stdout.flush().expect("Unable to write to stdout");
+    Ok(Value::Tuple(Vec::new()))
+}
+
+fn write_stderr(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let mut stderr = io::stderr().lock();
+    for argument in arguments {
+        let text = argument.as_string().expect("Only strings are written to stderr");
+        stderr.write_all(text.as_bytes()).expect("Unable to write to stderr");
+    }
+    Ok(Value::Tuple(Vec::new()))
+}
+
+/// This reads a line from stdin without its line ending.
+/// Reading once stdin has run out throws.
+fn read_stdin_line(interpreter: &mut Interpreter, _: Vec<Value>) -> Result<Value, RuntimeError> {
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) => interpreter.throw("Unable to read a line: stdin has no more lines"),
+        Ok(_) => {
+            let length = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(length);
+            Ok(Value::string(&line))
+        },
+        Err(error) => interpreter.throw(&format!("Unable to read a line: {}", error)),
+    }
+}
+
+/// `fn show(Show a) -> String`
+fn show(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    Ok(Value::string(&shown_argument(interpreter, "show", &arguments)?))
+}
+
+/// `fn print(Show a) Console -> IO ()`
+/// The value is shown right away, but nothing is printed until the action runs.
+fn print(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let text = shown_argument(interpreter, "print", &arguments)?;
+    Ok(Value::native_io(write_stdout, vec![Value::string(&text)], Type::Unit))
+}
+
+/// `fn println(Show a) Console -> IO ()`
+fn println(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let text = shown_argument(interpreter, "println", &arguments)? + "\n";
+    Ok(Value::native_io(write_stdout, vec![Value::string(&text)], Type::Unit))
+}
+
+/// `fn eprint(Show a) Console -> IO ()`
+fn eprint(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let text = shown_argument(interpreter, "eprint", &arguments)?;
+    Ok(Value::native_io(write_stderr, vec![Value::string(&text)], Type::Unit))
+}
+
+/// `fn eprintln(Show a) Console -> IO ()`
+fn eprintln(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let text = shown_argument(interpreter, "eprintln", &arguments)? + "\n";
+    Ok(Value::native_io(write_stderr, vec![Value::string(&text)], Type::Unit))
+}
+
+/// `fn readline() Console exn -> IO String`
+fn readline(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    if !arguments.is_empty() {
+        panic!("readline takes no arguments but was given {}", arguments.len());
+    }
+    let string_type = Type::TypeList{name: Box::new(Type::Single("List".to_string())), parameters: vec![Type::Single("Char".to_string())]};
+    Ok(Value::native_io(read_stdin_line, Vec::new(), string_type))
+}
+
+
+#[cfg(test)]
+mod console_tests {
+    use super::*;
+    use crate::parser::file_parser::file_parser_helper;
+    use crate::types::IoAction;
+    use crate::effect_checker::EffectError;
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_hello_world_with_monads() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("fn main() -> IO () {\n    println(\"Hello, World!\")\n}", &mut interpreter);
+
+        assert_eq!(interpreter.start_program(), Ok(()));
+    }
+
+    #[test]
+    fn test_console_effect_is_checked() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("fn greet() -> IO () { println(\"Hello\") }\nfn main() -> IO () { greet() }", &mut interpreter);
+
+        let errors = interpreter.check_effects().unwrap_err();
+
+        assert_eq!(errors, vec![EffectError::Undeclared { function: "greet".to_string(), effect: "Console".to_string() }], "Only main returning IO may leave Console undeclared");
+    }
+
+    fn show_text(interpreter: &mut Interpreter, value: Value) -> String {
+        show_value(interpreter, &value).unwrap()
+    }
+
+    #[test]
+    fn test_structural_show() {
+        let mut interpreter = Interpreter::new();
+
+        assert_eq!(show_text(&mut interpreter, Value::string("plain")), "plain");
+        assert_eq!(show_text(&mut interpreter, Value::Float(1.0)), "1.0");
+        assert_eq!(show_text(&mut interpreter, Value::List(vec![Value::UInt(1), Value::UInt(2)].into(), Type::Single("UInt".to_string()))), "[1, 2]");
+        assert_eq!(show_text(&mut interpreter, Value::Tuple(vec![Value::Int(-1), Value::string("a"), Value::Char('b')])), "(-1, \"a\", 'b')");
+        assert_eq!(show_text(&mut interpreter, Value::just(Value::bool(true))), "Just(True)");
+        assert_eq!(show_text(&mut interpreter, Value::nothing(Type::Single("UInt".to_string()))), "Nothing");
+    }
+
+    fn shout(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        match arguments.as_slice() {
+            [Value::UInt(n)] => Ok(Value::string(&format!("{}!", n))),
+            _ => panic!("shout expects a UInt"),
+        }
+    }
+
+    #[test]
+    fn test_show_instances_for_generic_types_and_strings() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("fn maybe(m) -> String { \"maybe\" }\nfn whole(m) -> String { \"whole\" }\nfn text(s: String) -> String { \"text\" }", &mut interpreter);
+        let show_class = Type::TypeList{name: Box::new(Type::Single("Show".to_string())), parameters: vec![Type::Single("a".to_string())]};
+        let maybe_of = |parameter: &str| Type::TypeList{name: Box::new(Type::Single("Maybe".to_string())), parameters: vec![Type::Single(parameter.to_string())]};
+        for (instance, name) in [(maybe_of("a"), "maybe"), (maybe_of("UInt"), "whole"), (Type::Single("String".to_string()), "text")] {
+            let function = interpreter.get_value(name, &HashMap::new()).unwrap().unwrap();
+            interpreter.add_typeclass_instance(show_class.clone(), instance, vec![("show".to_string(), function)]);
+        }
+
+        let nested = Value::List(vec![Value::just(Value::Int(1)), Value::just(Value::UInt(2))].into(), maybe_of("Int"));
+
+        assert_eq!(show_text(&mut interpreter, Value::just(Value::Int(1))), "maybe", "The (Maybe a) instance did not match Maybe Int");
+        assert_eq!(show_text(&mut interpreter, nested), "[maybe, whole]", "The more specific instance did not win");
+        assert_eq!(show_text(&mut interpreter, Value::string("hello")), "text", "A string on its own skipped its Show instance");
+        assert_eq!(show_text(&mut interpreter, Value::Char('c')), "c");
+    }
+
+    #[test]
+    fn test_print_uses_show_instance() {
+        let mut interpreter = Interpreter::new();
+        interpreter.add_native_function("shout", shout);
+        file_parser_helper("fn loud(n: UInt) -> String { shout(n) }", &mut interpreter);
+        let loud = interpreter.get_value("loud", &HashMap::new()).unwrap().unwrap();
+        let show_class = Type::TypeList{name: Box::new(Type::Single("Show".to_string())), parameters: vec![Type::Single("a".to_string())]};
+        interpreter.add_typeclass_instance(show_class, Type::Single("UInt".to_string()), vec![("show".to_string(), loud)]);
+
+        let printed = interpreter.call_function("println", vec![Value::List(vec![Value::UInt(3)].into(), Type::Single("UInt".to_string()))], HashMap::new()).unwrap();
+
+        match printed {
+            Value::IO(action, _) => match action.as_ref() {
+                IoAction::Native(_, arguments) => assert_eq!(arguments[0].as_string().as_deref(), Some("[3!]\n")),
+                other => panic!("println made the wrong action: {:?}", other),
+            },
+            other => panic!("println should give back IO but gave {:?}", other),
+        }
+    }
+}
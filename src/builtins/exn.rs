@@ -0,0 +1,156 @@
+use lazy_static::lazy_static;
+
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::builtins::parse_prelude;
+use crate::parser::file_parser::{TopLevelStatement, register_statements};
+use crate::types::{Value, TypeUtils};
+
+
+/// This is the part of the `exn` effect that is written in the language itself.
+/// An exception that nothing catches reaches `uncaught-exn` once it escapes main, which ends the program.
+const PRELUDE: &str = "
+effect exn {
+    @Control
+    fn throw-exn(String) -> a,
+    @Final
+    fn uncaught-exn(message: String) -> () {
+        panic(message)
+    }
+}
+
+fn throw(message: String) exn -> a {
+    throw-exn(message)
+}
+
+fn try(body, catch) -> Any {
+    with fn throw-exn(message) {
+        catch(message)
+    } {
+        body()
+    }
+}
+
+fn try-or(body, fallback) -> Any {
+    with fn throw-exn(message) {
+        fallback
+    } {
+        body()
+    }
+}
+";
+
+lazy_static! {
+    static ref PRELUDE_STATEMENTS: Vec<TopLevelStatement> = parse_prelude("exn", PRELUDE);
+}
+
+pub fn register(interpreter: &mut Interpreter) {
+    interpreter.add_native_function("panic", panic);
+    interpreter.add_native_function("div", div);
+    interpreter.add_native_function("get", get);
+    interpreter.declare_native_effects("div", &["exn"]);
+    interpreter.declare_native_effects("get", &["exn"]);
+    register_statements(PRELUDE_STATEMENTS.clone(), interpreter);
+}
+
+/// `fn panic(String) -> ()`
+/// This ends the program with the message and can't be caught.
+fn panic(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [message] => match message.as_string() {
+            Some(message) => panic!("{}", message),
+            None => panic!("panic expects a String but was given a value of type {}", message.get_type()),
+        },
+        _ => panic!("panic takes 1 argument but was given {}", arguments.len()),
+    }
+}
+
+/// `fn div(Int, Int) exn -> Int`
+/// `fn div(UInt, UInt) exn -> UInt`
+/// `fn div(Float, Float) -> Float`
+/// Integer division by zero throws instead of crashing.
+fn div(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [Value::Int(_), Value::Int(0)] | [Value::UInt(_), Value::UInt(0)] => interpreter.throw("Division by zero"),
+        [Value::Int(x), Value::Int(y)] => match x.checked_div(*y) {
+            Some(result) => Ok(Value::Int(result)),
+            None => interpreter.throw("Integer overflow in division"),
+        },
+        [Value::UInt(x), Value::UInt(y)] => Ok(Value::UInt(x / y)),
+        [Value::Float(x), Value::Float(y)] => Ok(Value::Float(x / y)),
+        [x, y] => panic!("div expects two numbers of the same type but was given {} and {}", x.get_type(), y.get_type()),
+        _ => panic!("div takes 2 arguments but was given {}", arguments.len()),
+    }
+}
+
+/// `fn get([a], UInt) exn -> a`
+/// `fn get(H-List, UInt) exn -> Any`
+/// Looking past the end of the list throws.
+fn get(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let (values, index) = match arguments.as_slice() {
+        [Value::List(values, _), Value::UInt(index)] => (values.as_slice(), *index),
+        [Value::HList(values), Value::UInt(index)] => (values.as_slice(), *index),
+        [list, index] => panic!("get expects a list and a UInt but was given {} and {}", list.get_type(), index.get_type()),
+        _ => panic!("get takes 2 arguments but was given {}", arguments.len()),
+    };
+    match values.get(index as usize) {
+        Some(value) => Ok(value.clone()),
+        None => interpreter.throw(&format!("Index {} is out of bounds for a list of length {}", index, values.len())),
+    }
+}
+
+
+#[cfg(test)]
+mod exn_tests {
+    use super::*;
+    use crate::interpreter::panic_message;
+    use crate::parser::file_parser::file_parser_helper;
+
+    use std::collections::HashMap;
+    use std::panic::{self, AssertUnwindSafe};
+
+    fn run(program: &str) -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper(program, &mut interpreter);
+        interpreter.start_program().unwrap();
+        interpreter
+    }
+
+    fn load(interpreter: &Interpreter, name: &str) -> Value {
+        match interpreter.get_value(name, &HashMap::new()).unwrap() {
+            Some(Value::Atomic(atomic)) => atomic.load(),
+            other => panic!("{} was {:?}", name, other),
+        }
+    }
+
+    #[test]
+    fn test_catching_division_by_zero() {
+        let interpreter = run("@Atomic\nresult := 0u;\n\
+                               fn main() -> () { store(result, with fn throw-exn(message) { 99u } { div(3u, 0u) }); }");
+
+        assert!(matches!(load(&interpreter, "result"), Value::UInt(99)), "Division by zero was not caught");
+    }
+
+    #[test]
+    fn test_try_helpers() {
+        let interpreter = run("@Atomic\ncaught := 0u;\n@Atomic\nfallen := 0u;\n\
+                               fn lookup() exn -> UInt { get([1u, 2u], 5u) }\n\
+                               fn recover(message: String) -> UInt { 7u }\n\
+                               fn main() exn -> () { store(caught, try(lookup, recover)); store(fallen, try-or(lookup, 8u)); }");
+
+        assert!(matches!(load(&interpreter, "caught"), Value::UInt(7)), "try did not call the catch function");
+        assert!(matches!(load(&interpreter, "fallen"), Value::UInt(8)), "try-or did not give the fallback");
+    }
+
+    #[test]
+    fn test_uncaught_exception_ends_program() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("fn main() exn -> () { throw(\"out of cheese\"); }", &mut interpreter);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| interpreter.start_program()));
+
+        match result {
+            Err(payload) => assert_eq!(panic_message(payload), "out of cheese"),
+            Ok(result) => panic!("Program should have panicked but gave {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use lazy_static::lazy_static;
+
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::builtins::parse_prelude;
+use crate::parser::file_parser::{TopLevelStatement, register_statements};
+use crate::types::{Value, TypeUtils};
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+
+/// Every value can be dropped, but only a `File` has anything to clean up unless a type adds its own `Drop` instance.
+/// Nothing drops a value when it goes out of scope, so `drop` only runs when it is called.
+const PRELUDE: &str = "
+class (Drop a) {
+    fn drop(a) -> ()
+}
+";
+
+lazy_static! {
+    static ref PRELUDE_STATEMENTS: Vec<TopLevelStatement> = parse_prelude("file", PRELUDE);
+}
+
+pub fn register(interpreter: &mut Interpreter) {
+    interpreter.add_native_function("open", open);
+    interpreter.add_native_function("read", read);
+    interpreter.add_native_function("readln", readln);
+    interpreter.add_native_function("write", write);
+    interpreter.add_native_function("writeln", writeln);
+    interpreter.add_native_function("drop", drop);
+    for name in ["open", "read", "readln", "write", "writeln"] {
+        interpreter.declare_native_effects(name, &["exn"]);
+    }
+    register_statements(PRELUDE_STATEMENTS.clone(), interpreter);
+}
+
+/// This is a file opened with `open`.
+/// Every copy of the value shares the same handle, so dropping any of them closes the file for all of them.
+/// The file is also closed once the last copy goes away.
+#[derive(Clone)]
+pub struct FileHandle {
+    path: Arc<str>,
+    state: Arc<Mutex<FileState>>,
+}
+
+enum FileState {
+    Reading(BufReader<fs::File>),
+    Writing(fs::File),
+    Closed,
+}
+
+impl FileHandle {
+    /// The mode is `'r'` to read, `'w'` to write over the file, or `'a'` to write at the end of it.
+    pub fn open(path: &str, mode: char) -> Result<FileHandle, String> {
+        let state = match mode {
+            'r' => fs::File::open(path).map(|file| FileState::Reading(BufReader::new(file))),
+            'w' => fs::File::create(path).map(FileState::Writing),
+            'a' => OpenOptions::new().append(true).create(true).open(path).map(FileState::Writing),
+            _ => return Err(format!("Unable to open {}: '{}' is not a file mode, use 'r', 'w', or 'a'", path, mode)),
+        };
+        match state {
+            Ok(state) => Ok(FileHandle { path: path.into(), state: Arc::new(Mutex::new(state)) }),
+            Err(error) => Err(format!("Unable to open {}: {}", path, error)),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, FileState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn close(&self) {
+        *self.state() = FileState::Closed;
+    }
+
+    fn reader<T>(&self, read: impl FnOnce(&mut BufReader<fs::File>) -> io::Result<T>) -> Result<T, String> {
+        match &mut *self.state() {
+            FileState::Reading(reader) => read(reader).map_err(|error| format!("Unable to read {}: {}", self.path, error)),
+            FileState::Writing(_) => Err(format!("Unable to read {}: it was opened for writing", self.path)),
+            FileState::Closed => Err(format!("Unable to read {}: it has been closed", self.path)),
+        }
+    }
+
+    /// This reads everything from where the file is up to its end.
+    pub fn read_to_end(&self) -> Result<String, String> {
+        self.reader(|reader| {
+            let mut text = String::new();
+            reader.read_to_string(&mut text)?;
+            Ok(text)
+        })
+    }
+
+    /// This reads the next line without its line ending, and `None` means the file has run out.
+    pub fn read_line(&self) -> Result<Option<String>, String> {
+        self.reader(|reader| {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            let length = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(length);
+            Ok(Some(line))
+        })
+    }
+
+    pub fn write(&self, text: &str) -> Result<(), String> {
+        match &mut *self.state() {
+            FileState::Writing(file) => file.write_all(text.as_bytes()).map_err(|error| format!("Unable to write to {}: {}", self.path, error)),
+            FileState::Reading(_) => Err(format!("Unable to write to {}: it was opened for reading", self.path)),
+            FileState::Closed => Err(format!("Unable to write to {}: it has been closed", self.path)),
+        }
+    }
+}
+
+impl fmt::Debug for FileHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "File({})", self.path)
+    }
+}
+
+fn file<'a>(function_name: &str, value: &'a Value) -> &'a FileHandle {
+    match value {
+        Value::File(file) => file,
+        other => panic!("{} expects a File but was given a value of type {}", function_name, other.get_type()),
+    }
+}
+
+fn text(function_name: &str, value: &Value) -> String {
+    value.as_string().unwrap_or_else(|| panic!("{} expects a String but was given a value of type {}", function_name, value.get_type()))
+}
+
+/// `fn open(String, Char) exn -> File`
+fn open(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [path, Value::Char(mode)] => match FileHandle::open(&text("open", path), *mode) {
+            Ok(file) => Ok(Value::File(file)),
+            Err(message) => interpreter.throw(&message),
+        },
+        [_, mode] => panic!("open expects a Char for the mode but was given a value of type {}", mode.get_type()),
+        _ => panic!("open takes 2 arguments but was given {}", arguments.len()),
+    }
+}
+
+/// `fn read(File) exn -> String`
+/// This reads the rest of the file.
+fn read(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [value] => match file("read", value).read_to_end() {
+            Ok(text) => Ok(Value::string(&text)),
+            Err(message) => interpreter.throw(&message),
+        },
+        _ => panic!("read takes 1 argument but was given {}", arguments.len()),
+    }
+}
+
+/// `fn readln(File) exn -> String`
+/// Reading past the last line throws.
+fn readln(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [value] => {
+            let file = file("readln", value);
+            match file.read_line() {
+                Ok(Some(line)) => Ok(Value::string(&line)),
+                Ok(None) => interpreter.throw(&format!("Unable to read {}: there are no more lines", file.path())),
+                Err(message) => interpreter.throw(&message),
+            }
+        },
+        _ => panic!("readln takes 1 argument but was given {}", arguments.len()),
+    }
+}
+
+fn write_text(interpreter: &mut Interpreter, function_name: &str, arguments: &[Value], ending: &str) -> Result<Value, RuntimeError> {
+    match arguments {
+        [value, line] => match file(function_name, value).write(&(text(function_name, line) + ending)) {
+            Ok(()) => Ok(Value::Tuple(Vec::new())),
+            Err(message) => interpreter.throw(&message),
+        },
+        _ => panic!("{} takes 2 arguments but was given {}", function_name, arguments.len()),
+    }
+}
+
+/// `fn write(File, String) exn -> ()`
+fn write(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    write_text(interpreter, "write", &arguments, "")
+}
+
+/// `fn writeln(File, String) exn -> ()`
+fn writeln(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    write_text(interpreter, "writeln", &arguments, "\n")
+}
+
+/// `fn drop(a) -> ()`
+/// This is the fallback for types without a `Drop` instance.
+/// Dropping a `File` closes it and dropping anything else does nothing.
+fn drop(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [Value::File(file)] => file.close(),
+        [_] => {},
+        _ => panic!("drop takes 1 argument but was given {}", arguments.len()),
+    }
+    Ok(Value::Tuple(Vec::new()))
+}
+
+
+#[cfg(test)]
+mod file_tests {
+    use super::*;
+    use crate::parser::file_parser::file_parser_helper;
+    use crate::types::Type;
+
+    use std::collections::HashMap;
+    use std::env;
+    use std::process;
+
+    fn temporary_path(name: &str) -> String {
+        env::temp_dir().join(format!("file_tests_{}_{}", process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    fn interpreter(program: &str, path: &str) -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper(&format!("path = \"{}\";\n{}", path, program), &mut interpreter);
+        interpreter
+    }
+
+    fn text_of(value: Value) -> String {
+        value.as_string().unwrap_or_else(|| panic!("Expected a String but got {:?}", value))
+    }
+
+    #[test]
+    fn test_write_then_read() {
+        let path = temporary_path("write_then_read");
+        let mut interpreter = interpreter("fn save() exn -> () { file = open(path, 'w'); writeln(file, \"first\"); write(file, \"second\"); drop(file); }\n\
+                                           fn add() exn -> () { file = open(path, 'a'); writeln(file, \"\"); writeln(file, \"third\"); }\n\
+                                           fn first_line() exn -> String { readln(open(path, 'r')) }\n\
+                                           fn everything() exn -> String { read(open(path, 'r')) }", &path);
+
+        interpreter.call_function("save", vec![], HashMap::new()).unwrap();
+        interpreter.call_function("add", vec![], HashMap::new()).unwrap();
+        let first_line = text_of(interpreter.call_function("first_line", vec![], HashMap::new()).unwrap());
+        let everything = text_of(interpreter.call_function("everything", vec![], HashMap::new()).unwrap());
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(first_line, "first");
+        assert_eq!(everything, "first\nsecond\nthird\n");
+    }
+
+    #[test]
+    fn test_drop_instances_are_called() {
+        let path = temporary_path("drop_instances");
+        let mut interpreter = interpreter("@Atomic\ndropped := 0u;\n\
+                                           fn release(n: UInt) -> () { store(dropped, n); }\n\
+                                           fn main() exn -> () { drop(7u); file = open(path, 'w'); drop(file); write(file, \"late\"); }", &path);
+        let release = interpreter.get_value("release", &HashMap::new()).unwrap().unwrap();
+        let drop_class = Type::TypeList{name: Box::new(Type::Single("Drop".to_string())), parameters: vec![Type::Single("a".to_string())]};
+        interpreter.add_typeclass_instance(drop_class, Type::Single("UInt".to_string()), vec![("drop".to_string(), release)]);
+
+        let result = interpreter.call_function("main", vec![], HashMap::new());
+        let dropped = interpreter.get_value("dropped", &HashMap::new()).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert!(matches!(dropped, Some(Value::Atomic(ref atomic)) if matches!(atomic.load(), Value::UInt(7))), "The Drop instance was not called: {:?}", dropped);
+        assert!(matches!(result, Err(RuntimeError::UnhandledEffect(..))), "The built-in drop did not close the file: {:?}", result);
+    }
+
+    #[test]
+    fn test_file_errors_are_thrown() {
+        let path = temporary_path("missing");
+        let mut interpreter = interpreter("fn missing() -> Any { with fn throw-exn(message) { message } { open(path, 'r') } }\n\
+                                           fn closed() -> Any { with fn throw-exn(message) { message } { file = open(path, 'w'); drop(file); write(file, \"late\") } }", &path);
+
+        let missing = text_of(interpreter.call_function("missing", vec![], HashMap::new()).unwrap());
+        let closed = text_of(interpreter.call_function("closed", vec![], HashMap::new()).unwrap());
+        let _ = fs::remove_file(&path);
+
+        assert!(missing.starts_with(&format!("Unable to open {}", path)), "Opening a missing file did not throw: {}", missing);
+        assert_eq!(closed, format!("Unable to write to {}: it has been closed", path));
+    }
+}
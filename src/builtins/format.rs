@@ -0,0 +1,288 @@
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::builtins::console::show_value;
+use crate::types::{Value, TypeUtils};
+
+
+pub fn register(interpreter: &mut Interpreter) {
+    interpreter.add_variadic_function("format", 1, format);
+    interpreter.declare_native_effects("format", &["exn"]);
+}
+
+/// This is why a template couldn't be filled in.
+#[derive(Debug)]
+enum FormatError {
+    Invalid(String),//The template doesn't fit its arguments, which is thrown through exn
+    Runtime(RuntimeError),//A Show instance failed
+}
+
+impl From<RuntimeError> for FormatError {
+    fn from(error: RuntimeError) -> FormatError {
+        FormatError::Runtime(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+/// This is what comes after the `:` in a placeholder, which is `[[fill]align][0][width][.precision][x|X|b|o]` like in Rust.
+#[derive(Debug, Clone, PartialEq)]
+struct Spec {
+    fill: char,
+    align: Option<Align>,
+    zero: bool,
+    width: Option<usize>,
+    precision: Option<usize>,
+    radix: Option<char>,
+}
+
+fn align_of(c: char) -> Option<Align> {
+    match c {
+        '<' => Some(Align::Left),
+        '>' => Some(Align::Right),
+        '^' => Some(Align::Center),
+        _ => None,
+    }
+}
+
+fn parse_spec(spec: &str) -> Result<Spec, String> {
+    let mut chars: Vec<char> = spec.chars().collect();
+    let mut parsed = Spec { fill: ' ', align: None, zero: false, width: None, precision: None, radix: None };
+
+    if let Some(align) = chars.get(1).and_then(|c| align_of(*c)) {
+        parsed.fill = chars[0];
+        parsed.align = Some(align);
+        chars.drain(..2);
+    }
+    else if let Some(align) = chars.first().and_then(|c| align_of(*c)) {
+        parsed.align = Some(align);
+        chars.remove(0);
+    }
+
+    if let Some(radix) = chars.last().copied().filter(|c| matches!(c, 'x' | 'X' | 'b' | 'o')) {
+        parsed.radix = Some(radix);
+        chars.pop();
+    }
+
+    let rest: String = chars.into_iter().collect();
+    let (width, precision) = match rest.split_once('.') {
+        Some((width, precision)) => (width, Some(precision)),
+        None => (rest.as_str(), None),
+    };
+    let width = match width.strip_prefix('0') {
+        Some(width) if !width.is_empty() => {
+            parsed.zero = true;
+            width
+        },
+        _ => width,
+    };
+    if !width.is_empty() {
+        parsed.width = Some(width.parse().map_err(|_| format!("{:?} is not a width", width))?);
+    }
+    if let Some(precision) = precision {
+        parsed.precision = Some(precision.parse().map_err(|_| format!("{:?} is not a precision", precision))?);
+    }
+    Ok(parsed)
+}
+
+fn is_number(value: &Value) -> bool {
+    matches!(value, Value::Int(_) | Value::UInt(_) | Value::Byte(_) | Value::Float(_))
+}
+
+fn radix_text(value: &Value, radix: char) -> Option<String> {
+    let text = match (value, radix) {
+        (Value::Int(i), 'x') => format!("{:x}", i),
+        (Value::Int(i), 'X') => format!("{:X}", i),
+        (Value::Int(i), 'b') => format!("{:b}", i),
+        (Value::Int(i), 'o') => format!("{:o}", i),
+        (Value::UInt(i), 'x') => format!("{:x}", i),
+        (Value::UInt(i), 'X') => format!("{:X}", i),
+        (Value::UInt(i), 'b') => format!("{:b}", i),
+        (Value::UInt(i), 'o') => format!("{:o}", i),
+        (Value::Byte(i), 'x') => format!("{:x}", i),
+        (Value::Byte(i), 'X') => format!("{:X}", i),
+        (Value::Byte(i), 'b') => format!("{:b}", i),
+        (Value::Byte(i), 'o') => format!("{:o}", i),
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// This renders one argument, which goes through `Show` unless the spec asks for something only numbers have.
+fn render(interpreter: &mut Interpreter, value: &Value, spec: &Spec) -> Result<String, FormatError> {
+    let text = match (spec.radix, spec.precision, value) {
+        (Some(radix), _, value) => radix_text(value, radix).ok_or_else(|| FormatError::Invalid(format!("only integers can be shown in another base but was given a value of type {}", value.get_type())))?,
+        (None, Some(precision), Value::Float(f)) => format!("{:.*}", precision, f),
+        (None, Some(precision), value) if !is_number(value) => show_value(interpreter, value)?.chars().take(precision).collect(),
+        (None, _, value) => show_value(interpreter, value)?,
+    };
+
+    let length = text.chars().count();
+    let width = match spec.width {
+        Some(width) if width > length => width,
+        _ => return Ok(text),
+    };
+    let padding = width - length;
+
+    // Zeros go after the sign so that -5 padded to 4 is -005
+    if spec.zero && is_number(value) {
+        return Ok(match text.strip_prefix('-') {
+            Some(digits) => format!("-{}{}", "0".repeat(padding), digits),
+            None => format!("{}{}", "0".repeat(padding), text),
+        });
+    }
+
+    let fill = |count: usize| spec.fill.to_string().repeat(count);
+    let default_align = if is_number(value) { Align::Right } else { Align::Left };
+    Ok(match spec.align.unwrap_or(default_align) {
+        Align::Left => format!("{}{}", text, fill(padding)),
+        Align::Right => format!("{}{}", fill(padding), text),
+        Align::Center => format!("{}{}{}", fill(padding / 2), text, fill(padding - padding / 2)),
+    })
+}
+
+/// This is the value a placeholder names, which is either the position of an argument or a variable where `format` was called.
+fn named_argument(interpreter: &Interpreter, name: &str, arguments: &[Value]) -> Result<Value, FormatError> {
+    if let Ok(index) = name.parse::<usize>() {
+        return arguments.get(index).cloned().ok_or_else(|| FormatError::Invalid(format!("there is no argument {} since there are only {}", index, arguments.len())));
+    }
+    if !name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_') {
+        return Err(FormatError::Invalid(format!("{{{}}} is not a position or a name", name)));
+    }
+    match interpreter.caller_value(name)? {
+        Some(value) => Ok(value.get_immutable()),
+        None => Err(FormatError::Invalid(format!("there is no variable named {}", name))),
+    }
+}
+
+/// This fills in the placeholders of a template with the arguments.
+/// `{}` takes the next argument, `{0}` takes an argument by its position, `{name}` takes the variable `name`, and `{{` and `}}` are braces.
+fn format_text(interpreter: &mut Interpreter, template: &str, arguments: &[Value]) -> Result<String, FormatError> {
+    let invalid = |message: &str| Err(FormatError::Invalid(message.to_string()));
+
+    let mut output = String::new();
+    let mut next_argument = 0;
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                output.push('{');
+            },
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                output.push('}');
+            },
+            '}' => return invalid("a } has to be written as }}"),
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return invalid("a placeholder is missing its }"),
+                    }
+                }
+                let (name, spec) = placeholder.split_once(':').unwrap_or((&placeholder, ""));
+                let spec = parse_spec(spec).map_err(FormatError::Invalid)?;
+                let argument = if name.is_empty() {
+                    next_argument += 1;
+                    named_argument(interpreter, &(next_argument - 1).to_string(), arguments)?
+                }
+                else {
+                    named_argument(interpreter, name, arguments)?
+                };
+                output += &render(interpreter, &argument, &spec)?;
+            },
+            c => output.push(c),
+        }
+    }
+    Ok(output)
+}
+
+/// `fn format(String, ...) exn -> String`
+/// A template that doesn't fit its arguments throws.
+fn format(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [template, Value::HList(values)] => match template.as_string() {
+            Some(template) => match format_text(interpreter, &template, values) {
+                Ok(text) => Ok(Value::string(&text)),
+                Err(FormatError::Invalid(message)) => interpreter.throw(&format!("Invalid format string {:?}: {}", template, message)),
+                Err(FormatError::Runtime(error)) => Err(error),
+            },
+            None => panic!("format expects a String template but was given a value of type {}", template.get_type()),
+        },
+        _ => panic!("format takes a template and the values to put into it but was given {} arguments", arguments.len()),
+    }
+}
+
+
+#[cfg(test)]
+mod format_tests {
+    use super::*;
+    use crate::parser::file_parser::file_parser_helper;
+    use crate::types::Type;
+
+    use std::collections::HashMap;
+
+    fn format_values(template: &str, values: Vec<Value>) -> String {
+        let mut interpreter = Interpreter::new();
+        format_text(&mut interpreter, template, &values).unwrap()
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("fn sum() -> String { format(\"{} + {} = {2}, {{{0}}}\", 1u, 2u, 3u) }", &mut interpreter);
+
+        let result = interpreter.call_function("sum", vec![], HashMap::new()).unwrap();
+
+        assert_eq!(result.as_string().as_deref(), Some("1 + 2 = 3, {1}"));
+    }
+
+    #[test]
+    fn test_named_placeholders() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("fn greet(name: String) -> String { count := 3u; format(\"{name} has {count:>3} and {0}\", 1.5) }", &mut interpreter);
+
+        let result = interpreter.call_function("greet", vec![Value::string("ada")], HashMap::new()).unwrap();
+
+        assert_eq!(result.as_string().as_deref(), Some("ada has   3 and 1.5"));
+    }
+
+    #[test]
+    fn test_invalid_templates_throw() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("fn attempt(template: String) -> String { with fn throw-exn(message) { message } { format(template, 1u, \"text\") } }", &mut interpreter);
+
+        for (template, reason) in [("{", "missing its }"), ("{2}", "there is no argument 2"), ("{missing}", "no variable named missing"), ("{1:x}", "only integers")] {
+            let result = interpreter.call_function("attempt", vec![Value::string(template)], HashMap::new()).unwrap();
+            let message = result.as_string().unwrap();
+            assert!(message.starts_with("Invalid format string") && message.contains(reason), "{:?} threw {:?}", template, message);
+        }
+        let result = interpreter.call_function("attempt", vec![Value::string("{:s}")], HashMap::new()).unwrap();
+        assert!(result.as_string().unwrap().starts_with("Invalid format string"), "A bad spec was not thrown");
+    }
+
+    #[test]
+    fn test_width_precision_and_alignment() {
+        assert_eq!(format_values("[{:5}|{:<5}|{:^7}|{:*>6}]", vec![Value::UInt(42), Value::UInt(42), Value::string("mid"), Value::string("ab")]), "[   42|42   |  mid  |****ab]");
+        assert_eq!(format_values("{:.2} {:8.3} {:.3}", vec![Value::Float(1.23456), Value::Float(2.0), Value::string("truncated")]), "1.23    2.000 tru");
+        assert_eq!(format_values("{:05}", vec![Value::Int(-42)]), "-0042");
+    }
+
+    #[test]
+    fn test_hex_and_binary() {
+        assert_eq!(format_values("{:x} {:X} {:08b} {:o}", vec![Value::UInt(255), Value::Int(255), Value::Byte(5), Value::UInt(8)]), "ff FF 00000101 10");
+    }
+
+    #[test]
+    fn test_arguments_are_shown() {
+        let list = Value::List(vec![Value::string("a"), Value::string("b")].into(), Type::Single("String".to_string()));
+
+        assert_eq!(format_values("{} {} {}", vec![Value::string("plain"), list, Value::just(Value::Char('c'))]), "plain [\"a\", \"b\"] Just('c')");
+    }
+}
@@ -0,0 +1,39 @@
+pub mod actor;
+pub mod atomic;
+pub mod channel;
+pub mod concurrency;
+pub mod console;
+pub mod exn;
+pub mod file;
+pub mod format;
+pub mod monad;
+pub mod parallel;
+
+use chumsky::Parser;
+use chumsky::primitive::end;
+
+use crate::interpreter::Interpreter;
+use crate::parser::file_parser::{TopLevelStatement, module_parser};
+use crate::parser::lexer::lexer;
+
+
+/// This adds every built-in function to the interpreter.
+pub fn register_builtins(interpreter: &mut Interpreter) {
+    atomic::register(interpreter);
+    channel::register(interpreter);
+    concurrency::register(interpreter);
+    parallel::register(interpreter);
+    actor::register(interpreter);
+    exn::register(interpreter);
+    monad::register(interpreter);
+    console::register(interpreter);
+    file::register(interpreter);
+    format::register(interpreter);
+}
+
+/// This parses a part of the standard library that is written in the language itself.
+/// Every interpreter registers these, so modules keep the result in a `lazy_static` to only parse it once.
+pub(crate) fn parse_prelude(name: &str, source: &str) -> Vec<TopLevelStatement> {
+    let tokens = lexer(source).unwrap_or_else(|error| panic!("Something went wrong lexing the {} prelude: {:?}", name, error));
+    module_parser().then_ignore(end()).parse(tokens).unwrap_or_else(|error| panic!("Something went wrong parsing the {} prelude: {:?}", name, error))
+}
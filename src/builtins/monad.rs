@@ -0,0 +1,480 @@
+use lazy_static::lazy_static;
+
+use crate::interpreter::{Interpreter, RuntimeError, NativeFunction, await_value};
+use crate::builtins::parse_prelude;
+use crate::parser::expression_parser::Expression;
+use crate::parser::file_parser::{TopLevelStatement, register_statements};
+use crate::types::{Type, Value, TypeUtils, IoAction};
+use crate::sync::Promise;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+
+/// The instances for IO, Maybe, and Promise are registered like any other, and programs can add instances of their own.
+/// `pure` doesn't know which monad it is in, so it makes an IO action and `>>=` and `>>` turn that into the monad they are in with its `pure`.
+const PRELUDE: &str = "
+class (Monad m) {
+    fn (>>=)((m a), fn(a) -> (m b)) -> (m b)
+    fn (>>)((m a), (m b)) -> (m b)
+    fn pure(a) -> (m a)
+}
+";
+
+lazy_static! {
+    static ref PRELUDE_STATEMENTS: Vec<TopLevelStatement> = parse_prelude("monad", PRELUDE);
+}
+
+pub fn register(interpreter: &mut Interpreter) {
+    // These pick the instance themselves so that what `pure` made can be turned into the monad being bound
+    interpreter.add_dispatching_function(">>=", bind);
+    interpreter.add_dispatching_function(">>", then);
+    interpreter.add_dispatching_function("pure", pure);
+    interpreter.add_promise_function("Monad lift", lift_result);
+    register_statements(PRELUDE_STATEMENTS.clone(), interpreter);
+    add_instance(interpreter, "IO", [io_bind, io_then, pure]);
+    add_instance(interpreter, "Maybe", [maybe_bind, maybe_then, maybe_pure]);
+    add_instance(interpreter, "Promise", [promise_bind, promise_then, promise_pure]);
+}
+
+fn monad_class() -> Type {
+    Type::TypeList{name: Box::new(Type::Single("Monad".to_string())), parameters: vec![Type::Single("m".to_string())]}
+}
+
+/// This registers `>>=`, `>>`, and `pure` of a built-in monad as an instance of `Monad`.
+/// Each instance function calls a built-in under a name that programs can't write, like `IO >>=`.
+fn add_instance(interpreter: &mut Interpreter, monad: &str, functions: [NativeFunction; 3]) {
+    let instance = Type::TypeList{name: Box::new(Type::Single(monad.to_string())), parameters: vec![Type::Single("a".to_string())]};
+    let signatures: [(&str, &[&str]); 3] = [(">>=", &["monad", "function"]), (">>", &["monad", "next"]), ("pure", &["value"])];
+    let mut instance_functions = Vec::new();
+    for ((name, parameters), function) in signatures.into_iter().zip(functions) {
+        let native = format!("{} {}", monad, name);
+        interpreter.add_promise_function(&native, function);
+        // The monad is given its type so that a promise is passed on as it is instead of being waited for
+        let monad_type = Type::TypeList{name: Box::new(Type::Single(monad.to_string())), parameters: vec![Type::Single("Any".to_string())]};
+        let parameters: Vec<(String, Option<Type>)> = parameters.iter()
+            .map(|parameter| (parameter.to_string(), (*parameter == "monad").then(|| monad_type.clone())))
+            .collect();
+        let arguments = parameters.iter().map(|(parameter, _)| Expression::Variable(parameter.clone())).collect();
+        let body = Expression::Call(native, arguments);
+        instance_functions.push((name.to_string(), Value::Function(Vec::new(), parameters, Vec::new(), Type::Single("Any".to_string()), HashMap::new(), body)));
+    }
+    interpreter.add_typeclass_instance(monad_class(), instance, instance_functions);
+}
+
+/// This finds the instance of a `Monad` function for the monad a value is in.
+fn monad_function(interpreter: &Interpreter, name: &str, monad: &Value) -> Value {
+    interpreter.typeclass_function(name, &monad.get_type()).unwrap_or_else(|| panic!("There is no Monad instance for {}", monad.get_type()))
+}
+
+/// A function body waits for the promise it gives back, so what the Promise instance makes has to be put back into a promise.
+fn keep_promise(monad: &Value, value: Value) -> Value {
+    match (monad, value) {
+        (Value::Promise(..), value @ Value::Promise(..)) => value,
+        (Value::Promise(..), value) => fulfilled(value),
+        (_, value) => value,
+    }
+}
+
+/// `pure` always makes IO, so a function that gives back another monad can give back IO that was built out of `pure` and binds.
+/// This builds that action again in the monad of `like`, with the `pure` of its instance.
+fn lift(interpreter: &mut Interpreter, like: &Value, value: Value) -> Result<Value, RuntimeError> {
+    let (action, the_type) = match value {
+        Value::IO(action, the_type) => (action, the_type),
+        value => return Ok(value),
+    };
+    match action.as_ref() {
+        IoAction::Pure(value) => {
+            let pure = monad_function(interpreter, "pure", like);
+            let value = interpreter.call_function_value("pure", &pure, vec![value.clone()])?;
+            Ok(keep_promise(like, value))
+        },
+        IoAction::Located(_, inner) => lift(interpreter, like, Value::IO(inner.clone(), the_type)),
+        IoAction::Bind(first, function) => {
+            let first = lift(interpreter, like, Value::IO(first.clone(), Type::Single("Any".to_string())))?;
+            bind(interpreter, vec![first, function.clone()])
+        },
+        IoAction::Then(first, second) => {
+            let first = lift(interpreter, like, Value::IO(first.clone(), Type::Single("Any".to_string())))?;
+            let second = lift(interpreter, like, Value::IO(second.clone(), the_type))?;
+            then(interpreter, vec![first, second])
+        },
+        IoAction::Native(..) => panic!("Tried to use an IO action of type {} as a {}", Value::IO(action.clone(), the_type).get_type(), like.get_type()),
+    }
+}
+
+/// `fn Monad lift(m a, b) -> m b`
+/// This is what the functions given to instances call to turn what they make into the monad.
+fn lift_result(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [like, value] => lift(interpreter, like, await_value(value.clone())?),
+        _ => panic!("Monad lift takes 2 arguments but was given {}", arguments.len()),
+    }
+}
+
+/// This wraps the function given to `>>=` so that the instance gets back a value of its own monad even when the function used `pure`.
+fn lifted(function: &Value, like: &Value) -> Value {
+    let captured = HashMap::from([("function".to_string(), function.clone()), ("monad".to_string(), like.clone())]);
+    let result = Expression::Call("function".to_string(), vec![Expression::Variable("value".to_string())]);
+    let body = Expression::Call("Monad lift".to_string(), vec![Expression::Variable("monad".to_string()), result]);
+    Value::Function(Vec::new(), vec![("value".to_string(), None)], Vec::new(), Type::Single("Any".to_string()), captured, body)
+}
+
+/// `fn (>>=)(Monad m, fn(a) -> m b) -> m b`
+/// IO is where `pure` puts its value already, so only the other monads get the function wrapped.
+fn bind(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [monad, function @ Value::Function(..)] => {
+            let instance = monad_function(interpreter, ">>=", monad);
+            let function = match monad {
+                Value::IO(..) => function.clone(),
+                _ => lifted(function, monad),
+            };
+            let result = interpreter.call_function_value(">>=", &instance, vec![monad.clone(), function])?;
+            Ok(keep_promise(monad, result))
+        },
+        [_, function] => panic!(">>= expects a function as its second argument but was given a value of type {}", function.get_type()),
+        _ => panic!(">>= takes 2 arguments but was given {}", arguments.len()),
+    }
+}
+
+/// `fn (>>)(Monad m, m b) -> m b`
+fn then(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [monad, next] => {
+            let instance = monad_function(interpreter, ">>", monad);
+            let next = match monad {
+                Value::IO(..) => next.clone(),
+                _ => lift(interpreter, monad, await_value(next.clone())?)?,
+            };
+            let result = interpreter.call_function_value(">>", &instance, vec![monad.clone(), next])?;
+            Ok(keep_promise(monad, result))
+        },
+        _ => panic!(">> takes 2 arguments but was given {}", arguments.len()),
+    }
+}
+
+/// `fn pure(a) -> IO a`
+fn pure(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [value] => Ok(Value::pure_io(value.clone())),
+        _ => panic!("pure takes 1 argument but was given {}", arguments.len()),
+    }
+}
+
+/// `fn (>>=)(IO a, fn(a) -> IO b) -> IO b`
+fn io_bind(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [Value::IO(action, _), function] => Ok(Value::IO(Arc::new(IoAction::Bind(action.clone(), function.clone())), Type::Single("Any".to_string()))),
+        _ => panic!(">>= takes 2 arguments but was given {}", arguments.len()),
+    }
+}
+
+/// `fn (>>)(IO a, IO b) -> IO b`
+fn io_then(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [Value::IO(first, _), Value::IO(second, the_type)] => Ok(Value::IO(Arc::new(IoAction::Then(first.clone(), second.clone())), the_type.clone())),
+        [_, other] => panic!(">> expects IO after an IO action but was given a value of type {}", other.get_type()),
+        _ => panic!(">> takes 2 arguments but was given {}", arguments.len()),
+    }
+}
+
+/// This gives back `Some(Some(value))` for `Just(value)`, `Some(None)` for `Nothing`, and `None` if the value isn't a `Maybe`.
+fn maybe_value(value: &Value) -> Option<Option<&Value>> {
+    match value {
+        Value::Algebraic{name, values, ..} if name == "Maybe" => Some(values.get(&Type::Single("Just".to_string()))),
+        _ => None,
+    }
+}
+
+/// `fn (>>=)(Maybe a, fn(a) -> Maybe b) -> Maybe b`
+fn maybe_bind(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [monad, function] => match maybe_value(monad) {
+            Some(Some(value)) => interpreter.call_function_value(">>=", function, vec![value.clone()]),
+            _ => Ok(monad.clone()),
+        },
+        _ => panic!(">>= takes 2 arguments but was given {}", arguments.len()),
+    }
+}
+
+/// `fn (>>)(Maybe a, Maybe b) -> Maybe b`
+fn maybe_then(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [monad, next] => match maybe_value(monad) {
+            Some(Some(_)) => Ok(next.clone()),
+            _ => Ok(monad.clone()),
+        },
+        _ => panic!(">> takes 2 arguments but was given {}", arguments.len()),
+    }
+}
+
+/// `fn pure(a) -> Maybe a`
+fn maybe_pure(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [value] => Ok(Value::just(value.clone())),
+        _ => panic!("pure takes 1 argument but was given {}", arguments.len()),
+    }
+}
+
+fn fulfilled(value: Value) -> Value {
+    let promise = Promise::new();
+    let the_type = value.get_type();
+    promise.fulfil(Ok(value));
+    Value::create_promise(promise, the_type)
+}
+
+/// `fn (>>=)(Promise a, fn(a) -> Promise b) -> Promise b`
+/// This waits for the promise before calling the function.
+fn promise_bind(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [promise, function] => {
+            let value = await_value(promise.clone())?;
+            interpreter.call_function_value(">>=", function, vec![value])
+        },
+        _ => panic!(">>= takes 2 arguments but was given {}", arguments.len()),
+    }
+}
+
+/// `fn (>>)(Promise a, Promise b) -> Promise b`
+fn promise_then(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [first, next] => {
+            await_value(first.clone())?;
+            Ok(next.clone())
+        },
+        _ => panic!(">> takes 2 arguments but was given {}", arguments.len()),
+    }
+}
+
+/// `fn pure(a) -> Promise a`
+fn promise_pure(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match arguments.as_slice() {
+        [value] => Ok(fulfilled(value.clone())),
+        _ => panic!("pure takes 1 argument but was given {}", arguments.len()),
+    }
+}
+
+
+#[cfg(test)]
+mod monad_tests {
+    use super::*;
+    use crate::interpreter::panic_message;
+    use crate::types::AlgebraicType;
+    use crate::parser::file_parser::file_parser_helper;
+    use crate::parser::expression_parser::expression_parser;
+    use crate::parser::lexer::lexer;
+    use chumsky::Parser;
+
+    use std::panic::{self, AssertUnwindSafe};
+
+    fn parse_expression(input: &str) -> Expression {
+        let tokens = lexer(input).expect("Something went wrong lexing the expression");
+        expression_parser().parse(tokens).expect("Something went wrong parsing the expression")
+    }
+
+    fn add_one(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        match arguments.as_slice() {
+            [Value::UInt(x)] => Ok(Value::UInt(x + 1)),
+            _ => panic!("add_one expects a UInt"),
+        }
+    }
+
+    /// This makes an IO action that adds to the global `runs` when it runs.
+    fn deferred(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        Ok(Value::native_io(add_to_runs, arguments, Type::Unit))
+    }
+
+    fn add_to_runs(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let runs = interpreter.get_value("runs", &HashMap::new())?.expect("runs was not declared");
+        interpreter.call_function("fetch_add", vec![runs, arguments[0].clone()], HashMap::new())?;
+        Ok(Value::Tuple(Vec::new()))
+    }
+
+    /// This makes a value of `Counted`, a monad the tests give an instance in the program.
+    fn counted(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        match arguments.as_slice() {
+            [value, count @ Value::UInt(_)] => Ok(Value::Algebraic{agb_type: AlgebraicType::Product, types: Vec::new(), name: "Counted".to_string(),
+                values: HashMap::from([(Type::Single("value".to_string()), value.clone()), (Type::Single("count".to_string()), count.clone())])}),
+            _ => panic!("counted expects a value and a UInt"),
+        }
+    }
+
+    fn counted_field(value: &Value, field: &str) -> Value {
+        match value {
+            Value::Algebraic{name, values, ..} if name == "Counted" => values[&Type::Single(field.to_string())].clone(),
+            other => panic!("Expected a Counted but was given {:?}", other),
+        }
+    }
+
+    fn value_of(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        Ok(counted_field(&arguments[0], "value"))
+    }
+
+    fn count_of(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        Ok(counted_field(&arguments[0], "count"))
+    }
+
+    fn plus(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        match arguments.as_slice() {
+            [Value::UInt(a), Value::UInt(b)] => Ok(Value::UInt(a + b)),
+            _ => panic!("plus expects two UInts"),
+        }
+    }
+
+    fn runs(interpreter: &Interpreter) -> Value {
+        match interpreter.get_value("runs", &HashMap::new()).unwrap() {
+            Some(Value::Atomic(runs)) => runs.load(),
+            other => panic!("runs was {:?}", other),
+        }
+    }
+
+    fn interpreter(program: &str) -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        interpreter.add_native_function("add_one", add_one);
+        interpreter.add_native_function("deferred", deferred);
+        file_parser_helper(program, &mut interpreter);
+        interpreter
+    }
+
+    #[test]
+    fn test_main_returning_io_is_run() {
+        let mut interpreter = interpreter("@Atomic\nruns := 0u;\n\
+                                           fn bump(n: UInt) -> (IO ()) { deferred(n) }\n\
+                                           fn main() -> (IO ()) { >>=(pure(1u), bump) }");
+
+        interpreter.start_program().unwrap();
+
+        assert!(matches!(runs(&interpreter), Value::UInt(1)), "The IO action main gave back was not run");
+    }
+
+    #[test]
+    fn test_io_statements_are_chained() {
+        let mut interpreter = interpreter("@Atomic\nruns := 0u;\n\
+                                           fn twice() -> (IO ()) { deferred(1u); deferred(2u) }");
+
+        interpreter.evaluate_expression(&parse_expression("{ action = >>(deferred(1u), deferred(2u)); }"), &mut HashMap::new()).unwrap();
+        let twice = interpreter.call_function("twice", vec![], HashMap::new()).unwrap();
+        let statements = interpreter.evaluate_expression(&parse_expression("{ deferred(4u); 7u }"), &mut HashMap::new()).unwrap();
+        assert!(matches!(runs(&interpreter), Value::UInt(0)), "An IO action ran before anything ran it");
+        assert!(matches!(twice, Value::IO(..)), "Calling a function that gives back IO did not give back IO: {:?}", twice);
+
+        let Value::IO(action, _) = statements else { panic!("The block did not give back its IO statements: {:?}", statements) };
+        let result = interpreter.run_io(&action).unwrap();
+        assert!(matches!(result, Value::UInt(7)), "The chained action did not make the block's value: {:?}", result);
+        assert!(matches!(runs(&interpreter), Value::UInt(4)), "Running the block's action did not run its statements");
+    }
+
+    #[test]
+    fn test_io_statements_run_when_function_does_not_give_back_io() {
+        let mut interpreter = interpreter("@Atomic\nruns := 0u;\n\
+                                           fn imperative() -> UInt { deferred(1u); deferred(2u); 5u }");
+
+        let result = interpreter.call_function("imperative", vec![], HashMap::new()).unwrap();
+
+        assert!(matches!(result, Value::UInt(5)), "The function did not give back the value of its block: {:?}", result);
+        assert!(matches!(runs(&interpreter), Value::UInt(3)), "The statements of a function that doesn't give back IO were not run");
+    }
+
+    #[test]
+    fn test_maybe_monad() {
+        let mut interpreter = interpreter("fn step(n: UInt) -> Any { pure(add_one(n)) }");
+        let step = interpreter.get_value("step", &HashMap::new()).unwrap().unwrap();
+
+        let just = interpreter.call_function(">>=", vec![Value::just(Value::UInt(1)), step.clone()], HashMap::new()).unwrap();
+        let nothing = interpreter.call_function(">>=", vec![Value::nothing(Type::Single("UInt".to_string())), step], HashMap::new()).unwrap();
+
+        assert!(matches!(maybe_value(&just), Some(Some(Value::UInt(2)))), "Just was not passed on: {:?}", just);
+        assert!(matches!(maybe_value(&nothing), Some(None)), "Nothing did not stop the chain: {:?}", nothing);
+    }
+
+    #[test]
+    fn test_do_block_with_maybe() {
+        let mut interpreter = interpreter("fn step(n: UInt) -> Any { pure(add_one(n)) }\n\
+                                           fn chain(m) -> Any { do {\n    x <- m\n    y <- step(x)\n    z = add_one(y)\n    pure(z)\n} }");
+
+        let just = interpreter.call_function("chain", vec![Value::just(Value::UInt(1))], HashMap::new()).unwrap();
+        let nothing = interpreter.call_function("chain", vec![Value::nothing(Type::Single("UInt".to_string()))], HashMap::new()).unwrap();
+
+        assert!(matches!(maybe_value(&just), Some(Some(Value::UInt(3)))), "The do block did not bind through Just: {:?}", just);
+        assert!(matches!(maybe_value(&nothing), Some(None)), "Nothing did not stop the do block: {:?}", nothing);
+    }
+
+    #[test]
+    fn test_do_block_with_io() {
+        let mut interpreter = interpreter("@Atomic\nruns := 0u;\n\
+                                           fn main() -> (IO ()) { do {\n    deferred(1u)\n    n <- pure(2u)\n    deferred(n)\n} }");
+
+        interpreter.start_program().unwrap();
+
+        assert!(matches!(runs(&interpreter), Value::UInt(3)), "Not every statement of the do block was run");
+    }
+
+    #[test]
+    fn test_do_block_with_program_instance() {
+        let mut interpreter = interpreter("fn counted_bind(c, f) -> Any { next = f(value_of(c)); counted(value_of(next), plus(count_of(c), count_of(next))) }\n\
+                                           fn counted_then(c, next) -> Any { counted(value_of(next), plus(count_of(c), count_of(next))) }\n\
+                                           fn counted_pure(x) -> Any { counted(x, 0u) }\n\
+                                           fn tick(n: UInt) -> Any { counted(add_one(n), 1u) }\n\
+                                           fn chain(start) -> Any { do {\n    x <- start\n    y <- tick(x)\n    tick(y)\n    z <- pure(add_one(y))\n    pure(z)\n} }");
+        for (name, function) in [("counted", counted as NativeFunction), ("value_of", value_of), ("count_of", count_of), ("plus", plus)] {
+            interpreter.add_native_function(name, function);
+        }
+        let functions = [(">>=", "counted_bind"), (">>", "counted_then"), ("pure", "counted_pure")].iter()
+            .map(|(name, function)| (name.to_string(), interpreter.get_value(function, &HashMap::new()).unwrap().unwrap()))
+            .collect();
+        interpreter.add_typeclass_instance(monad_class(), Type::Single("Counted".to_string()), functions);
+
+        let start = counted(&mut interpreter, vec![Value::UInt(1), Value::UInt(5)]).unwrap();
+        let result = interpreter.call_function("chain", vec![start], HashMap::new()).unwrap();
+
+        assert!(matches!(counted_field(&result, "value"), Value::UInt(3)), "The do block did not bind through the instance: {:?}", result);
+        assert!(matches!(counted_field(&result, "count"), Value::UInt(7)), "The instance's >>= and >> were not used: {:?}", result);
+    }
+
+    #[test]
+    fn test_do_block_with_promise() {
+        let mut interpreter = interpreter("@ThreadSpawn\nfn work(n: UInt) -> UInt { add_one(n) }\n\
+                                           fn chain() -> Any { do {\n    x <- work(1u)\n    y <- work(x)\n    pure(y)\n} }");
+
+        let result = interpreter.call_function("chain", vec![], HashMap::new()).and_then(await_value).unwrap();
+
+        assert!(matches!(result, Value::UInt(3)), "The do block did not wait for each promise: {:?}", result);
+    }
+
+    #[test]
+    fn test_do_block_errors_point_at_line() {
+        let mut interpreter = interpreter("fn main() -> (IO ()) { do {\n    x <- pure(1u)\n    panic(\"bad\")\n} }");
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| interpreter.start_program()));
+
+        match result {
+            Err(payload) => assert_eq!(panic_message(payload), "bad\n  in the do block statement on line 3"),
+            Ok(result) => panic!("Program should have panicked but gave {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_do_block_thrown_errors_point_at_line() {
+        let mut interpreter = interpreter("@Atomic\nruns := 0u;\n\
+                                           fn risky() exn -> (IO ()) { do {\n    x <- pure(1u)\n    throw(\"bad\")\n} }\n\
+                                           fn attempt() exn -> () { risky() }\n\
+                                           fn recover(message) -> () { deferred(1u); () }\n\
+                                           fn main() exn -> () { try(attempt, recover); attempt() }");
+
+        let Value::IO(action, _) = interpreter.call_function("risky", vec![], HashMap::new()).unwrap() else { panic!("risky did not give back IO") };
+        let thrown = interpreter.run_io(&action);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| interpreter.start_program()));
+
+        match thrown {
+            Err(RuntimeError::Located(5, ref error)) => assert!(matches!(**error, RuntimeError::UnhandledEffect(ref effect, ..) if effect == "exn"), "The wrong error was located: {:?}", error),
+            ref other => panic!("The thrown error did not get the line of its statement: {:?}", other),
+        }
+        assert!(thrown.unwrap_err().to_string().ends_with("\n  in the do block statement on line 5"), "The located error does not show its line");
+        assert!(matches!(runs(&interpreter), Value::UInt(1)), "try did not catch an exception thrown from a do block statement");
+        match result {
+            Err(payload) => assert_eq!(panic_message(payload), "bad\n  in the do block statement on line 5"),
+            Ok(result) => panic!("An uncaught exception should have ended the program but it gave {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use crate::interpreter::{Interpreter, RuntimeError, await_value};
+use crate::sync::Promise;
+use crate::types::{Type, Value, TypeUtils};
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+
+pub fn register(interpreter: &mut Interpreter) {
+    interpreter.add_native_function("par_map", par_map);
+    interpreter.add_native_function("par_filter", par_filter);
+    interpreter.add_native_function("par_reduce", par_reduce);
+    interpreter.add_native_function("par_sort", par_sort);
+    interpreter.add_native_function("par_sort_by", par_sort_by);
+}
+
+fn get_list<'a>(function_name: &str, value: &'a Value) -> (&'a Arc<Vec<Value>>, &'a Type) {
+    match value {
+        Value::List(values, element_type) => (values, element_type),
+        other => panic!("{} expects a list but was given a value of type {}", function_name, other.get_type()),
+    }
+}
+
+fn get_function<'a>(function_name: &str, value: &'a Value) -> &'a Value {
+    match value {
+        function @ Value::Function(..) => function,
+        other => panic!("{} expects a function but was given a value of type {}", function_name, other.get_type()),
+    }
+}
+
+fn check_argument_count(function_name: &str, arguments: &[Value], count: usize) {
+    if arguments.len() != count {
+        panic!("{} takes {} arguments but was given {}", function_name, count, arguments.len());
+    }
+}
+
+fn is_true(function_name: &str, value: &Value) -> bool {
+    match value {
+        Value::Algebraic{name, values, ..} if name == "Bool" => values.contains_key(&Type::Single("True".to_string())),
+        other => panic!("{} expects its function to return a Bool but it returned a value of type {}", function_name, other.get_type()),
+    }
+}
+
+/// This splits the list into one chunk per worker and runs `work` on every chunk in parallel.
+/// The results come back in the same order as the chunks so the callers can keep the order of the list.
+/// Everything that goes to another thread has to be sendable, just like the arguments of a `@ThreadSpawn` function.
+fn run_chunks<F>(interpreter: &Interpreter, function_name: &str, function: Option<&Value>, values: &[Value], work: F) -> Result<Vec<Value>, RuntimeError>
+where F: Fn(&mut Interpreter, Option<&Value>, Vec<Value>) -> Result<Value, RuntimeError> + Send + Sync + 'static {
+    if function.is_some_and(|function| !function.is_sendable()) {
+        panic!("Tried to move a function into {} but it holds a reference to a mutable variable", function_name);
+    }
+    if values.iter().any(|value| !value.is_sendable()) {
+        panic!("Tried to move a list into {} but it holds a reference to a mutable variable", function_name);
+    }
+    if values.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let chunk_size = values.len().div_ceil(interpreter.worker_count());
+    let work = Arc::new(work);
+    let promises: Vec<Promise> = values.chunks(chunk_size).map(|chunk| {
+        let chunk = chunk.to_vec();
+        let function = function.cloned();
+        let work = work.clone();
+        interpreter.spawn_task(function_name, move |interpreter| work(interpreter, function.as_ref(), chunk))
+    }).collect();
+
+    let mut results = Vec::new();
+    for promise in promises {
+        results.push(await_value(Value::create_promise(promise, Type::Single("a".to_string())))?);
+    }
+    Ok(results)
+}
+
+fn concatenate(function_name: &str, chunks: Vec<Value>) -> Vec<Value> {
+    chunks.iter().flat_map(|chunk| get_list(function_name, chunk).0.iter().cloned()).collect()
+}
+
+fn compare_values(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Int(left), Value::Int(right)) => Some(left.cmp(right)),
+        (Value::UInt(left), Value::UInt(right)) => Some(left.cmp(right)),
+        (Value::Float(left), Value::Float(right)) => left.partial_cmp(right),
+        (Value::Char(left), Value::Char(right)) => Some(left.cmp(right)),
+        (Value::List(left, _), Value::List(right, _)) => compare_sequences(left, right),
+        (Value::Tuple(left), Value::Tuple(right)) => compare_sequences(left, right),
+        _ => None,
+    }
+}
+
+fn compare_sequences(left: &[Value], right: &[Value]) -> Option<Ordering> {
+    for (left, right) in left.iter().zip(right) {
+        match compare_values(left, right)? {
+            Ordering::Equal => {},
+            ordering => return Some(ordering),
+        }
+    }
+    Some(left.len().cmp(&right.len()))
+}
+
+/// This merges two sorted lists, taking from the left list on ties so that the sort is stable.
+fn merge(left: Vec<Value>, right: Vec<Value>, less: &mut impl FnMut(&Value, &Value) -> Result<bool, RuntimeError>) -> Result<Vec<Value>, RuntimeError> {
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        if less(r, l)? {
+            merged.extend(right.next());
+        }
+        else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+/// This is a merge sort since a comparison written in the program can fail, which slice sorting doesn't allow for.
+fn merge_sort(mut values: Vec<Value>, less: &mut impl FnMut(&Value, &Value) -> Result<bool, RuntimeError>) -> Result<Vec<Value>, RuntimeError> {
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+    let right = values.split_off(values.len() / 2);
+    let left = merge_sort(values, less)?;
+    let right = merge_sort(right, less)?;
+    merge(left, right, less)
+}
+
+/// This sorts every chunk on its own thread and then merges the sorted chunks in order.
+fn sort_chunks<F>(interpreter: &mut Interpreter, function_name: &'static str, function: Option<&Value>, list: &Value, less: F) -> Result<Value, RuntimeError>
+where F: Fn(&mut Interpreter, Option<&Value>, &Value, &Value) -> Result<bool, RuntimeError> + Send + Sync + Clone + 'static {
+    let (values, element_type) = get_list(function_name, list);
+    let chunk_less = less.clone();
+    let chunks = run_chunks(interpreter, function_name, function, values, move |interpreter, function, chunk| {
+        let sorted = merge_sort(chunk, &mut |left, right| chunk_less(interpreter, function, left, right))?;
+        Ok(Value::List(sorted.into(), Type::Single("a".to_string())))
+    })?;
+
+    let mut sorted = Vec::new();
+    for chunk in chunks {
+        let (chunk, _) = get_list(function_name, &chunk);
+        sorted = merge(sorted, chunk.to_vec(), &mut |left, right| less(interpreter, function, left, right))?;
+    }
+    Ok(Value::List(sorted.into(), element_type.clone()))
+}
+
+/// `fn par_map((a) -> b, [a]) -> [b]`
+fn par_map(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    check_argument_count("par_map", &arguments, 2);
+    let function = get_function("par_map", &arguments[0]);
+    let (values, _) = get_list("par_map", &arguments[1]);
+    let chunks = run_chunks(interpreter, "par_map", Some(function), values, |interpreter, function, chunk| {
+        let function = function.expect("par_map always has a function");
+        let mut mapped = Vec::new();
+        for value in chunk {
+            mapped.push(interpreter.call_function_value("par_map", function, vec![value])?);
+        }
+        Ok(Value::List(mapped.into(), Type::Single("b".to_string())))
+    })?;
+
+    let mapped = concatenate("par_map", chunks);
+    let element_type = mapped.first().map(|value| value.get_type()).unwrap_or(Type::Single("b".to_string()));
+    Ok(Value::List(mapped.into(), element_type))
+}
+
+/// `fn par_filter((a) -> Bool, [a]) -> [a]`
+fn par_filter(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    check_argument_count("par_filter", &arguments, 2);
+    let function = get_function("par_filter", &arguments[0]);
+    let (values, element_type) = get_list("par_filter", &arguments[1]);
+    let chunks = run_chunks(interpreter, "par_filter", Some(function), values, |interpreter, function, chunk| {
+        let function = function.expect("par_filter always has a function");
+        let mut kept = Vec::new();
+        for value in chunk {
+            if is_true("par_filter", &interpreter.call_function_value("par_filter", function, vec![value.clone()])?) {
+                kept.push(value);
+            }
+        }
+        Ok(Value::List(kept.into(), Type::Single("a".to_string())))
+    })?;
+
+    Ok(Value::List(concatenate("par_filter", chunks).into(), element_type.clone()))
+}
+
+/// `fn par_reduce((a, a) -> a, a, [a]) -> a`
+/// Every chunk is folded on its own and then the results are folded onto the initial value, so the function has to be associative.
+fn par_reduce(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    check_argument_count("par_reduce", &arguments, 3);
+    let function = get_function("par_reduce", &arguments[0]);
+    let (values, _) = get_list("par_reduce", &arguments[2]);
+    let chunks = run_chunks(interpreter, "par_reduce", Some(function), values, |interpreter, function, chunk| {
+        let function = function.expect("par_reduce always has a function");
+        let mut chunk = chunk.into_iter();
+        let mut accumulator = chunk.next().expect("Chunks are never empty");
+        for value in chunk {
+            accumulator = interpreter.call_function_value("par_reduce", function, vec![accumulator, value])?;
+        }
+        Ok(accumulator)
+    })?;
+
+    let mut accumulator = arguments[1].clone();
+    for value in chunks {
+        accumulator = interpreter.call_function_value("par_reduce", function, vec![accumulator, value])?;
+    }
+    Ok(accumulator)
+}
+
+/// `fn par_sort([a]) -> [a]`
+/// This sorts numbers, characters, and lists and tuples of them.
+fn par_sort(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    check_argument_count("par_sort", &arguments, 1);
+    sort_chunks(interpreter, "par_sort", None, &arguments[0], |_, _, left, right| {
+        match compare_values(left, right) {
+            Some(ordering) => Ok(ordering == Ordering::Less),
+            None => panic!("par_sort can't compare a value of type {} with a value of type {}", left.get_type(), right.get_type()),
+        }
+    })
+}
+
+/// `fn par_sort_by((a, a) -> Bool, [a]) -> [a]`
+/// The function says whether its first argument goes before its second.
+fn par_sort_by(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    check_argument_count("par_sort_by", &arguments, 2);
+    let function = get_function("par_sort_by", &arguments[0]);
+    sort_chunks(interpreter, "par_sort_by", Some(function), &arguments[1], |interpreter, function, left, right| {
+        let function = function.expect("par_sort_by always has a function");
+        let less = interpreter.call_function_value("par_sort_by", function, vec![left.clone(), right.clone()])?;
+        Ok(is_true("par_sort_by", &less))
+    })
+}
+
+
+#[cfg(test)]
+mod parallel_tests {
+    use super::*;
+    use crate::parser::file_parser::file_parser_helper;
+    use crate::parser::expression_parser::Expression;
+
+    use std::collections::HashMap;
+
+    const FUNCTIONS: &str = "fn inc(x: Int) -> Int { add(x, 1) }\n\
+                             fn even(x: Int) -> Bool { is_even(x) }\n\
+                             fn sum(x: Int, y: Int) -> Int { add(x, y) }\n\
+                             fn greater(x: Int, y: Int) -> Bool { less(y, x) }\n";
+
+    fn add(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        match arguments.as_slice() {
+            [Value::Int(x), Value::Int(y)] => Ok(Value::Int(x + y)),
+            _ => panic!("add expects two Ints"),
+        }
+    }
+
+    fn is_even(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        match arguments.as_slice() {
+            [Value::Int(x)] => Ok(Value::bool(x % 2 == 0)),
+            _ => panic!("is_even expects an Int"),
+        }
+    }
+
+    fn less(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        match arguments.as_slice() {
+            [Value::Int(x), Value::Int(y)] => Ok(Value::bool(x < y)),
+            _ => panic!("less expects two Ints"),
+        }
+    }
+
+    fn interpreter() -> Interpreter {
+        let mut interpreter = Interpreter::with_worker_count(4);
+        interpreter.add_native_function("add", add);
+        interpreter.add_native_function("is_even", is_even);
+        interpreter.add_native_function("less", less);
+        file_parser_helper(FUNCTIONS, &mut interpreter);
+        interpreter
+    }
+
+    fn ints(values: impl IntoIterator<Item = i64>) -> Value {
+        Value::List(values.into_iter().map(Value::Int).collect::<Vec<_>>().into(), Type::Single("Int".to_string()))
+    }
+
+    fn to_ints(value: Value) -> Vec<i64> {
+        match value {
+            Value::List(values, _) => values.iter().map(|value| match value {
+                Value::Int(x) => *x,
+                _ => panic!("Expected a list of Ints"),
+            }).collect(),
+            _ => panic!("Expected a list"),
+        }
+    }
+
+    fn function(interpreter: &Interpreter, name: &str) -> Value {
+        interpreter.get_value(name, &HashMap::new()).unwrap().expect("Function was not declared")
+    }
+
+    #[test]
+    fn test_par_map_keeps_order() {
+        let mut interpreter = interpreter();
+        let inc = function(&interpreter, "inc");
+
+        let result = interpreter.call_function("par_map", vec![inc, ints(0..1000)], HashMap::new()).unwrap();
+
+        assert_eq!(to_ints(result), (1..1001).collect::<Vec<_>>(), "par_map changed the order of the list");
+    }
+
+    #[test]
+    fn test_par_filter_and_reduce() {
+        let mut interpreter = interpreter();
+        let even = function(&interpreter, "even");
+        let sum = function(&interpreter, "sum");
+
+        let evens = interpreter.call_function("par_filter", vec![even, ints(0..100)], HashMap::new()).unwrap();
+        let total = interpreter.call_function("par_reduce", vec![sum, Value::Int(5), ints(1..101)], HashMap::new()).unwrap();
+
+        assert_eq!(to_ints(evens), (0..100).step_by(2).collect::<Vec<_>>(), "par_filter kept the wrong values");
+        assert!(matches!(total, Value::Int(5055)), "par_reduce gave the wrong total: {:?}", total);
+    }
+
+    #[test]
+    fn test_par_sort() {
+        let mut interpreter = interpreter();
+        let greater = function(&interpreter, "greater");
+        let shuffled = ints((0..500).map(|x| (x * 7919) % 500));
+
+        let ascending = interpreter.call_function("par_sort", vec![shuffled.clone()], HashMap::new()).unwrap();
+        let descending = interpreter.call_function("par_sort_by", vec![greater, shuffled], HashMap::new()).unwrap();
+
+        assert_eq!(to_ints(ascending), (0..500).collect::<Vec<_>>(), "par_sort did not sort the list");
+        assert_eq!(to_ints(descending), (0..500).rev().collect::<Vec<_>>(), "par_sort_by did not use the function");
+    }
+
+    #[test]
+    fn test_functions_are_values_in_programs() {
+        let mut interpreter = interpreter();
+        file_parser_helper("fn main() -> (List Int) { par_map(inc, [1, 2, 3]) }", &mut interpreter);
+
+        let result = interpreter.call_function("main", vec![], HashMap::new()).unwrap();
+
+        assert_eq!(to_ints(result), vec![2, 3, 4], "par_map did not run the named function");
+    }
+
+    #[test]
+    #[should_panic(expected = "Tried to move a function into par_map but it holds a reference to a mutable variable")]
+    fn test_captured_reference_panics() {
+        let mut interpreter = interpreter();
+        let captured = HashMap::from([("total".to_string(), Value::new_ref(Value::Int(0)))]);
+        let function = Value::Function(vec![], vec![], vec![], Type::Single("Int".to_string()), captured, Expression::Block(Vec::new()));
+
+        let _ = interpreter.call_function("par_map", vec![function, ints(0..10)], HashMap::new());
+    }
+}
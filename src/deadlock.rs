@@ -0,0 +1,157 @@
+use crate::sync::{GlobalMutex, GlobalGuard, LockError};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+
+
+/// This is one of the threads that make up a deadlock.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadlockedThread {
+    pub thread: String,
+    pub waiting_for: String,
+    pub held_by: String,
+    pub frames: Vec<String>,//The script functions the thread was in, innermost last
+}
+
+/// This describes a cycle in the waits-for graph, where every thread waits for a global that the next one holds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadlockReport {
+    pub threads: Vec<DeadlockedThread>,
+}
+
+impl fmt::Display for DeadlockReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Deadlock between {} threads", self.threads.len())?;
+        for thread in &self.threads {
+            write!(f, "\n  thread {} is waiting for {}, which is held by thread {}", thread.thread, thread.waiting_for, thread.held_by)?;
+            for frame in thread.frames.iter().rev() {
+                write!(f, "\n    in {}", frame)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Waiter {
+    thread: String,
+    global: String,
+    mutex: Arc<GlobalMutex>,
+    frames: Vec<String>,
+}
+
+/// This keeps track of which thread is waiting for which `@ThreadMutable` global.
+/// Together with the owners of the globals this is the waits-for graph, and a thread that is about to wait checks that it doesn't close a cycle.
+/// Registering and checking happen under one lock so the last thread to join a cycle always sees it.
+/// Threads stand in for tasks here for the same reason they do in `GlobalMutex`.
+#[derive(Debug, Default)]
+pub struct DeadlockDetector {
+    waiters: Mutex<HashMap<ThreadId, Waiter>>,
+}
+
+fn thread_name() -> String {
+    let current = thread::current();
+    current.name().map(str::to_string).unwrap_or_else(|| format!("{:?}", current.id()))
+}
+
+impl DeadlockDetector {
+    pub fn new() -> DeadlockDetector {
+        DeadlockDetector::default()
+    }
+
+    /// This takes the lock like `GlobalMutex::lock` but fails with a report instead of waiting if waiting would deadlock.
+    pub fn lock(&self, name: &str, mutex: &Arc<GlobalMutex>, frames: &[String], timeout: Option<Duration>) -> Result<GlobalGuard, LockError> {
+        match mutex.lock(Some(Duration::ZERO)) {
+            Err(LockError::TimedOut) => {},
+            result => return result,
+        }
+
+        let me = thread::current().id();
+        {
+            let mut waiters = self.waiters.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            waiters.insert(me, Waiter {
+                thread: thread_name(),
+                global: name.to_string(),
+                mutex: mutex.clone(),
+                frames: frames.to_vec(),
+            });
+            if let Some(report) = find_cycle(&waiters, me) {
+                waiters.remove(&me);
+                return Err(LockError::Deadlock(report));
+            }
+        }
+
+        let result = mutex.lock(timeout);
+        self.waiters.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(&me);
+        result
+    }
+}
+
+/// This follows the waits-for graph from `start` and reports the cycle if it leads back to `start`.
+fn find_cycle(waiters: &HashMap<ThreadId, Waiter>, start: ThreadId) -> Option<DeadlockReport> {
+    let mut chain = vec![start];
+    loop {
+        let waiter = &waiters[chain.last().expect("The chain always has a thread")];
+        let owner = waiter.mutex.owner()?;
+        if owner == start {
+            break;
+        }
+        // A cycle that we aren't part of was already reported by whoever closed it
+        if chain.contains(&owner) || !waiters.contains_key(&owner) {
+            return None;
+        }
+        chain.push(owner);
+    }
+
+    let threads = chain.iter().enumerate().map(|(index, thread)| {
+        let waiter = &waiters[thread];
+        let holder = &waiters[&chain[(index + 1) % chain.len()]];
+        DeadlockedThread {
+            thread: waiter.thread.clone(),
+            waiting_for: waiter.global.clone(),
+            held_by: holder.thread.clone(),
+            frames: waiter.frames.clone(),
+        }
+    }).collect();
+    Some(DeadlockReport { threads })
+}
+
+
+#[cfg(test)]
+mod deadlock_tests {
+    use super::*;
+    use crate::interpreter::Variable;
+    use crate::types::Value;
+
+    use std::sync::Barrier;
+
+    #[test]
+    fn test_opposite_lock_order_is_reported() {
+        let detector = Arc::new(DeadlockDetector::new());
+        let a = Arc::new(GlobalMutex::new(Variable::new(Value::Int(0))));
+        let b = Arc::new(GlobalMutex::new(Variable::new(Value::Int(0))));
+        let barrier = Arc::new(Barrier::new(2));
+
+        let handles: Vec<_> = [("a", a.clone(), "b", b.clone()), ("b", b, "a", a)].into_iter().map(|(first_name, first, second_name, second)| {
+            let detector = detector.clone();
+            let barrier = barrier.clone();
+            thread::Builder::new().name(format!("holds-{}", first_name)).spawn(move || {
+                let _first = detector.lock(first_name, &first, &["main".to_string()], None).unwrap();
+                barrier.wait();
+                detector.lock(second_name, &second, &["main".to_string(), "transfer".to_string()], None).err()
+            }).unwrap()
+        }).collect();
+        let errors: Vec<_> = handles.into_iter().filter_map(|handle| handle.join().unwrap()).collect();
+
+        assert_eq!(errors.len(), 1, "Exactly one thread should see the deadlock");
+        let LockError::Deadlock(report) = &errors[0] else { panic!("Expected a deadlock but got {:?}", errors[0]) };
+        let mut globals: Vec<_> = report.threads.iter().map(|thread| thread.waiting_for.as_str()).collect();
+        globals.sort();
+        assert_eq!(globals, vec!["a", "b"], "Report did not name both globals");
+        assert!(report.threads.iter().all(|thread| thread.frames == ["main", "transfer"]), "Report did not keep the stack frames");
+        assert!(report.to_string().contains("in transfer"), "Report did not print the stack frames");
+    }
+}
@@ -0,0 +1,272 @@
+use crate::parser::effect_parser::{Effect, effect_name};
+use crate::parser::expression_parser::Expression;
+use crate::types::{Type, Value};
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+
+/// These are the ways a program can get its effects wrong.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EffectError {
+    Undeclared {
+        function: String,
+        effect: String,
+    },
+    EscapesMain(String),
+}
+
+impl fmt::Display for EffectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EffectError::Undeclared { function, effect } => write!(f, "Function {} performs the effect {} but does not declare it", function, effect),
+            EffectError::EscapesMain(effect) => write!(f, "The effect {} can escape main but it has no @Final operation", effect),
+        }
+    }
+}
+
+/// This checks the effects of every function before the program runs.
+/// The effects a body performs come from the operations it calls and the declared effects of the functions it calls or passes around.
+/// A `with` block takes away an effect when it handles every operation of it, but what its handlers do is still performed outside of it.
+/// Calling a parameter adds nothing, since the effects of a function passed to a higher-order function are charged to whoever passes it.
+/// Built-in functions perform the effects they were registered with, which are checked the same way as those of a declared function.
+/// Only effects that are declared with `effect` or performed by a built-in function are checked, so functions can still list other effects freely.
+/// An effect that only built-in functions perform, like `Console`, is provided by the interpreter, so it can't be handled and is allowed to escape main.
+/// A `main` that returns `IO` is run by the interpreter as well, so it doesn't have to declare those effects either.
+pub struct EffectChecker<'a> {
+    functions: &'a HashMap<String, Value>,
+    natives: &'a HashMap<String, &'static [&'static str]>,
+    effects: &'a HashMap<String, Effect>,
+    operations: HashMap<&'a str, String>,
+    provided: HashSet<&'static str>,
+}
+
+impl<'a> EffectChecker<'a> {
+    pub fn new(functions: &'a HashMap<String, Value>, natives: &'a HashMap<String, &'static [&'static str]>, effects: &'a HashMap<String, Effect>) -> EffectChecker<'a> {
+        let operations = effects.iter()
+            .flat_map(|(name, effect)| effect.operations.iter().map(move |operation| (operation.name.as_str(), name.clone())))
+            .collect();
+        let provided = natives.values().flat_map(|effects| effects.iter().copied()).collect();
+        EffectChecker { functions, natives, effects, operations, provided }
+    }
+
+    /// This checks every function and effect operation, and then that whatever main performs can be finished by a `@Final` operation.
+    pub fn check(&self) -> Result<(), Vec<EffectError>> {
+        let mut errors = Vec::new();
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by_key(|(name, _)| *name);
+        let operations = self.effects.values().flat_map(|effect| effect.operations.iter().filter(|operation| operation.has_body()).map(|operation| (&operation.name, &operation.function)));
+        for (name, function) in functions.into_iter().chain(operations) {
+            let runs_in_io = name == "main" && returns_io(function);
+            for effect in self.undeclared_effects(function) {
+                if runs_in_io && self.is_provided(&effect) {
+                    continue;
+                }
+                errors.push(EffectError::Undeclared { function: name.clone(), effect });
+            }
+        }
+
+        if let Some(main) = self.functions.get("main") {
+            for effect in self.declared_effects(main).union(&self.function_effects(main)) {
+                let finished = self.effects.get(effect).is_none_or(|effect| effect.final_operation().is_some_and(|operation| operation.has_body()));
+                if !finished {
+                    errors.push(EffectError::EscapesMain(effect.clone()));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(errors)
+        }
+    }
+
+    /// This is true for an effect that the interpreter provides instead of a handler.
+    fn is_provided(&self, effect: &str) -> bool {
+        self.provided.contains(effect) && !self.effects.contains_key(effect)
+    }
+
+    fn declared_effects(&self, function: &Value) -> BTreeSet<String> {
+        match function {
+            Value::Function(_, _, effects, ..) => effects.iter().map(effect_name).collect(),
+            _ => BTreeSet::new(),
+        }
+    }
+
+    fn undeclared_effects(&self, function: &Value) -> BTreeSet<String> {
+        let declared = self.declared_effects(function);
+        self.function_effects(function).into_iter().filter(|effect| !declared.contains(effect)).collect()
+    }
+
+    /// This is every declared effect that the body of a function performs.
+    pub fn function_effects(&self, function: &Value) -> BTreeSet<String> {
+        match function {
+            Value::Function(_, args, _, _, captured, body) => {
+                let mut locals: HashSet<String> = args.iter().map(|(name, _)| name.clone()).chain(captured.keys().cloned()).collect();
+                assigned_names(body, &mut locals);
+                self.expression_effects(body, &locals)
+            },
+            _ => BTreeSet::new(),
+        }
+    }
+
+    fn expression_effects(&self, expression: &Expression, locals: &HashSet<String>) -> BTreeSet<String> {
+        let mut effects = BTreeSet::new();
+        match expression {
+            Expression::Literal(_) | Expression::Retry => {},
+            Expression::Variable(name) => effects.extend(self.named_effects(name, locals)),
+            Expression::Call(name, arguments) => {
+                if !locals.contains(name) {
+                    match self.operations.get(name.as_str()) {
+                        Some(effect) if !self.functions.contains_key(name) => {
+                            effects.insert(effect.clone());
+                        },
+                        _ => effects.extend(self.named_effects(name, locals)),
+                    }
+                }
+                for argument in arguments {
+                    effects.extend(self.expression_effects(argument, locals));
+                }
+            },
+            Expression::List(items) | Expression::Tuple(items) | Expression::Block(items) => {
+                for item in items {
+                    effects.extend(self.expression_effects(item, locals));
+                }
+            },
+            Expression::Assign { value, .. } => effects.extend(self.expression_effects(value, locals)),
+            Expression::Lock(_, body) | Expression::Scope(body) | Expression::Atomically(body) => effects.extend(self.expression_effects(body, locals)),
+            Expression::For { iterable, body, .. } => {
+                effects.extend(self.expression_effects(iterable, locals));
+                effects.extend(self.expression_effects(body, locals));
+            },
+            Expression::With(handlers, body) => {
+                let handled: HashSet<&str> = handlers.iter().map(|handler| handler.operation.as_str()).collect();
+                effects.extend(self.expression_effects(body, locals).into_iter().filter(|effect| {
+                    !self.effects.get(effect).is_some_and(|effect| effect.operations.iter()
+                        .filter(|operation| !operation.is_final())
+                        .all(|operation| handled.contains(operation.name.as_str())))
+                }));
+                for handler in handlers {
+                    let mut handler_locals = locals.clone();
+                    handler_locals.extend(handler.args.iter().map(|(name, _)| name.clone()));
+                    assigned_names(&handler.body, &mut handler_locals);
+                    effects.extend(self.expression_effects(&handler.body, &handler_locals));
+                }
+            },
+            // Do blocks call their lambdas right away, so what they perform is performed by the function around them
+            Expression::Lambda(args, body) => {
+                let mut lambda_locals = locals.clone();
+                lambda_locals.extend(args.iter().map(|(name, _)| name.clone()));
+                assigned_names(body, &mut lambda_locals);
+                effects.extend(self.expression_effects(body, &lambda_locals));
+            },
+            Expression::Located(_, body) | Expression::Spread(body) => effects.extend(self.expression_effects(body, locals)),
+        }
+        effects
+    }
+
+    /// This is what using a global function performs, which is only known from the effects it declares.
+    fn named_effects(&self, name: &str, locals: &HashSet<String>) -> BTreeSet<String> {
+        if locals.contains(name) {
+            return BTreeSet::new();
+        }
+        let declared = match (self.functions.get(name), self.natives.get(name)) {
+            (Some(function), _) => self.declared_effects(function),
+            (None, Some(effects)) => effects.iter().map(|effect| effect.to_string()).collect(),
+            (None, None) => BTreeSet::new(),
+        };
+        declared.into_iter().filter(|effect| self.effects.contains_key(effect) || self.provided.contains(effect.as_str())).collect()
+    }
+}
+
+fn returns_io(function: &Value) -> bool {
+    match function {
+        Value::Function(_, _, _, Type::TypeList{name, ..}, ..) => matches!(name.as_ref(), Type::Single(name) if name == "IO"),
+        Value::Function(_, _, _, Type::Single(name), ..) => name == "IO",
+        _ => false,
+    }
+}
+
+/// This collects every variable a body declares, so that calling one of them isn't mistaken for calling a global function.
+fn assigned_names(expression: &Expression, names: &mut HashSet<String>) {
+    match expression {
+        Expression::Assign { name, value, .. } => {
+            names.insert(name.clone());
+            assigned_names(value, names);
+        },
+        Expression::Call(_, items) | Expression::List(items) | Expression::Tuple(items) | Expression::Block(items) => {
+            for item in items {
+                assigned_names(item, names);
+            }
+        },
+        Expression::Lock(_, body) | Expression::Scope(body) | Expression::Atomically(body) | Expression::With(_, body) | Expression::Located(_, body) | Expression::Spread(body) => assigned_names(body, names),
+        Expression::For { variable, iterable, body } => {
+            names.insert(variable.clone());
+            assigned_names(iterable, names);
+            assigned_names(body, names);
+        },
+        // The variables of a lambda are its own
+        Expression::Literal(_) | Expression::Variable(_) | Expression::Retry | Expression::Lambda(..) => {},
+    }
+}
+
+
+#[cfg(test)]
+mod effect_checker_tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::parser::file_parser::file_parser_helper;
+
+    const EFFECTS: &str = "effect fail { @Control fn fail(UInt) -> a, @Final fn uncaught(n: UInt) -> () { () } }\n\
+                           effect ask { fn ask() -> UInt }\n";
+
+    fn check(program: &str) -> Result<(), Vec<EffectError>> {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper(&format!("{}{}", EFFECTS, program), &mut interpreter);
+        interpreter.check_effects()
+    }
+
+    #[test]
+    fn test_declared_effects_pass() {
+        assert_eq!(check("fn failing() fail -> UInt { fail(1u) }\nfn main() fail -> UInt { failing() }"), Ok(()));
+    }
+
+    #[test]
+    fn test_undeclared_effect() {
+        let errors = check("fn failing() -> UInt { fail(1u) }\nfn main() fail -> UInt { failing() }").unwrap_err();
+
+        assert_eq!(errors, vec![EffectError::Undeclared { function: "failing".to_string(), effect: "fail".to_string() }]);
+    }
+
+    #[test]
+    fn test_with_removes_handled_effect() {
+        assert_eq!(check("fn failing() fail -> UInt { fail(1u) }\nfn main() -> UInt { with fn fail(n) { n } { failing() } }"), Ok(()));
+        let errors = check("fn main() -> UInt { with fn fail(n) { ask() } { fail(1u) } }").unwrap_err();
+        assert_eq!(errors, vec![EffectError::Undeclared { function: "main".to_string(), effect: "ask".to_string() }, EffectError::EscapesMain("ask".to_string())], "The handler's own effects were not counted");
+    }
+
+    #[test]
+    fn test_higher_order_functions_are_polymorphic() {
+        let program = "fn apply(f, x: UInt) -> UInt { f(x) }\nfn failing(n: UInt) fail -> UInt { fail(n) }\n";
+
+        assert_eq!(check(&format!("{}fn main() fail -> UInt {{ apply(failing, 1u) }}", program)), Ok(()));
+        let errors = check(&format!("{}fn main() -> UInt {{ apply(failing, 1u) }}", program)).unwrap_err();
+        assert_eq!(errors, vec![EffectError::Undeclared { function: "main".to_string(), effect: "fail".to_string() }]);
+    }
+
+    #[test]
+    fn test_effect_escaping_main_needs_final() {
+        let errors = check("fn main() ask -> UInt { ask() }").unwrap_err();
+
+        assert_eq!(errors, vec![EffectError::EscapesMain("ask".to_string())]);
+    }
+
+    #[test]
+    fn test_builtin_effects_are_checked() {
+        let errors = check("fn half() -> UInt { div(1u, 0u) }\nfn first() exn -> UInt { get([1u], 0u) }").unwrap_err();
+
+        assert_eq!(errors, vec![EffectError::Undeclared { function: "half".to_string(), effect: "exn".to_string() }]);
+    }
+}
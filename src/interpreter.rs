@@ -1,9 +1,25 @@
 
 use std::collections::{HashMap, HashSet};
-use std::sync::{RwLock, Arc, Mutex, TryLockResult, TryLockError};
+use std::sync::{RwLock, Arc, Mutex};
+use std::fmt;
+use std::time::Duration;
 use std::thread;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
 
-use crate::types::{Type, Value,TypeUtils, ValRef};
+use crate::types::{Type, Value,TypeUtils, ValRef, AtomicValue, IoAction, is_varargs_type, match_instance};
+use crate::builtins::register_builtins;
+use crate::builtins::channel::receive;
+use crate::sync::{GlobalMutex, GlobalGuard, LockError, Promise, TaskScope, Actor};
+use crate::thread_pool::{self, ThreadPool};
+use crate::deadlock::{DeadlockDetector, DeadlockReport};
+use crate::scheduler::Scheduler;
+use crate::stm::Transaction;
+use crate::effect_checker::{EffectChecker, EffectError};
+use crate::parser::function_parser::Attribute;
+use crate::parser::expression_parser::{Expression, EffectHandler};
+use crate::parser::global_parser::GlobalVariable;
+use crate::parser::effect_parser::Effect;
 
 #[derive(Debug, Clone)]
 pub struct Variable {
@@ -60,6 +76,15 @@ impl Variable {
         }
     }
 
+    /// This overwrites the value without going through a reference.
+    /// Globals that are protected by a mutex use this since the mutex is what makes them mutable.
+    pub fn replace_value(&mut self, r_value: Value) {
+        if self.the_type.get_type() != r_value.get_type() {
+            panic!("Tried to set a value of the wrong type to a variable");
+        }
+        self.value = Some(r_value);
+    }
+
 
     pub fn get_immutable(&self) -> Value {
         if let Some(ref value) = self.value {
@@ -88,6 +113,124 @@ impl Variable {
     //TODO: Add function that allows us to bind generics to a variable
 }
 
+/// These are the errors that a running program can cause.
+/// Unlike panics they are handed back to the caller so that they can be reported or handled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    UndefinedVariable(String),
+    NotLockable(String),
+    LockPoisoned(String),
+    LockTimeout(String),
+    ThreadPanicked(String, String),
+    ChannelClosed,
+    ChannelTypeMismatch(Type, Type),
+    Deadlock(DeadlockReport),
+    TransactionRetry,
+    TransactionConflict,
+    UnhandledEffect(String, String, EffectArguments),
+    EffectUnwind(usize, String, EffectArguments),
+    Effects(Vec<EffectError>),
+    Located(usize, Box<RuntimeError>),//The line of the do block statement the error came out of
+}
+
+/// These are the arguments of an effect operation that is unwinding the stack.
+/// Values can't be compared, so two of these are only equal if they are the same arguments.
+#[derive(Debug, Clone)]
+pub struct EffectArguments(Arc<Vec<Value>>);
+
+impl EffectArguments {
+    pub fn new(arguments: Vec<Value>) -> EffectArguments {
+        EffectArguments(Arc::new(arguments))
+    }
+
+    pub fn to_vec(&self) -> Vec<Value> {
+        self.0.as_ref().clone()
+    }
+}
+
+impl PartialEq for EffectArguments {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::UndefinedVariable(name) => write!(f, "Tried to use a variable that doesn't exist: {}", name),
+            RuntimeError::NotLockable(name) => write!(f, "Tried to lock {} which is not a Thread-Mutable global variable", name),
+            RuntimeError::LockPoisoned(name) => write!(f, "Another thread panicked while holding the lock for {}", name),
+            RuntimeError::LockTimeout(name) => write!(f, "Timed out waiting for the lock for {}", name),
+            RuntimeError::ThreadPanicked(function, message) => write!(f, "Thread running {} panicked: {}", function, message),
+            RuntimeError::ChannelClosed => write!(f, "Tried to use a channel that has been closed"),
+            RuntimeError::ChannelTypeMismatch(element_type, value_type) => write!(f, "Tried to send a value of type {} on a channel of {}", value_type, element_type),
+            RuntimeError::Deadlock(report) => write!(f, "{}", report),
+            RuntimeError::TransactionRetry => write!(f, "Used retry outside of an atomically block"),
+            RuntimeError::TransactionConflict => write!(f, "A transaction read a global that another thread changed"),
+            RuntimeError::UnhandledEffect(effect, operation, _) => write!(f, "Nothing handled {} from the effect {}", operation, effect),
+            RuntimeError::EffectUnwind(_, operation, _) => write!(f, "Tried to unwind to a handler for {} that is no longer running", operation),
+            RuntimeError::Effects(errors) => {
+                let errors: Vec<String> = errors.iter().map(EffectError::to_string).collect();
+                write!(f, "{}", errors.join("\n"))
+            },
+            RuntimeError::Located(line, error) => write!(f, "{}\n  in the do block statement on line {}", error, line),
+        }
+    }
+}
+
+/// This waits for a promise to finish and gives back what it holds.
+/// Any other value is already finished so it is handed back as is.
+pub fn await_value(value: Value) -> Result<Value, RuntimeError> {
+    let mut value = value;
+    while let Value::Promise(promise, _) = value {
+        value = thread_pool::wait_helping(None, || promise.try_result()).expect("Waiting without a deadline never times out")?;
+    }
+    Ok(value)
+}
+
+/// This adds the line of a do block statement to the message of any panic that happens while it runs, and to the error it gives back.
+/// Nested do blocks each add their own line, and whoever catches the panic or the error reports it with all of them.
+/// Errors that only move control back to a `with` or `atomically` block are passed on as they are.
+fn at_line<T>(line: usize, run: impl FnOnce() -> Result<T, RuntimeError>) -> Result<T, RuntimeError> {
+    let result = match panic::catch_unwind(AssertUnwindSafe(run)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let location = format!("  in the do block statement on line {}", line);
+            panic::resume_unwind(Box::new(format!("{}\n{}", panic_message(payload), location)))
+        },
+    };
+    result.map_err(|error| match error {
+        RuntimeError::EffectUnwind(..) | RuntimeError::TransactionRetry | RuntimeError::TransactionConflict => error,
+        error => RuntimeError::Located(line, Box::new(error)),
+    })
+}
+
+/// This puts every argument after the first `fixed` ones into an H-List, which takes the place of all of them.
+/// A call with too few arguments is left as it is for the function to complain about.
+pub fn collect_varargs(mut arguments: Vec<Value>, fixed: usize) -> Vec<Value> {
+    if arguments.len() >= fixed {
+        let rest = arguments.split_off(fixed);
+        arguments.push(Value::HList(rest));
+    }
+    arguments
+}
+
+fn is_promise_type(the_type: &Type) -> bool {
+    matches!(the_type, Type::TypeList { name, .. } if **name == Type::Single("Promise".to_string()))
+}
+
+pub fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    }
+    else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    }
+    else {
+        "unknown panic".to_string()
+    }
+}
+
 /// This represents a typeclass implementation.
 /// It contains a Type to allow us to type check and a hashmap of functions.
 pub struct TypeClass {
@@ -112,38 +255,116 @@ impl TypeClass {
 
 }
 
+/// These are the instances of every typeclass function, kept with the type each one is for.
+type InstanceTable = HashMap<String, Vec<(Type, Value)>>;
+
 /// This represents the interpreter's data structure.
-/// There are two symbol tables, one for named functions and one for typeclasses. The typeclass one has double indirection because we don't know the type of a function since there will be multiple implementations,
-/// There is also a hashmap that allows us to lookup the typeclass for a type.
-/// We then we have a hashmap that allows us to lookup the valid typeclasses for a type so we can't implement typeclasses that don't exist.
-/// We then have a hashmap that allows us to lookup global variables. These are either immutable or mutable, But they are all local to the thread. Immutable Variables can't be reassigned.
-/// We then have a hashmap that allows us to lookup shared global variables. These are all immutable and cannot be reassigned by any thread.
-/// We then have a hashmap that allows us to lookup mutable global variables. These are all mutable and can be reassigned by any thread. They are however protected by a mutex.
+/// There are symbol tables for named functions, for the functions that are built into the interpreter and written in Rust, and for typeclasses.
+/// The typeclass one has double indirection because we don't know the type of a function since there will be multiple implementations.
+/// There are also hashmaps for the default implementations of typeclass functions and for the valid typeclasses of a type, so we can't implement typeclasses that don't exist.
+/// Global variables come in four kinds:
+/// thread local ones, which every thread gets its own copy of by rerunning their declarations in order so that a declaration can use the globals before it,
+/// shared ones, which are immutable and cannot be reassigned by any thread,
+/// mutable ones, which any thread can reassign but only while holding their mutex, waited on for at most the lock timeout,
+/// and atomic ones, which are numbers that every thread can update without taking a lock.
+/// `@ThreadSpawn` calls go onto the thread pool shared by every interpreter made from this one, or onto the scheduler in deterministic mode, and belong to the innermost `scope` block.
+/// Each interpreter keeps the stack of script functions its thread is in for the optional deadlock detector, and the transaction log while it is inside of an `atomically` block.
+/// Every actor the program starts is kept with the promise of its thread so that they can be stopped when main returns.
+/// The declared effects are kept along with which effect each operation belongs to, so that operations can be called by name, and the handlers installed by `with` blocks are kept on a stack for each thread, innermost last.
+/// While a built-in function runs, the local variables of whoever called it are kept so that it can look them up by name.
 #[derive(Debug, Clone)]
 pub struct Interpreter {
     function_symbol_table: Arc<RwLock<HashMap<String, Value>>>,
-    type_class_symbol_table: Arc<RwLock<HashMap<String, HashMap<Type, Value>>>>,
+    native_function_table: Arc<RwLock<HashMap<String, NativeEntry>>>,
+    type_class_symbol_table: Arc<RwLock<InstanceTable>>,
     default_symbol_table: Arc<RwLock<HashMap<String, Value>>>,
     valid_typeclasses: Arc<RwLock<HashMap<Type, Vec<Type>>>>,
     valid_types: Arc<RwLock<HashSet<Type>>>,
     local_global_variables: HashMap<String, Variable>,
+    thread_local_declarations: Arc<RwLock<Vec<GlobalVariable>>>,
     shared_global_variables: Arc<RwLock<HashMap<String, Variable>>>,
-    mutable_global_variables: Arc<RwLock<HashMap<String, Arc<Mutex<Variable>>>>>,
+    mutable_global_variables: Arc<RwLock<HashMap<String, Arc<GlobalMutex>>>>,
+    atomic_global_variables: Arc<RwLock<HashMap<String, AtomicValue>>>,
+    lock_timeout: Option<Duration>,
+    thread_pool: Arc<ThreadPool>,
+    task_scope: Option<TaskScope>,
+    call_stack: Vec<String>,
+    deadlock_detector: Option<Arc<DeadlockDetector>>,
+    scheduler: Option<Arc<Scheduler>>,
+    transaction: Option<Transaction>,
+    actors: Arc<Mutex<Vec<(Actor, Promise)>>>,
+    effect_table: Arc<RwLock<HashMap<String, Effect>>>,
+    effect_operation_table: Arc<RwLock<HashMap<String, String>>>,
+    handlers: Vec<Handler>,
+    next_handler_frame: usize,
+    caller_variables: HashMap<String, Value>,
+}
+
+/// This is a handler installed by a `with` block.
+/// `frame` tells apart the `with` blocks on a thread so that a `@Control` operation unwinds to the one that handles it.
+#[derive(Debug, Clone)]
+struct Handler {
+    frame: usize,
+    operation: String,
+    function: Value,
+}
+
+/// This is the signature of a built-in function.
+/// They get the interpreter so that they can call back into the program.
+pub type NativeFunction = fn(&mut Interpreter, Vec<Value>) -> Result<Value, RuntimeError>;
+
+/// This is a built-in function along with how it wants to be called and the effects it performs.
+#[derive(Debug, Clone, Copy)]
+struct NativeEntry {
+    function: NativeFunction,
+    awaits_arguments: bool,
+    fixed: Option<usize>,//How many arguments come before the rest are collected, if it is variadic
+    effects: &'static [&'static str],
+    picks_instance: bool,//Whether it picks the typeclass instance to call itself instead of one being picked by its first argument
+}
+
+impl NativeEntry {
+    fn new(function: NativeFunction, awaits_arguments: bool, fixed: Option<usize>) -> NativeEntry {
+        NativeEntry { function, awaits_arguments, fixed, effects: &[], picks_instance: false }
+    }
 }
 
 
 impl Interpreter {
     pub fn new() -> Interpreter {
-        Interpreter {
+        Interpreter::with_worker_count(ThreadPool::default_worker_count())
+    }
+
+    /// This makes an interpreter whose thread pool runs at most `worker_count` threads at once.
+    pub fn with_worker_count(worker_count: usize) -> Interpreter {
+        let mut interpreter = Interpreter {
             function_symbol_table: Arc::new(RwLock::new(HashMap::new())),
+            native_function_table: Arc::new(RwLock::new(HashMap::new())),
             type_class_symbol_table: Arc::new(RwLock::new(HashMap::new())),
             default_symbol_table: Arc::new(RwLock::new(HashMap::new())),
             valid_typeclasses: Arc::new(RwLock::new(HashMap::new())),
             valid_types: Arc::new(RwLock::new(HashSet::new())),
             local_global_variables: HashMap::new(),
+            thread_local_declarations: Arc::new(RwLock::new(Vec::new())),
             shared_global_variables: Arc::new(RwLock::new(HashMap::new())),
             mutable_global_variables: Arc::new(RwLock::new(HashMap::new())),
-        }
+            atomic_global_variables: Arc::new(RwLock::new(HashMap::new())),
+            lock_timeout: None,
+            thread_pool: Arc::new(ThreadPool::new(worker_count)),
+            task_scope: None,
+            call_stack: Vec::new(),
+            deadlock_detector: None,
+            scheduler: None,
+            transaction: None,
+            actors: Arc::new(Mutex::new(Vec::new())),
+            effect_table: Arc::new(RwLock::new(HashMap::new())),
+            effect_operation_table: Arc::new(RwLock::new(HashMap::new())),
+            handlers: Vec::new(),
+            next_handler_frame: 0,
+            caller_variables: HashMap::new(),
+        };
+        register_builtins(&mut interpreter);
+        interpreter
     }
 }
 
@@ -175,18 +396,110 @@ impl Interpreter {
         self.valid_typeclasses.write().expect("Interpreter was not able to be written to").insert(class, func_table);
     }
 
-    pub fn add_typeclass_instance(&mut self, class: Type, functions: Vec<(String, Value)>) {
+    /// This adds the functions of a typeclass for one type, which are picked when the first argument has that type.
+    pub fn add_typeclass_instance(&mut self, class: Type, instance: Type, functions: Vec<(String, Value)>) {
         if !self.valid_typeclasses.read().unwrap().contains_key(&class) {
             panic!("Tried to add a typeclass instance for a typeclass that doesn't exist");
         }
 
+        // Types are matched rather than hashed, so an instance for `(Maybe a)` and one for `(Maybe Int)` can both be kept
         let mut table = self.type_class_symbol_table.write().expect("Interpreter was not able to be written to");
         for (name, func) in functions {
-            if let Some(ref mut v_table) = table.get_mut(&name) {
-                v_table.insert(class.clone(), func);
+            let instances = table.entry(name).or_default();
+            let same_instance = |other: &Type| match_instance(other, &instance).is_some() && match_instance(&instance, other).is_some();
+            match instances.iter_mut().find(|(other, _)| same_instance(other)) {
+                Some(existing) => existing.1 = func,
+                None => instances.push((instance.clone(), func)),
             }
+        }
+    }
+
+    /// This finds the instance of a typeclass function for a type, which built-ins use before falling back to their own version.
+    /// An instance for a type with type variables, like `(Maybe a)`, fits every type it can be matched with, but an instance with fewer variables wins.
+    pub fn typeclass_function(&self, name: &str, the_type: &Type) -> Option<Value> {
+        let table = self.type_class_symbol_table.read().expect("Unable to read interpreter");
+        table.get(name)?.iter()
+            .filter_map(|(instance, function)| match_instance(instance, the_type).map(|bound| (bound, function)))
+            .min_by_key(|(bound, _)| *bound)
+            .map(|(_, function)| function.clone())
+    }
 
+    /// This adds an effect declaration and makes each of its operations callable by name.
+    pub fn add_effect(&mut self, effect: Effect) {
+        let name = effect.base_name();
+        let mut operations = self.effect_operation_table.write().expect("Interpreter was not able to be written to");
+        for operation in &effect.operations {
+            if let Some(other) = operations.insert(operation.name.clone(), name.clone()) {
+                if other != name {
+                    panic!("The operation {} is declared by both the effect {} and the effect {}", operation.name, other, name);
+                }
+            }
+        }
+        self.effect_table.write().expect("Interpreter was not able to be written to").insert(name, effect);
+    }
+
+    pub fn get_effect(&self, name: &str) -> Option<Effect> {
+        self.effect_table.read().expect("Unable to read interpreter").get(name).cloned()
+    }
+
+    /// This is the effect that declares the operation `name`, if any does.
+    pub fn effect_of_operation(&self, name: &str) -> Option<String> {
+        self.effect_operation_table.read().expect("Unable to read interpreter").get(name).cloned()
+    }
+
+    /// This checks that every function declares the effects it performs and that main can't leak an effect without a `@Final` operation.
+    pub fn check_effects(&self) -> Result<(), Vec<EffectError>> {
+        let functions = self.function_symbol_table.read().expect("Unable to read interpreter");
+        let effects = self.effect_table.read().expect("Unable to read interpreter");
+        let natives = self.native_function_table.read().expect("Unable to read interpreter").iter().map(|(name, native)| (name.clone(), native.effects)).collect();
+        EffectChecker::new(&functions, &natives, &effects).check()
+    }
+
+    /// This runs an effect operation with the innermost handler for it.
+    /// Other operations run their handler in place and resume with what it returns, while a `@Control` operation unwinds to its `with` block first.
+    /// Either way the handler only sees the handlers that were installed outside of its own `with` block.
+    /// Without a handler an operation runs its default body, or else unwinds all the way out to be given to the `@Final` operation of its effect.
+    fn perform_operation(&mut self, effect_name: &str, name: &str, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let effect = self.get_effect(effect_name).expect("An operation always belongs to a declared effect");
+        let operation = effect.operations.iter().find(|operation| operation.name == name).expect("An effect always has its own operations");
+        if let Some(index) = self.handlers.iter().rposition(|handler| handler.operation == name) {
+            if operation.is_control() {
+                return Err(RuntimeError::EffectUnwind(self.handlers[index].frame, name.to_string(), EffectArguments::new(arguments)));
+            }
+            let mut inner_handlers = self.handlers.split_off(index);
+            let function = inner_handlers[0].function.clone();
+            let result = self.function_caller(name, function, arguments);
+            self.handlers.append(&mut inner_handlers);
+            return result;
+        }
+        if operation.has_body() && !operation.is_control() {
+            return self.function_caller(name, operation.function.clone(), arguments);
         }
+        Err(RuntimeError::UnhandledEffect(effect_name.to_string(), name.to_string(), EffectArguments::new(arguments)))
+    }
+
+    /// This raises an exception through the `exn` effect, which is how built-in functions report errors that a script can catch.
+    pub fn throw(&mut self, message: &str) -> Result<Value, RuntimeError> {
+        let effect = self.effect_of_operation("throw-exn").expect("The exn effect is always declared");
+        self.perform_operation(&effect, "throw-exn", vec![Value::string(message)])
+    }
+
+    /// This gives an operation that escaped main to the `@Final` operation of its effect.
+    fn run_final_operation(&mut self, effect_name: &str, name: &str, arguments: EffectArguments) -> Result<Value, RuntimeError> {
+        let effect = self.get_effect(effect_name).expect("An operation always belongs to a declared effect");
+        match effect.final_operation() {
+            Some(last) if last.has_body() && last.name != name => self.function_caller(&last.name, last.function.clone(), arguments.to_vec()),
+            _ => Err(RuntimeError::UnhandledEffect(effect_name.to_string(), name.to_string(), arguments)),
+        }
+    }
+
+    /// This turns the clauses of a `with` block into handlers, which see the variables around the block like a closure would.
+    fn make_handler(&self, frame: usize, handler: &EffectHandler, local_variables: &HashMap<String, Value>) -> Handler {
+        if self.effect_of_operation(&handler.operation).is_none() {
+            panic!("Tried to handle {} but it is not an operation of any effect", handler.operation);
+        }
+        let function = Value::Function(Vec::new(), handler.args.clone(), Vec::new(), Type::Single("Any".to_string()), local_variables.clone(), (*handler.body).clone());
+        Handler { frame, operation: handler.operation.clone(), function }
     }
 
     pub fn add_type(&mut self, the_type: Type) {
@@ -194,16 +507,58 @@ impl Interpreter {
     }
     
     pub fn new_for_thread(& self) -> Interpreter {
-        Interpreter {
+        let mut interpreter = Interpreter {
             function_symbol_table: self.function_symbol_table.clone(),
+            native_function_table: self.native_function_table.clone(),
             type_class_symbol_table: self.type_class_symbol_table.clone(),
             default_symbol_table: self.default_symbol_table.clone(),
             valid_typeclasses: self.valid_typeclasses.clone(),
             valid_types: self.valid_types.clone(),
             local_global_variables: HashMap::new(),
+            thread_local_declarations: self.thread_local_declarations.clone(),
             shared_global_variables: self.shared_global_variables.clone(),
             mutable_global_variables: self.mutable_global_variables.clone(),
+            atomic_global_variables: self.atomic_global_variables.clone(),
+            lock_timeout: self.lock_timeout,
+            thread_pool: self.thread_pool.clone(),
+            task_scope: self.task_scope.clone(),
+            call_stack: Vec::new(),
+            deadlock_detector: self.deadlock_detector.clone(),
+            scheduler: self.scheduler.clone(),
+            transaction: None,
+            actors: self.actors.clone(),
+            effect_table: self.effect_table.clone(),
+            effect_operation_table: self.effect_operation_table.clone(),
+            handlers: Vec::new(),
+            next_handler_frame: 0,
+            caller_variables: HashMap::new(),
+        };
+        let declarations = self.thread_local_declarations.read().expect("Unable to read interpreter").clone();
+        for global in declarations {
+            interpreter.add_thread_local_variable(&global);
         }
+        interpreter
+    }
+
+    /// This sets how long a thread will wait for the lock of a Thread-Mutable global before giving up.
+    /// `None` means that threads will wait forever.
+    pub fn set_lock_timeout(&mut self, timeout: Option<Duration>) {
+        self.lock_timeout = timeout;
+    }
+
+    /// This turns on the lock-order tracker for `@ThreadMutable` globals.
+    /// Interpreters made for other threads after this share the same tracker.
+    pub fn set_deadlock_detection(&mut self, enabled: bool) {
+        self.deadlock_detector = enabled.then(|| Arc::new(DeadlockDetector::new()));
+    }
+
+    /// This runs the program on a deterministic scheduler with the given seed instead of on the thread pool.
+    pub fn set_deterministic_seed(&mut self, seed: Option<u64>) {
+        self.scheduler = seed.map(Scheduler::new);
+    }
+
+    pub fn scheduler(&self) -> Option<&Arc<Scheduler>> {
+        self.scheduler.as_ref()
     }
 
 
@@ -211,23 +566,114 @@ impl Interpreter {
         self.function_symbol_table.write().unwrap().insert(name.to_string(), value);
     }
 
-    pub fn set_value(&mut self, name: &str, function_variables: &mut HashMap<String, Variable>, value: Value) {
-        if self.mutable_global_variables.read().unwrap().contains_key(name) {
-            unimplemented!("Need to implement mutable global variables");
-            /*loop {
-                match self.mutable_global_variables.try_write() {
-                    TryLockResult::Err(TryLockError::WouldBlock) => {
-                        continue;
-                    }
-                    TryLockResult::Err(TryLockError::Poisoned(_)) => {
-                        panic!("Another thread panicked while holding the lock");
-                    }
-                    TryLockResult::Ok(mut guard) => {
-                        guard.insert(name.to_string(), Arc::new(Mutex::new(Variable::new(ValuePtr::new_mut(value)))));
-                        break;
-                    }
-                }
-            }*/
+    pub fn add_native_function(&mut self, name: &str, function: NativeFunction) {
+        self.native_function_table.write().expect("Interpreter was not able to be written to").insert(name.to_string(), NativeEntry::new(function, true, None));
+    }
+
+    /// This adds a built-in function that gets promises as they are instead of waiting for them first.
+    pub fn add_promise_function(&mut self, name: &str, function: NativeFunction) {
+        self.native_function_table.write().expect("Interpreter was not able to be written to").insert(name.to_string(), NativeEntry::new(function, false, None));
+    }
+
+    /// This adds a built-in typeclass function that picks which instance to call itself, like `>>=` does.
+    /// It gets promises as they are, and instances of `name` are only ever called through it.
+    pub fn add_dispatching_function(&mut self, name: &str, function: NativeFunction) {
+        let entry = NativeEntry { picks_instance: true, ..NativeEntry::new(function, false, None) };
+        self.native_function_table.write().expect("Interpreter was not able to be written to").insert(name.to_string(), entry);
+    }
+
+    /// This adds a built-in function whose arguments after the first `fixed` ones are collected into an H-List, which it gets as its last argument.
+    pub fn add_variadic_function(&mut self, name: &str, fixed: usize, function: NativeFunction) {
+        self.native_function_table.write().expect("Interpreter was not able to be written to").insert(name.to_string(), NativeEntry::new(function, true, Some(fixed)));
+    }
+
+    /// This sets the effects a built-in function performs so that the effect checker can hold its callers to them.
+    pub fn declare_native_effects(&mut self, name: &str, effects: &'static [&'static str]) {
+        match self.native_function_table.write().expect("Interpreter was not able to be written to").get_mut(name) {
+            Some(entry) => entry.effects = effects,
+            None => panic!("Tried to declare the effects of {} but it is not a built-in function", name),
+        }
+    }
+
+    /// This evaluates the initial value of a global variable and puts it into the table that matches its attributes.
+    /// Globals without a threading attribute are local to the thread.
+    pub fn add_global_variable(&mut self, global: GlobalVariable) {
+        let is_thread_local = ![Attribute::Atomic, Attribute::ThreadShared, Attribute::ThreadMutable].iter().any(|attribute| global.attributes.contains(attribute));
+        if is_thread_local {
+            self.add_thread_local_variable(&global);
+            self.thread_local_declarations.write().expect("Interpreter was not able to be written to").push(global);
+            return;
+        }
+
+        let value = self.evaluate_global(&global);
+        if global.attributes.contains(&Attribute::Atomic) {
+            let value = AtomicValue::new(value).unwrap_or_else(|| panic!("Atomic global variable {} must be an Int, UInt, or Byte", global.name));
+            self.atomic_global_variables.write().expect("Interpreter was not able to be written to").insert(global.name, value);
+        }
+        else if global.attributes.contains(&Attribute::ThreadShared) {
+            if global.mutable {
+                panic!("Thread shared global variable {} can't be mutable", global.name);
+            }
+            self.shared_global_variables.write().expect("Interpreter was not able to be written to").insert(global.name, Variable::new(value));
+        }
+        else {
+            self.mutable_global_variables.write().expect("Interpreter was not able to be written to").insert(global.name, Arc::new(GlobalMutex::new(Variable::new(value))));
+        }
+    }
+
+    fn evaluate_global(&mut self, global: &GlobalVariable) -> Value {
+        let value = self.evaluate_expression(&global.value, &mut HashMap::new()).unwrap_or_else(|error| panic!("Unable to initialize global variable {}: {}", global.name, error));
+        if let Some(ref the_type) = global.the_type {
+            if *the_type != value.get_type() {
+                panic!("Global variable {} was declared as {} but was given a value of type {}", global.name, the_type, value.get_type());
+            }
+        }
+        value
+    }
+
+    /// This runs the declaration of a thread local global for the thread that owns this interpreter.
+    fn add_thread_local_variable(&mut self, global: &GlobalVariable) {
+        let value = self.evaluate_global(global);
+        let value = if global.mutable { Value::new_ref(value) } else { value };
+        self.local_global_variables.insert(global.name.clone(), Variable::new(value));
+    }
+
+    fn is_global(&self, name: &str) -> bool {
+        self.local_global_variables.contains_key(name)
+            || self.shared_global_variables.read().unwrap().contains_key(name)
+            || self.mutable_global_variables.read().unwrap().contains_key(name)
+            || self.atomic_global_variables.read().unwrap().contains_key(name)
+    }
+
+    /// This takes the lock of a Thread-Mutable global.
+    /// It blocks until the lock is free or the lock timeout runs out, and returns `None` if there is no such global.
+    fn lock_global(&self, name: &str) -> Result<Option<GlobalGuard>, RuntimeError> {
+        let mutex = self.mutable_global_variables.read().unwrap().get(name).cloned();
+        match mutex {
+            Some(mutex) => {
+                let guard = match &self.deadlock_detector {
+                    Some(detector) => detector.lock(name, &mutex, &self.call_stack, self.lock_timeout),
+                    None => mutex.lock(self.lock_timeout),
+                };
+                guard.map(Some).map_err(|error| match error {
+                    LockError::Poisoned => RuntimeError::LockPoisoned(name.to_string()),
+                    LockError::TimedOut => RuntimeError::LockTimeout(name.to_string()),
+                    LockError::Deadlock(report) => RuntimeError::Deadlock(report),
+                })
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_value(&mut self, name: &str, function_variables: &mut HashMap<String, Value>, value: Value) -> Result<(), RuntimeError> {
+        if let Some(transaction) = self.transaction.as_mut().filter(|_| self.mutable_global_variables.read().unwrap().contains_key(name)) {
+            transaction.write(name, value);
+        }
+        else if let Some(guard) = self.lock_global(name)? {
+            guard.replace_value(value);
+        }
+        else if let Some(atomic) = self.atomic_global_variables.read().unwrap().get(name) {
+            atomic.store(&value);
         }
         else if self.shared_global_variables.read().unwrap().contains_key(name) {
             panic!("Tried to assign to a shared global variable");
@@ -245,51 +691,96 @@ impl Interpreter {
             variable.set_value(value);
         }
         else {
-            panic!("Tried to assign to a variable that doesn't exist");
+            return Err(RuntimeError::UndefinedVariable(name.to_string()));
         }
-        
+        Ok(())
+    }
+
+    /// This reads a Thread-Mutable global inside of a transaction, or gives back `None` if the name isn't one.
+    fn transactional_read(&mut self, name: &str) -> Result<Option<Value>, RuntimeError> {
+        let Some(mutex) = self.mutable_global_variables.read().unwrap().get(name).cloned() else {
+            return Ok(None);
+        };
+        if let Some(value) = self.transaction.as_ref().and_then(|transaction| transaction.written(name)) {
+            return Ok(Some(value));
+        }
+        let guard = self.lock_global(name)?.expect("Thread-Mutable globals can always be locked");
+        let value = guard.variable().get_immutable();
+        let version = mutex.version();
+        drop(guard);
+        let transaction = self.transaction.as_mut().expect("Only called inside of a transaction");
+        if !transaction.record_read(name, mutex, version) {
+            return Err(RuntimeError::TransactionConflict);
+        }
+        Ok(Some(value))
+    }
+
+    /// This takes the locks of every global in the transaction and writes them if nothing it read has changed.
+    /// It returns false if the transaction has to run again.
+    fn commit(&mut self, transaction: Transaction) -> Result<bool, RuntimeError> {
+        let mut guards = HashMap::new();
+        for name in transaction.globals() {
+            let guard = self.lock_global(&name)?.expect("Only Thread-Mutable globals are in a transaction");
+            guards.insert(name, guard);
+        }
+        if !transaction.is_consistent() {
+            return Ok(false);
+        }
+        for (name, value) in transaction.into_writes() {
+            guards[&name].replace_value(value);
+        }
+        Ok(true)
     }
 
-    pub fn get_value(&self, name: &str, function_variables: &HashMap<String, Value>) -> Option<Value> {
+    pub fn get_value(&self, name: &str, function_variables: &HashMap<String, Value>) -> Result<Option<Value>, RuntimeError> {
         if let Some(variable) = function_variables.get(name) {
-            return Some(variable.clone());
+            return Ok(Some(variable.clone()));
         }
         if let Some(variable) = self.local_global_variables.get(name) {
-            return Some(variable.get_immutable());
+            return Ok(Some(variable.get_immutable()));
         }
         if let Some(variable) = self.shared_global_variables.read().unwrap().get(name) {
-            return Some(variable.get_immutable());
-        }
-        if let Some(variable) = self.mutable_global_variables.read().unwrap().get(name) {
-            let value;
-            loop {
-                match variable.try_lock() {
-                    TryLockResult::Err(TryLockError::WouldBlock) => {
-                        continue;
-                    }
-                    TryLockResult::Err(TryLockError::Poisoned(_)) => {
-                        panic!("Another thread panicked while holding the lock");
-                    }
-                    TryLockResult::Ok(guard) => {
-                        value = guard.clone();
-                        break;
-                    }
-                }
-            }
-            return Some(value.get_immutable());
+            return Ok(Some(variable.get_immutable()));
         }
-        None
+        if let Some(guard) = self.lock_global(name)? {
+            let value = guard.variable().get_immutable();
+            return Ok(Some(value));
+        }
+        if let Some(atomic) = self.atomic_global_variables.read().unwrap().get(name) {
+            // We hand out the atomic itself so that built-ins like fetch_add can update it
+            return Ok(Some(Value::Atomic(atomic.clone())));
+        }
+        // Naming a function without calling it gives the function as a value
+        if let Some(function) = self.function_symbol_table.read().expect("Unable to read interpreter").get(name) {
+            return Ok(Some(function.clone()));
+        }
+        Ok(None)
         
     }
 
-    fn function_caller(& mut self, function_name: &str, function: Value, arguments: Vec<Value>) -> Value {
+    fn function_caller(& mut self, function_name: &str, function: Value, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
         match function {
-            Value::Function(threaded, args, effects, ret_type, variable_map, body) => {
+            Value::Function(threaded, args, _effects, ret_type, variable_map, body) => {
                 let mut variable_map = variable_map;
+                // The arguments that don't have a parameter of their own are collected for the `...` parameter
+                let arguments = if args.last().is_some_and(|(_, the_type)| the_type.as_ref().is_some_and(is_varargs_type)) {
+                    let mut arguments = collect_varargs(arguments, args.len() - 1);
+                    if let Some(Value::HList(values)) = arguments.last_mut() {
+                        for value in values.iter_mut() {
+                            *value = await_value(value.clone())?;
+                        }
+                    }
+                    arguments
+                }
+                else {
+                    arguments
+                };
                 let mut pass_by_ref = false;
-                for ((name, the_type),mut arg) in args.iter().zip(arguments.into_iter()) {
+                for ((name, the_type), arg) in args.iter().zip(arguments) {
+                    // A promise is only waited on once something needs the value inside of it
+                    let arg = if the_type.as_ref().is_some_and(is_promise_type) { arg } else { await_value(arg)? };
                     //TODO: add in ablity to bind generics to types
-                    if the_type.get_type() != arg.get_type() {
+                    if the_type.is_some() && the_type.get_type() != arg.get_type() {
                         panic!("Tried to call function {} with argument of type {} when it expected type {}", function_name, arg.get_type(), the_type.get_type());
                     }
                     if the_type.is_ref() {
@@ -300,22 +791,24 @@ impl Interpreter {
                         variable_map.insert(name.to_string(), arg.get_immutable());
                     }
                 }
-                if threaded {
+                if threaded.contains(&Attribute::ThreadSpawn) {
                     if pass_by_ref {
                         panic!("Tried to call a threaded function with a reference");
                     }
-                    let mut interpreter = self.clone();
-
-
-                    let handle = Arc::new(RwLock::new(thread::spawn(move || {
-                        return interpreter.evaluate_block(&mut variable_map, &body);
-                    })));
-
-                    return Value::create_promise(handle, ret_type.clone());
+                    // Captured variables and values inside of arguments can still hold references to this thread's mutable variables
+                    if let Some((name, _)) = variable_map.iter().find(|(_, value)| !value.is_sendable()) {
+                        panic!("Tried to move {} into threaded function {} but it holds a reference to a mutable variable", name, function_name);
+                    }
+                    let promise_type = ret_type.clone();
+                    let name = function_name.to_string();
+                    let promise = self.spawn_task(function_name, move |interpreter| {
+                        interpreter.evaluate_function_body(&name, &mut variable_map, &body, &ret_type)
+                    });
+                    return Ok(Value::create_promise(promise, promise_type));
 
                 }
 
-                return self.evaluate_block(&mut variable_map, &body);
+                self.evaluate_function_body(function_name, &mut variable_map, &body, &ret_type)
                 
 
             },
@@ -325,26 +818,136 @@ impl Interpreter {
         }
     }
 
-    pub fn call_function(&mut self, name: &str, arguments: Vec<Value>, local_variables: HashMap<String, Value>) -> Value {
+    /// This runs `work` on the thread pool with its own interpreter and returns the promise that it fulfils.
+    /// A panic inside of `work` is reported as a `ThreadPanicked` error in the name of `task_name`.
+    /// The task belongs to the innermost `scope` block so that the block waits for it.
+    pub fn spawn_task(&self, task_name: &str, work: impl FnOnce(&mut Interpreter) -> Result<Value, RuntimeError> + Send + 'static) -> Promise {
+        let (promise, task) = self.prepare_task(task_name, work);
+        match &self.scheduler {
+            Some(scheduler) => scheduler.spawn(task),
+            None => self.thread_pool.spawn(task),
+        }
+        if let Some(scope) = &self.task_scope {
+            scope.add(promise.clone());
+        }
+        promise
+    }
 
-        let function = if let Some(function) = self.function_symbol_table.read().expect("Unable to read interpreter").get(name) {
-            function.clone()
+    /// This runs `work` on a thread of its own instead of on the pool, for work that lives as long as the program like an actor.
+    /// Waiting on the pool lets a worker pick up other tasks, which would trap them behind something that never finishes.
+    /// The thread doesn't belong to any `scope` block.
+    pub fn spawn_thread(&self, thread_name: &str, work: impl FnOnce(&mut Interpreter) -> Result<Value, RuntimeError> + Send + 'static) -> Promise {
+        let (promise, task) = self.prepare_task(thread_name, work);
+        match &self.scheduler {
+            Some(scheduler) => scheduler.spawn(task),
+            None => {
+                thread::Builder::new().name(thread_name.to_string()).spawn(task).expect("Unable to start a thread");
+            },
         }
-        else if let Some(function) = self.type_class_symbol_table.read().expect("Unable to read interpreter").get(name).unwrap_or(&HashMap::new()).get(&arguments[0].get_type()) {
-            function.clone()
+        promise
+    }
 
+    /// This gives `work` an interpreter for its thread and catches its panics so that the promise is always fulfilled.
+    fn prepare_task(&self, task_name: &str, work: impl FnOnce(&mut Interpreter) -> Result<Value, RuntimeError> + Send + 'static) -> (Promise, impl FnOnce() + Send + 'static) {
+        let mut interpreter = self.new_for_thread();
+        let promise = Promise::new();
+        let worker_promise = promise.clone();
+        let task_name = task_name.to_string();
+
+        let task = move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| work(&mut interpreter)));
+            let result = result.unwrap_or_else(|payload| Err(RuntimeError::ThreadPanicked(task_name, panic_message(payload))));
+            worker_promise.fulfil(result);
+        };
+        (promise, task)
+    }
+
+    pub fn add_actor(&self, actor: Actor, promise: Promise) {
+        self.actors.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push((actor, promise));
+    }
+
+    /// This stops every actor once their mailbox is empty and waits for it to finish.
+    /// The newest actor goes first so that a supervisor, which is started before what it watches, still hears about failures while the others finish.
+    fn stop_actors(&self) {
+        loop {
+            let newest = self.actors.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).pop();
+            let Some((actor, promise)) = newest else {
+                break;
+            };
+            actor.stop();
+            // A failed actor has already told its supervisors
+            let _ = await_value(Value::create_promise(promise, Type::Unit));
+        }
+    }
+
+    pub fn worker_count(&self) -> usize {
+        self.thread_pool.max_workers()
+    }
+
+    /// This is the pool that `@ThreadSpawn` functions run on, which loading a program uses to parse its modules as well.
+    pub fn thread_pool(&self) -> &ThreadPool {
+        &self.thread_pool
+    }
+
+    /// This calls a function that was passed around as a value, like the function given to a built-in such as `par_map`.
+    pub fn call_function_value(&mut self, function_name: &str, function: &Value, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        await_value(self.function_caller(function_name, function.clone(), arguments)?)
+    }
+
+    pub fn call_function(&mut self, name: &str, arguments: Vec<Value>, local_variables: HashMap<String, Value>) -> Result<Value, RuntimeError> {
+
+        // Local variables shadow everything, and functions declared in the program shadow effect operations and everything built in
+        if let Some(function) = self.check_if_function(name, &local_variables) {
+            return self.function_caller(name, function, arguments);
         }
-        else if let Some(function) = self.default_symbol_table.read().expect("Unable to read interpreter").get(name) {
-            function.clone()
+        let function = self.function_symbol_table.read().expect("Unable to read interpreter").get(name).cloned();
+        if let Some(function) = function {
+            return self.function_caller(name, function, arguments);
         }
-        else if let Some(function) = self.check_if_function(name, &local_variables) {
-            function
+        if let Some(effect) = self.effect_of_operation(name) {
+            return self.perform_operation(&effect, name, arguments);
         }
-        else {
-            panic!("Either tried to call a function that doesn't exist or tried to call something that isn't a function: {}", name);
-        };
-        return self.function_caller(name, function, arguments);
 
+        // An instance for the type of the first argument is picked over the built-in version of a typeclass function,
+        // unless the built-in version is the one that picks the instance
+        let native_function = self.native_function_table.read().expect("Unable to read interpreter").get(name).copied();
+        if !native_function.is_some_and(|native| native.picks_instance) {
+            let instance = arguments.first().and_then(|argument| self.typeclass_function(name, &argument.get_type()));
+            if let Some(function) = instance {
+                return self.function_caller(name, function, arguments);
+            }
+        }
+        if let Some(native_function) = native_function {
+            let outer_variables = std::mem::replace(&mut self.caller_variables, local_variables);
+            let result = self.call_native(native_function, arguments);
+            self.caller_variables = outer_variables;
+            return result;
+        }
+
+        let function = self.default_symbol_table.read().expect("Unable to read interpreter").get(name).cloned();
+        match function {
+            Some(function) => self.function_caller(name, function, arguments),
+            None => panic!("Either tried to call a function that doesn't exist or tried to call something that isn't a function: {}", name),
+        }
+    }
+
+    fn call_native(&mut self, native: NativeEntry, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        if !native.awaits_arguments {
+            return (native.function)(self, arguments);
+        }
+        let mut values = Vec::new();
+        for argument in arguments {
+            values.push(await_value(argument)?);
+        }
+        if let Some(fixed) = native.fixed {
+            values = collect_varargs(values, fixed);
+        }
+        (native.function)(self, values)
+    }
+
+    /// This looks up a variable where the running built-in function was called, like `format` does for `{name}`.
+    pub fn caller_value(&self, name: &str) -> Result<Option<Value>, RuntimeError> {
+        self.get_value(name, &self.caller_variables)
     }
 
     fn check_if_function(&self, name: &str, local_variables: &HashMap<String, Value>) -> Option<Value> {
@@ -356,17 +959,698 @@ impl Interpreter {
         }
     }
 
-    fn evaluate_block(&mut self, function_variables: &mut HashMap<String, Value>, block: &String) -> Value {
-        unimplemented!("Interpretation of functions is not yet implemented");
+    pub fn evaluate_expression(&mut self, expression: &Expression, local_variables: &mut HashMap<String, Value>) -> Result<Value, RuntimeError> {
+        match expression {
+            Expression::Literal(literal) => Ok(literal.to_value()),
+            Expression::Variable(name) => {
+                if self.transaction.is_some() && !local_variables.contains_key(name) {
+                    if let Some(value) = self.transactional_read(name)? {
+                        return Ok(value);
+                    }
+                }
+                match self.get_value(name, local_variables)? {
+                    Some(atomic @ Value::Atomic(_)) => Ok(atomic),
+                    Some(value) => Ok(value.get_immutable()),
+                    None => Err(RuntimeError::UndefinedVariable(name.clone())),
+                }
+            },
+            Expression::Call(name, arguments) => {
+                let mut values = Vec::new();
+                for argument in arguments {
+                    match argument {
+                        Expression::Spread(list) => match await_value(self.evaluate_expression(list, local_variables)?)? {
+                            Value::HList(elements) => values.extend(elements),
+                            other => panic!("Tried to spread a value of type {} into a call to {} but only an H-List can be spread", other.get_type(), name),
+                        },
+                        argument => values.push(self.evaluate_expression(argument, local_variables)?),
+                    }
+                }
+                self.call_function(name, values, local_variables.clone())
+            },
+            Expression::List(elements) => {
+                let mut values = Vec::new();
+                for element in elements {
+                    values.push(self.evaluate_expression(element, local_variables)?);
+                }
+                let element_type = values.first().map(|element| element.get_type()).unwrap_or(Type::Single("Any".to_string()));
+                if values.iter().any(|element| element.get_type() != element_type) {
+                    panic!("Tried to create a list with elements of different types");
+                }
+                Ok(Value::List(Arc::new(values), element_type))
+            },
+            Expression::Tuple(elements) => {
+                let mut values = Vec::new();
+                for element in elements {
+                    values.push(self.evaluate_expression(element, local_variables)?);
+                }
+                Ok(Value::Tuple(values))
+            },
+            Expression::Assign { name, mutable, value } => {
+                let value = self.evaluate_expression(value, local_variables)?;
+                if !local_variables.contains_key(name) && self.is_global(name) {
+                    self.set_value(name, local_variables, value)?;
+                }
+                else {
+                    match local_variables.get_mut(name) {
+                        Some(variable) if variable.is_mutable() && !mutable => variable.set_value(value),
+                        // Immutable variables can't be changed but they can be shadowed
+                        _ => {
+                            let value = if *mutable { Value::new_ref(value) } else { value };
+                            local_variables.insert(name.clone(), value);
+                        },
+                    }
+                }
+                Ok(Value::Tuple(Vec::new()))
+            },
+            Expression::Block(statements) => {
+                // Variables declared in a block don't escape it, but mutable variables from outside share their reference with the block
+                // An IO action used as a statement would otherwise be thrown away so it is chained in front of what the block gives back
+                let mut block_variables = local_variables.clone();
+                let mut result = Value::Tuple(Vec::new());
+                let mut actions = Vec::new();
+                for (index, statement) in statements.iter().enumerate() {
+                    result = self.evaluate_expression(statement, &mut block_variables)?;
+                    if let Value::IO(action, _) = &result {
+                        if index + 1 < statements.len() {
+                            actions.push(action.clone());
+                        }
+                    }
+                }
+                if actions.is_empty() {
+                    return Ok(result);
+                }
+                let (last, the_type) = match result {
+                    Value::IO(action, the_type) => (action, the_type),
+                    value => {
+                        let the_type = value.get_type();
+                        (Arc::new(IoAction::Pure(value)), the_type)
+                    },
+                };
+                let action = actions.into_iter().rev().fold(last, |next, action| Arc::new(IoAction::Then(action, next)));
+                Ok(Value::IO(action, the_type))
+            },
+            Expression::Lock(names, body) => {
+                if self.transaction.is_some() {
+                    panic!("Tried to lock {} inside of an atomically block", names.join(", "));
+                }
+                // The locks are always taken in the same order so that two lock blocks over the same globals can't deadlock each other
+                let mut names = names.clone();
+                names.sort();
+                names.dedup();
+                let mut guards = Vec::new();
+                for name in names.iter() {
+                    match self.lock_global(name)? {
+                        Some(guard) => guards.push(guard),
+                        None => return Err(RuntimeError::NotLockable(name.clone())),
+                    }
+                }
+                let result = self.evaluate_expression(body, local_variables);
+                drop(guards);
+                result
+            },
+            Expression::Atomically(body) => {
+                // A block inside of a transaction is just part of that transaction
+                if self.transaction.is_some() {
+                    return self.evaluate_expression(body, local_variables);
+                }
+                loop {
+                    self.transaction = Some(Transaction::new());
+                    let result = self.evaluate_expression(body, local_variables).and_then(await_value);
+                    let transaction = self.transaction.take().expect("Transaction was removed while it was running");
+                    match result {
+                        Err(RuntimeError::TransactionConflict) => {},
+                        Err(RuntimeError::TransactionRetry) => transaction.wait_for_change(),
+                        Err(error) => return Err(error),
+                        Ok(value) => {
+                            if self.commit(transaction)? {
+                                return Ok(value);
+                            }
+                        },
+                    }
+                }
+            },
+            Expression::Retry => Err(RuntimeError::TransactionRetry),
+            Expression::With(clauses, body) => {
+                let frame = self.next_handler_frame;
+                self.next_handler_frame += 1;
+                let handlers: Vec<Handler> = clauses.iter().map(|clause| self.make_handler(frame, clause, local_variables)).collect();
+                let depth = self.handlers.len();
+                self.handlers.extend(handlers.iter().cloned());
+                let result = self.evaluate_expression(body, local_variables).and_then(await_value);
+                self.handlers.truncate(depth);
+                match result {
+                    Err(RuntimeError::EffectUnwind(target, name, arguments)) if target == frame => {
+                        let handler = handlers.into_iter().find(|handler| handler.operation == name).expect("A with block only unwinds for its own operations");
+                        self.function_caller(&name, handler.function, arguments.to_vec()).and_then(await_value)
+                    },
+                    result => result,
+                }
+            },
+            Expression::Spread(_) => panic!("An H-List can only be spread into the arguments of a call"),
+            Expression::Lambda(args, body) => Ok(Value::Function(Vec::new(), args.clone(), Vec::new(), Type::Single("Any".to_string()), local_variables.clone(), (**body).clone())),
+            Expression::Located(line, body) => match at_line(*line, || self.evaluate_expression(body, local_variables))? {
+                // The action runs later, so it has to carry the line with it
+                Value::IO(action, the_type) => Ok(Value::IO(Arc::new(IoAction::Located(*line, action)), the_type)),
+                value => Ok(value),
+            },
+            Expression::Scope(body) => {
+                let outer_scope = self.task_scope.replace(TaskScope::new());
+                let result = self.evaluate_expression(body, local_variables);
+                let scope = std::mem::replace(&mut self.task_scope, outer_scope).expect("Scope was removed while it was running");
+                // Tasks can spawn more tasks into the scope while we wait so we keep going until there are none left
+                let mut first_error = None;
+                loop {
+                    let tasks = scope.take_tasks();
+                    if tasks.is_empty() {
+                        break;
+                    }
+                    for task in tasks {
+                        if let Err(error) = await_value(Value::create_promise(task, Type::Single("a".to_string()))) {
+                            first_error.get_or_insert(error);
+                        }
+                    }
+                }
+                match (result, first_error) {
+                    (Err(error), _) | (Ok(_), Some(error)) => Err(error),
+                    (Ok(value), None) => await_value(value),
+                }
+            },
+            Expression::For { variable, iterable, body } => {
+                let iterable = await_value(self.evaluate_expression(iterable, local_variables)?)?;
+                let mut loop_variables = local_variables.clone();
+                match iterable {
+                    Value::HList(values) => {
+                        for value in values.iter() {
+                            loop_variables.insert(variable.clone(), value.clone());
+                            self.evaluate_expression(body, &mut loop_variables)?;
+                        }
+                    },
+                    Value::List(values, _) => {
+                        for value in values.iter() {
+                            loop_variables.insert(variable.clone(), value.clone());
+                            self.evaluate_expression(body, &mut loop_variables)?;
+                        }
+                    },
+                    // A channel is read until it is closed and empty
+                    Value::Channel(channel) => {
+                        loop {
+                            match receive(&channel) {
+                                Ok(value) => {
+                                    loop_variables.insert(variable.clone(), value);
+                                    self.evaluate_expression(body, &mut loop_variables)?;
+                                },
+                                Err(RuntimeError::ChannelClosed) => break,
+                                Err(error) => return Err(error),
+                            }
+                        }
+                    },
+                    other => panic!("Tried to iterate over a value of type {}", other.get_type()),
+                }
+                Ok(Value::Tuple(Vec::new()))
+            },
+        }
+    }
+
+    fn evaluate_function_body(&mut self, function_name: &str, function_variables: &mut HashMap<String, Value>, body: &Expression, return_type: &Type) -> Result<Value, RuntimeError> {
+        self.call_stack.push(function_name.to_string());
+        // A function that doesn't give back IO runs what its statements built, which is how functions written without monads do IO
+        let value = self.evaluate_expression(body, function_variables).and_then(await_value).and_then(|value| match value {
+            Value::IO(ref action, _) if value.get_type() != *return_type => self.run_io(action),
+            value => Ok(value),
+        });
+        self.call_stack.pop();
+        let value = value?;
+        if value.get_type() != *return_type {
+            panic!("Function {} returned a value of type {} when it should return {}", function_name, value.get_type(), return_type);
+        }
+        Ok(value)
     }
 
-    pub fn start_program(&mut self) {
-        self.function_symbol_table.read().expect("Unable to read interpreter").get("main").expect("No main function");
-        self.call_function("main", vec![], HashMap::new());
+    /// This performs an IO action and gives back what it made.
+    pub fn run_io(&mut self, action: &IoAction) -> Result<Value, RuntimeError> {
+        match action {
+            IoAction::Pure(value) => Ok(value.clone()),
+            IoAction::Native(function, arguments) => function(self, arguments.clone()),
+            IoAction::Bind(action, function) => {
+                let value = self.run_io(action)?;
+                match self.call_function_value(">>=", function, vec![value])? {
+                    Value::IO(next, _) => self.run_io(&next),
+                    other => panic!("The function given to >>= for IO has to give back IO but gave back a value of type {}", other.get_type()),
+                }
+            },
+            IoAction::Then(first, second) => {
+                self.run_io(first)?;
+                self.run_io(second)
+            },
+            IoAction::Located(line, action) => at_line(*line, || self.run_io(action)),
+        }
+    }
 
+    /// This runs main, and if main gives back an IO action then that action is the program.
+    fn run_main(&mut self) -> Result<Value, RuntimeError> {
+        let result = self.call_function("main", vec![], HashMap::new()).and_then(await_value).and_then(|value| match value {
+            Value::IO(action, _) => self.run_io(&action),
+            value => Ok(value),
+        });
+        let result = match result {
+            Err(error) => self.finish_escaped_effect(error),
+            result => result,
+        };
+        self.stop_actors();
+        result
+    }
+
+    /// This gives an operation that escaped main to its `@Final` operation, which still knows the do block statements it came out of.
+    fn finish_escaped_effect(&mut self, error: RuntimeError) -> Result<Value, RuntimeError> {
+        match error {
+            RuntimeError::UnhandledEffect(effect, name, arguments) => self.run_final_operation(&effect, &name, arguments),
+            RuntimeError::Located(line, error) => at_line(line, || self.finish_escaped_effect(*error)),
+            error => Err(error),
+        }
+    }
+
+    pub fn start_program(&mut self) -> Result<(), RuntimeError> {
+        self.function_symbol_table.read().expect("Unable to read interpreter").get("main").expect("No main function");
+        self.check_effects().map_err(RuntimeError::Effects)?;
+        let result = match self.scheduler.clone() {
+            Some(scheduler) => scheduler.run_main(|| self.run_main()),
+            None => self.run_main(),
+        };
+        // Threads that main didn't wait for still get to finish before the program ends
+        self.thread_pool.shutdown();
+        result.map(|_| ())
     }
         
 
     
 }
 
+
+
+#[cfg(test)]
+mod interpreter_tests {
+    use super::*;
+    use crate::parser::file_parser::file_parser_helper;
+    use crate::parser::expression_parser::expression_parser;
+    use crate::parser::lexer::lexer;
+    use chumsky::Parser;
+    use std::thread;
+
+    fn parse_expression(input: &str) -> Expression {
+        let tokens = lexer(input).expect("Something went wrong lexing the expression");
+        expression_parser().parse(tokens).expect("Something went wrong parsing the expression")
+    }
+
+    fn add_one(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        match arguments.first() {
+            Some(Value::UInt(value)) => Ok(Value::UInt(value + 1)),
+            _ => panic!("add_one expects a UInt"),
+        }
+    }
+
+    #[test]
+    fn test_lock_block_across_threads() {
+        let mut interpreter = Interpreter::new();
+        interpreter.add_native_function("add_one", add_one);
+        file_parser_helper("@ThreadMutable\ncounter := 0u;", &mut interpreter);
+        let increment = parse_expression("lock(counter) { counter = add_one(counter); }");
+
+        let workers: Vec<_> = (0..8).map(|_| {
+            let mut interpreter = interpreter.new_for_thread();
+            let increment = increment.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    interpreter.evaluate_expression(&increment, &mut HashMap::new()).unwrap();
+                }
+            })
+        }).collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert!(matches!(interpreter.get_value("counter", &HashMap::new()).unwrap(), Some(Value::UInt(800))), "Increments were lost");
+    }
+
+    #[test]
+    fn test_thread_local_globals() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("total := 1;\nbase = 2;\npair = [base, base];", &mut interpreter);
+        interpreter.set_value("total", &mut HashMap::new(), Value::Int(5)).unwrap();
+
+        let mut thread_interpreter = interpreter.new_for_thread();
+        let (seen, pair) = thread::spawn(move || {
+            let seen = thread_interpreter.get_value("total", &HashMap::new()).unwrap();
+            thread_interpreter.set_value("total", &mut HashMap::new(), Value::Int(9)).unwrap();
+            (seen, thread_interpreter.get_value("pair", &HashMap::new()).unwrap())
+        }).join().unwrap();
+
+        assert!(matches!(seen, Some(Value::Int(1))), "Thread did not get a fresh copy of total");
+        assert!(matches!(pair, Some(Value::List(ref values, _)) if values.len() == 2), "pair was not initialized on the thread");
+        assert!(matches!(interpreter.get_value("total", &HashMap::new()).unwrap(), Some(Value::Int(5))), "Thread changed the copy of another thread");
+    }
+
+    #[test]
+    fn test_values_are_thread_safe() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Value>();
+        assert_send_sync::<Interpreter>();
+    }
+
+    #[test]
+    fn test_references_are_not_sendable() {
+        let reference = Value::new_ref(Value::Int(1));
+        let nested = Value::Tuple(vec![Value::Int(2), Value::List(Arc::new(vec![reference.clone()]), Type::Single("Int".to_string()))]);
+
+        assert!(Value::Int(1).is_sendable(), "Numbers should be sendable");
+        assert!(!reference.is_sendable(), "References should not be sendable");
+        assert!(!nested.is_sendable(), "A reference inside of a list should not be sendable");
+    }
+
+    #[test]
+    #[should_panic(expected = "Tried to move counter into threaded function worker")]
+    fn test_spawn_with_captured_reference() {
+        let mut interpreter = Interpreter::new();
+        let mut captured = HashMap::new();
+        captured.insert("counter".to_string(), Value::new_ref(Value::Int(0)));
+        interpreter.add_function("worker", Value::Function(vec![Attribute::ThreadSpawn], vec![], vec![], Type::Single("Int".to_string()), captured, Expression::Block(Vec::new())));
+
+        let _ = interpreter.call_function("worker", vec![], HashMap::new());
+    }
+
+    #[test]
+    fn test_spawned_function_is_awaited() {
+        let mut interpreter = Interpreter::new();
+        interpreter.add_native_function("add_one", add_one);
+        file_parser_helper("@ThreadSpawn\nfn work(n: UInt) -> UInt { add_one(n) }\nfn twice(p: (Promise UInt)) -> (UInt, UInt) { (add_one(p), add_one(p)) }", &mut interpreter);
+
+        let promise = interpreter.evaluate_expression(&parse_expression("work(1u)"), &mut HashMap::new()).unwrap();
+        assert!(matches!(promise, Value::Promise(_, _)), "ThreadSpawn function did not return a promise");
+
+        let result = interpreter.evaluate_expression(&parse_expression("{ p = work(1u); (add_one(p), twice(p)) }"), &mut HashMap::new()).unwrap();
+
+        match result {
+            Value::Tuple(values) => {
+                assert!(matches!(values[0], Value::UInt(3)), "Promise was not awaited");
+                assert!(matches!(values[1], Value::Tuple(ref pair) if matches!(pair[..], [Value::UInt(3), Value::UInt(3)])), "Promise could not be read twice");
+            },
+            _ => panic!("Result is not a tuple"),
+        }
+    }
+
+    #[test]
+    fn test_spawned_function_panic() {
+        let mut interpreter = Interpreter::new();
+        interpreter.add_native_function("add_one", add_one);
+        file_parser_helper("@ThreadSpawn\nfn boom() -> UInt { missing() }", &mut interpreter);
+
+        let promise = interpreter.evaluate_expression(&parse_expression("boom()"), &mut HashMap::new()).unwrap();
+        let result = interpreter.call_function("add_one", vec![promise], HashMap::new());
+
+        assert!(matches!(result, Err(RuntimeError::ThreadPanicked(ref function, _)) if function == "boom"), "Worker panic was not turned into a runtime error");
+    }
+
+    #[test]
+    fn test_nested_spawn_on_one_worker() {
+        let mut interpreter = Interpreter::with_worker_count(1);
+        interpreter.add_native_function("add_one", add_one);
+        file_parser_helper("@ThreadSpawn\nfn inner(n: UInt) -> UInt { add_one(n) }\n@ThreadSpawn\nfn outer(n: UInt) -> UInt { add_one(inner(n)) }", &mut interpreter);
+
+        let result = interpreter.evaluate_expression(&parse_expression("add_one(outer(1u))"), &mut HashMap::new()).unwrap();
+
+        assert!(matches!(result, Value::UInt(4)), "Worker did not run the task it was waiting on");
+    }
+
+    #[test]
+    fn test_lock_holder_does_not_run_other_tasks() {
+        let mut interpreter = Interpreter::with_worker_count(1);
+        file_parser_helper("@ThreadMutable\ncounter := 0u;\n@ThreadShared\nstarted = channel();\n@ThreadShared\nresume = channel();\n\
+                            @ThreadSpawn\nfn outer() -> UInt { lock(counter) { counter = 1u; send(started, 0u); recv(resume); counter } }\n\
+                            @ThreadSpawn\nfn inner() -> UInt { lock(counter) { counter = 99u; counter } }\n\
+                            fn main() -> (List UInt) { held = outer(); recv(started); waiting = inner(); timeout(waiting, 100u); send(resume, 0u); all([held, waiting]) }", &mut interpreter);
+
+        let result = interpreter.call_function("main", vec![], HashMap::new());
+
+        assert!(matches!(result, Ok(Value::List(ref values, _)) if matches!(values[..], [Value::UInt(1), Value::UInt(99)])), "Another task got into the lock while outer held it: {:?}", result);
+    }
+
+    #[test]
+    fn test_start_program_joins_workers() {
+        let mut interpreter = Interpreter::with_worker_count(2);
+        file_parser_helper("@Atomic\ncounter := 0u;\n@ThreadSpawn\nfn bump() -> UInt { fetch_add(counter, 1u) }\nfn main() -> UInt { bump(); bump(); bump(); 0u }", &mut interpreter);
+
+        interpreter.start_program().unwrap();
+
+        assert!(matches!(interpreter.get_value("counter", &HashMap::new()).unwrap().unwrap().get_immutable(), Value::UInt(3)), "Program ended before its threads finished");
+    }
+
+    #[test]
+    fn test_block_scope() {
+        let mut interpreter = Interpreter::new();
+        let mut locals = HashMap::new();
+
+        let result = interpreter.evaluate_expression(&parse_expression("{ x := 1; y = 2; { x = y; z = 3; } x }"), &mut locals).unwrap();
+
+        assert!(matches!(result, Value::Int(2)), "Block did not write through the mutable variable");
+        assert!(locals.is_empty(), "Variables escaped the block");
+    }
+
+    #[test]
+    fn test_lock_not_mutable_global() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("total := 1;", &mut interpreter);
+
+        let result = interpreter.evaluate_expression(&parse_expression("lock(total) { total }"), &mut HashMap::new());
+
+        assert_eq!(result.err(), Some(RuntimeError::NotLockable("total".to_string())));
+    }
+
+    #[test]
+    fn test_poisoned_lock_is_runtime_error() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("@ThreadMutable\ncounter := 0u;", &mut interpreter);
+
+        let mut thread_interpreter = interpreter.new_for_thread();
+        let result = thread::spawn(move || {
+            thread_interpreter.evaluate_expression(&parse_expression("lock(counter) { missing(counter) }"), &mut HashMap::new())
+        }).join();
+        assert!(result.is_err(), "Calling a missing function should panic");
+
+        let result = interpreter.evaluate_expression(&parse_expression("counter"), &mut HashMap::new());
+
+        assert_eq!(result.err(), Some(RuntimeError::LockPoisoned("counter".to_string())));
+    }
+
+    #[test]
+    fn test_lock_timeout() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("@ThreadMutable\ncounter := 0u;", &mut interpreter);
+        interpreter.set_lock_timeout(Some(Duration::from_millis(20)));
+
+        let guard = interpreter.lock_global("counter").unwrap().unwrap();
+        let mut thread_interpreter = interpreter.new_for_thread();
+        let result = thread::spawn(move || {
+            thread_interpreter.evaluate_expression(&parse_expression("counter"), &mut HashMap::new())
+        }).join().unwrap();
+        drop(guard);
+
+        assert_eq!(result.err(), Some(RuntimeError::LockTimeout("counter".to_string())));
+    }
+
+    #[test]
+    fn test_deadlock_is_reported() {
+        let mut interpreter = Interpreter::with_worker_count(2);
+        interpreter.set_deadlock_detection(true);
+        // Each side waits until the other holds its first lock, so both always end up waiting for the other
+        file_parser_helper("@ThreadMutable\na := 0u;\n@ThreadMutable\nb := 0u;\n\
+                            @ThreadShared\nhas_a = channel();\n@ThreadShared\nhas_b = channel();\n\
+                            fn give(n: UInt) -> UInt { lock(a) { send(has_a, 0u); recv(has_b); b = n; n } }\n\
+                            fn take(n: UInt) -> UInt { lock(b) { send(has_b, 0u); recv(has_a); a = n; n } }\n\
+                            @ThreadSpawn\nfn left() -> UInt { give(1u) }\n\
+                            @ThreadSpawn\nfn right() -> UInt { take(2u) }\n\
+                            fn main() -> UInt { scope { left(); right(); } 0u }", &mut interpreter);
+
+        let result = interpreter.call_function("main", vec![], HashMap::new());
+
+        let Err(RuntimeError::Deadlock(report)) = result else { panic!("Expected a deadlock but got {:?}", result) };
+        let mut globals: Vec<_> = report.threads.iter().map(|thread| thread.waiting_for.clone()).collect();
+        globals.sort();
+        assert_eq!(globals, vec!["a", "b"], "Report did not name both globals");
+        let mut frames: Vec<_> = report.threads.iter().map(|thread| thread.frames.clone()).collect();
+        frames.sort();
+        assert_eq!(frames, vec![vec!["left", "give"], vec!["right", "take"]], "Report did not have the script stack frames");
+    }
+
+    fn is_positive(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        match arguments.as_slice() {
+            [Value::UInt(n)] => Ok(Value::bool(*n > 0)),
+            _ => panic!("is_positive expects a UInt"),
+        }
+    }
+
+    #[test]
+    fn test_atomically_updates_globals_together() {
+        let mut interpreter = Interpreter::new();
+        interpreter.add_native_function("add_one", add_one);
+        file_parser_helper("@ThreadMutable\nsent := 0u;\n@ThreadMutable\nreceived := 0u;", &mut interpreter);
+        let transfer = parse_expression("atomically { sent = add_one(sent); received = add_one(received); }");
+
+        let workers: Vec<_> = (0..8).map(|_| {
+            let mut interpreter = interpreter.new_for_thread();
+            let transfer = transfer.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    interpreter.evaluate_expression(&transfer, &mut HashMap::new()).unwrap();
+                }
+            })
+        }).collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert!(matches!(interpreter.get_value("sent", &HashMap::new()).unwrap(), Some(Value::UInt(800))), "Transactions lost updates to sent");
+        assert!(matches!(interpreter.get_value("received", &HashMap::new()).unwrap(), Some(Value::UInt(800))), "Transactions lost updates to received");
+    }
+
+    // This is set once `is_ready` has seen that the global isn't ready yet
+    static SEEN_NOT_READY: (Mutex<bool>, std::sync::Condvar) = (Mutex::new(false), std::sync::Condvar::new());
+
+    fn is_ready(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        if matches!(arguments.as_slice(), [Value::UInt(0)]) {
+            *SEEN_NOT_READY.0.lock().unwrap() = true;
+            SEEN_NOT_READY.1.notify_all();
+        }
+        is_positive(interpreter, arguments)
+    }
+
+    #[test]
+    fn test_check_waits_for_a_write() {
+        let mut interpreter = Interpreter::new();
+        interpreter.add_native_function("add_one", add_one);
+        interpreter.add_native_function("is_ready", is_ready);
+        file_parser_helper("@ThreadMutable\nready := 0u;", &mut interpreter);
+        let wait = parse_expression("atomically { check(is_ready(ready)); ready }");
+
+        let mut waiter_interpreter = interpreter.new_for_thread();
+        let waiter = thread::spawn(move || waiter_interpreter.evaluate_expression(&wait, &mut HashMap::new()));
+        // The write only happens once the transaction has read 0, so it can only give back 1 by waiting for the write
+        let mut seen = SEEN_NOT_READY.0.lock().unwrap();
+        while !*seen {
+            seen = SEEN_NOT_READY.1.wait(seen).unwrap();
+        }
+        drop(seen);
+        interpreter.evaluate_expression(&parse_expression("ready = add_one(ready)"), &mut HashMap::new()).unwrap();
+
+        assert!(matches!(waiter.join().unwrap(), Ok(Value::UInt(1))), "Transaction did not see the write");
+    }
+
+    #[test]
+    fn test_retry_outside_of_transaction() {
+        let mut interpreter = Interpreter::new();
+
+        let result = interpreter.evaluate_expression(&parse_expression("retry"), &mut HashMap::new());
+
+        assert_eq!(result.err(), Some(RuntimeError::TransactionRetry));
+    }
+
+    #[test]
+    fn test_effect_operations_are_functions() {
+        let mut interpreter = Interpreter::new();
+        interpreter.add_native_function("add_one", add_one);
+        file_parser_helper("effect exn { @Control fn throw-exn(UInt) -> a, @Final fn uncaught-exn(e: UInt) -> UInt { add_one(e) } }\n\
+                            effect tick { fn next(n: UInt) -> UInt { add_one(n) } }\n\
+                            effect abort { @Control fn stop() -> a }", &mut interpreter);
+
+        assert_eq!(interpreter.effect_of_operation("throw-exn").as_deref(), Some("exn"));
+        let next = interpreter.call_function("next", vec![Value::UInt(1)], HashMap::new());
+        assert!(matches!(next, Ok(Value::UInt(2))), "Operation with a body did not run it: {:?}", next);
+        let thrown = interpreter.call_function("throw-exn", vec![Value::UInt(4)], HashMap::new());
+        assert!(matches!(thrown, Err(RuntimeError::UnhandledEffect(ref effect, ref name, _)) if effect == "exn" && name == "throw-exn"), "Unhandled operation did not unwind: {:?}", thrown);
+        let result = interpreter.run_final_operation("exn", "throw-exn", EffectArguments::new(vec![Value::UInt(4)]));
+        assert!(matches!(result, Ok(Value::UInt(5))), "Escaped operation did not reach the final operation: {:?}", result);
+        let stopped = interpreter.run_final_operation("abort", "stop", EffectArguments::new(Vec::new()));
+        assert!(matches!(stopped, Err(RuntimeError::UnhandledEffect(ref effect, ..)) if effect == "abort"), "Effect without a final operation was handled: {:?}", stopped);
+    }
+
+    #[test]
+    fn test_with_handlers() {
+        let mut interpreter = Interpreter::new();
+        interpreter.add_native_function("add_one", add_one);
+        file_parser_helper("effect ask { fn ask() -> UInt }\n\
+                            effect fail { @Control fn fail(UInt) -> a }\n\
+                            fn asking() ask -> UInt { add_one(ask()) }\n\
+                            fn failing() fail -> UInt { add_one(fail(1u)) }", &mut interpreter);
+
+        let resumed = interpreter.evaluate_expression(&parse_expression("with fn ask() { 41u } { asking() }"), &mut HashMap::new());
+        assert!(matches!(resumed, Ok(Value::UInt(42))), "Handler did not resume the operation: {:?}", resumed);
+        let unwound = interpreter.evaluate_expression(&parse_expression("with fn fail(n) { add_one(add_one(n)) } { failing() }"), &mut HashMap::new());
+        assert!(matches!(unwound, Ok(Value::UInt(3))), "Control operation did not unwind to the with block: {:?}", unwound);
+        let nested = interpreter.evaluate_expression(&parse_expression("with fn ask() { 1u } { with fn ask() { add_one(ask()) } { ask() } }"), &mut HashMap::new());
+        assert!(matches!(nested, Ok(Value::UInt(2))), "Handler did not use the outer handler: {:?}", nested);
+        assert!(interpreter.handlers.is_empty(), "Handlers were left installed");
+    }
+
+    #[test]
+    fn test_escaped_effect_runs_final() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("@Atomic\nseen := 0u;\n\
+                            effect fail { @Control fn fail(UInt) -> a, @Final fn uncaught(n: UInt) -> () { store(seen, n); } }\n\
+                            fn main() fail -> () { fail(7u); }", &mut interpreter);
+
+        interpreter.start_program().unwrap();
+
+        match interpreter.get_value("seen", &HashMap::new()).unwrap() {
+            Some(Value::Atomic(seen)) => assert!(matches!(seen.load(), Value::UInt(7)), "Final operation did not run"),
+            other => panic!("seen was {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_locals_and_instances_shadow_builtins() {
+        let mut interpreter = Interpreter::new();
+        interpreter.add_native_function("add_one", add_one);
+        file_parser_helper("fn increment(x: UInt) -> UInt { add_one(x) }\n\
+                            fn apply(send, x: UInt) -> UInt { send(x) }\n\
+                            fn loud(n: UInt) -> String { \"loud\" }\n\
+                            fn shown() -> String { show(1u) }", &mut interpreter);
+        let loud = interpreter.get_value("loud", &HashMap::new()).unwrap().unwrap();
+        let show_class = Type::TypeList{name: Box::new(Type::Single("Show".to_string())), parameters: vec![Type::Single("a".to_string())]};
+        interpreter.add_typeclass_instance(show_class, Type::Single("UInt".to_string()), vec![("show".to_string(), loud)]);
+
+        let increment = interpreter.get_value("increment", &HashMap::new()).unwrap().unwrap();
+        let applied = interpreter.call_function("apply", vec![increment, Value::UInt(1)], HashMap::new());
+        let shown = interpreter.call_function("shown", vec![], HashMap::new()).unwrap();
+
+        assert!(matches!(applied, Ok(Value::UInt(2))), "The parameter did not shadow the built-in send: {:?}", applied);
+        assert_eq!(shown.as_string().as_deref(), Some("loud"), "The Show instance was not picked over the built-in show");
+    }
+
+    #[test]
+    fn test_string_instances_match_lists_of_chars() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("fn quoted(text: String) -> String { \"quoted\" }\n\
+                            fn shown() -> String { show(['h', 'i']) }", &mut interpreter);
+        let quoted = interpreter.get_value("quoted", &HashMap::new()).unwrap().unwrap();
+        let show_class = Type::TypeList{name: Box::new(Type::Single("Show".to_string())), parameters: vec![Type::Single("a".to_string())]};
+        interpreter.add_typeclass_instance(show_class, Type::Single("String".to_string()), vec![("show".to_string(), quoted)]);
+
+        let shown = interpreter.call_function("shown", vec![], HashMap::new()).unwrap();
+        let list_of_chars = Type::TypeList{name: Box::new(Type::Single("List".to_string())), parameters: vec![Type::Single("Char".to_string())]};
+
+        assert_eq!(shown.as_string().as_deref(), Some("quoted"), "The String instance was not used for a list of chars");
+        assert!(interpreter.typeclass_function("show", &list_of_chars).is_some(), "The String instance was not found for List Char");
+    }
+
+    #[test]
+    fn test_varargs_and_spreading() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("fn second(first, rest: ...) exn -> Any { get(rest, 0u) }\n\
+                            fn forward(values: ...) exn -> Any { second(values...) }\n\
+                            fn log(template: String, values: ...) -> String { format(template, values...) }", &mut interpreter);
+
+        let second = interpreter.call_function("forward", vec![Value::UInt(1), Value::string("two"), Value::Char('c')], HashMap::new()).unwrap();
+        let logged = interpreter.call_function("log", vec![Value::string("{} and {}"), Value::UInt(1), Value::string("x")], HashMap::new()).unwrap();
+
+        assert_eq!(second.as_string().as_deref(), Some("two"), "The arguments were not collected and spread in order");
+        assert_eq!(logged.as_string().as_deref(), Some("1 and x"), "An H-List was not spread into a built-in");
+    }
+}
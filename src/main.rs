@@ -7,7 +7,86 @@ pub mod parser;
 pub mod interpreter;
 pub mod types;
 pub mod virtual_machine;
+pub mod builtins;
+pub mod sync;
+pub mod thread_pool;
+pub mod deadlock;
+pub mod scheduler;
+pub mod stm;
+pub mod effect_checker;
+
+use interpreter::{Interpreter, panic_message};
+use parser::module_loader::load_program;
+use thread_pool::{ThreadPool, WORKER_COUNT_VARIABLE};
+
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn usage() -> ! {
+    eprintln!("Usage: {} [--threads N] [--detect-deadlocks] [--deterministic | --seed N] <program.mil>", env::args().next().unwrap_or_else(|| "mil".to_string()));
+    eprintln!("The number of worker threads can also be set with the {} environment variable", WORKER_COUNT_VARIABLE);
+    eprintln!("--deterministic runs the threads one at a time with a random seed and --seed replays a seed");
+    process::exit(2);
+}
 
 fn main() {
-    println!("Hello, world!");
+    let mut worker_count = None;
+    let mut detect_deadlocks = false;
+    let mut seed = None;
+    let mut program = None;
+    let mut arguments = env::args().skip(1);
+    while let Some(argument) = arguments.next() {
+        if argument == "--detect-deadlocks" {
+            detect_deadlocks = true;
+            continue;
+        }
+        if argument == "--deterministic" {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Clock is before the epoch");
+            seed = seed.or(Some(now.as_nanos() as u64));
+            continue;
+        }
+        if argument == "--seed" {
+            seed = Some(arguments.next().and_then(|seed| seed.parse::<u64>().ok()).unwrap_or_else(|| usage()));
+            continue;
+        }
+        let count = if argument == "--threads" {
+            Some(arguments.next().unwrap_or_else(|| usage()))
+        }
+        else {
+            argument.strip_prefix("--threads=").map(str::to_string)
+        };
+        match count {
+            Some(count) => worker_count = Some(count.parse::<usize>().ok().filter(|count| *count > 0).unwrap_or_else(|| usage())),
+            None if program.is_none() && !argument.starts_with("--") => program = Some(argument),
+            None => usage(),
+        }
+    }
+    let program = program.unwrap_or_else(|| usage());
+
+    let mut interpreter = Interpreter::with_worker_count(worker_count.unwrap_or_else(ThreadPool::default_worker_count));
+    interpreter.set_deadlock_detection(detect_deadlocks);
+    interpreter.set_deterministic_seed(seed);
+    if let Err(error) = load_program(&program, &mut interpreter) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+    let result = panic::catch_unwind(AssertUnwindSafe(|| interpreter.start_program()));
+    // A failing deterministic run is only useful if it can be replayed
+    if let (false, Some(seed)) = (matches!(result, Ok(Ok(()))), seed) {
+        eprintln!("Deterministic scheduler seed: {}, rerun with --seed {} to replay", seed, seed);
+    }
+    match result {
+        Ok(Ok(())) => {},
+        Ok(Err(error)) => {
+            eprintln!("Runtime error: {}", error);
+            process::exit(1);
+        },
+        Err(payload) => {
+            // The panic itself was already printed, but not the lines of the do blocks it went through
+            eprintln!("Panicked: {}", panic_message(payload));
+            process::exit(101);
+        },
+    }
 }
@@ -0,0 +1,170 @@
+use chumsky::prelude::*;
+
+use std::collections::HashMap;
+
+use crate::parser::lexer::Token;
+use crate::parser::type_parser::type_parser;
+use crate::parser::function_parser::{Attribute, attribute_parser, function_definition_parser};
+use crate::parser::expression_parser::Expression;
+use crate::types::{Type, Value};
+
+
+/// This is one operation of an effect.
+/// `@Control` operations are only prototypes, since what they do is up to the handler.
+/// The `@Final` operation is what runs when an operation escapes into main without a handler.
+#[derive(Debug, Clone)]
+pub struct EffectOperation {
+    pub name: String,
+    pub function: Value,
+}
+
+impl EffectOperation {
+    fn has_attribute(&self, attribute: &Attribute) -> bool {
+        matches!(&self.function, Value::Function(attributes, ..) if attributes.contains(attribute))
+    }
+
+    pub fn is_control(&self) -> bool {
+        self.has_attribute(&Attribute::Control)
+    }
+
+    pub fn is_final(&self) -> bool {
+        self.has_attribute(&Attribute::Final)
+    }
+
+    /// Prototypes have an empty block for a body, a real body always ends with a value.
+    pub fn has_body(&self) -> bool {
+        !matches!(&self.function, Value::Function(.., Expression::Block(statements)) if statements.is_empty())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Effect {
+    pub name: Type,
+    pub operations: Vec<EffectOperation>,
+}
+
+impl Effect {
+    /// This is the name without its type parameters, so `(state s)` is `state`.
+    pub fn base_name(&self) -> String {
+        effect_name(&self.name)
+    }
+
+    pub fn final_operation(&self) -> Option<&EffectOperation> {
+        self.operations.iter().find(|operation| operation.is_final())
+    }
+}
+
+/// This is the name an effect is registered under, which is also how functions list it.
+pub fn effect_name(the_type: &Type) -> String {
+    match the_type {
+        Type::TypeList{name, ..} => effect_name(name),
+        other => other.to_string(),
+    }
+}
+
+/// This parses an operation without a body like `@Control fn throw-exn(exn) -> a`.
+fn operation_prototype_parser() -> impl Parser<Token, (String, Value), Error = Simple<Token>> {
+    attribute_parser()
+        .then_ignore(just(Token::Function))
+        .then(filter_map(|span, token| match token {
+            Token::Identifier(name) => Ok(name),
+            _ => Err(Simple::custom(span, "Expected identifier".to_string())),
+        }))
+        .then(type_parser()
+              .separated_by(just(Token::Comma))
+              .allow_trailing()
+              .delimited_by(just(Token::ParenLeft), just(Token::ParenRight)))
+        .then(type_parser().separated_by(just(Token::Comma)))
+        .then_ignore(just(Token::FunctionReturn))
+        .then(type_parser())
+        .map(|((((attributes, name), args), effects), return_type)| {
+            let args = args.into_iter().enumerate().map(|(index, arg)| (format!("arg{}", index), Some(arg))).collect();
+            (name, Value::Function(attributes, args, effects, return_type, HashMap::new(), Expression::Block(Vec::new())))
+        })
+}
+
+/// This parses an effect declaration like `effect exn { @Control fn throw-exn(exn) -> a, @Final fn uncaught-exn(e: exn) -> a { ... } }`.
+/// Every operation lists the effect it belongs to so that it can only be used by code that declares that effect.
+pub fn effect_parser() -> impl Parser<Token, Effect, Error = Simple<Token>> {
+    just(Token::Effect)
+        .ignore_then(type_parser())
+        .then(function_definition_parser()
+              .or(operation_prototype_parser())
+              .separated_by(just(Token::Comma).or_not())
+              .allow_trailing()
+              .delimited_by(just(Token::CurlyLeft), just(Token::CurlyRight)))
+        .map(|(name, operations)| {
+            let operations = operations.into_iter().map(|(operation_name, function)| {
+                let function = match function {
+                    Value::Function(attributes, args, mut effects, return_type, captured, body) => {
+                        if !effects.contains(&name) {
+                            effects.push(name.clone());
+                        }
+                        Value::Function(attributes, args, effects, return_type, captured, body)
+                    },
+                    other => other,
+                };
+                EffectOperation { name: operation_name, function }
+            }).collect();
+            Effect { name, operations }
+        })
+        .labelled("effect definition")
+}
+
+
+#[cfg(test)]
+mod effect_parser_tests {
+    use super::*;
+    use crate::parser::lexer::lexer;
+
+    #[test]
+    fn test_effect_definition() {
+        let input = "effect exn { @Control fn throw-exn(exn) -> a, @Final fn uncaught-exn(e: exn) -> a { e } }";
+
+        let tokens = lexer(input);
+
+        if tokens.is_err() {
+            panic!("Lexer error: {:?}", tokens.err());
+        }
+
+        let result = effect_parser().parse(tokens.unwrap());
+
+        if result.is_err() {
+            panic!("Parser error: {:?}", result.err());
+        }
+
+        let effect = result.unwrap();
+
+        assert_eq!(effect.base_name(), "exn", "Incorrect name");
+        let names: Vec<_> = effect.operations.iter().map(|operation| operation.name.as_str()).collect();
+        assert_eq!(names, vec!["throw-exn", "uncaught-exn"], "Incorrect operations");
+        assert!(effect.operations[0].is_control() && !effect.operations[0].has_body(), "throw-exn should be a control prototype");
+        assert!(effect.final_operation().is_some_and(|operation| operation.has_body()), "uncaught-exn should be the final operation");
+        match &effect.operations[0].function {
+            Value::Function(_, _, effects, ..) => assert_eq!(effects, &vec![Type::Single("exn".to_string())], "Operation does not list its effect"),
+            _ => panic!("Not a function"),
+        }
+    }
+
+    #[test]
+    fn test_parameterized_effect() {
+        let input = "effect (state s) { fn get() -> s\n fn put(s) -> () }";
+
+        let tokens = lexer(input);
+
+        if tokens.is_err() {
+            panic!("Lexer error: {:?}", tokens.err());
+        }
+
+        let result = effect_parser().parse(tokens.unwrap());
+
+        if result.is_err() {
+            panic!("Parser error: {:?}", result.err());
+        }
+
+        let effect = result.unwrap();
+
+        assert_eq!(effect.base_name(), "state", "Incorrect name");
+        assert_eq!(effect.operations.len(), 2, "Incorrect number of operations");
+    }
+}
@@ -0,0 +1,581 @@
+use chumsky::prelude::*;
+
+use std::ops::Range;
+
+use crate::parser::lexer::Token;
+use crate::parser::function_parser::function_argument_parser;
+use crate::types::{Type, Value};
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Char(char),
+    String(String),
+    Unit,
+}
+
+impl Literal {
+    pub fn to_value(&self) -> Value {
+        match self {
+            Literal::Int(i) => Value::Int(*i),
+            Literal::UInt(i) => Value::UInt(*i),
+            Literal::Float(f) => Value::Float(*f),
+            Literal::Char(c) => Value::Char(*c),
+            Literal::String(s) => Value::string(s),
+            Literal::Unit => Value::Tuple(Vec::new()),
+        }
+    }
+}
+
+/// This is one clause of a `with` block, which handles the effect operation of the same name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectHandler {
+    pub operation: String,
+    pub args: Vec<(String, Option<Type>)>,
+    pub body: Box<Expression>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Literal(Literal),
+    Variable(String),
+    Call(String, Vec<Expression>),
+    List(Vec<Expression>),
+    Tuple(Vec<Expression>),
+    Assign {
+        name: String,
+        mutable: bool,
+        value: Box<Expression>,
+    },
+    Block(Vec<Expression>),//The value of a block is the value of its last expression
+    Lock(Vec<String>, Box<Expression>),//Holds the locks of Thread-Mutable globals while the block runs
+    Scope(Box<Expression>),//Waits for every task spawned inside of it before finishing
+    Atomically(Box<Expression>),//Runs as a transaction over Thread-Mutable globals
+    Retry,//Restarts the enclosing transaction once a global it read has changed
+    With(Vec<EffectHandler>, Box<Expression>),//Handles effect operations performed while the block runs
+    Lambda(Vec<(String, Option<Type>)>, Box<Expression>),//An anonymous function, only made by desugaring do blocks
+    Located(usize, Box<Expression>),//Remembers the line of a do block statement so that errors can point back at it
+    Spread(Box<Expression>),//Passes every value of an H-List as its own argument, only allowed as an argument of a call
+    For {
+        variable: String,
+        iterable: Box<Expression>,
+        body: Box<Expression>,
+    },
+}
+
+
+/// This converts the text of a number token into a literal.
+/// The lexer keeps the sign, the base prefix, and the suffix so we have to deal with all of them here.
+pub fn number_literal(number: &str) -> Result<Literal, String> {
+    let (negative, digits) = match number.chars().next() {
+        Some('-') => (true, &number[1..]),
+        Some('+') => (false, &number[1..]),
+        _ => (false, number),
+    };
+
+    let radix = match digits.get(..2) {
+        Some("0x") => 16,
+        Some("0o") => 8,
+        Some("0b") => 2,
+        _ => 10,
+    };
+    let digits = if radix == 10 { digits } else { &digits[2..] };
+
+    // Hex numbers can end in an f so we don't treat that as a suffix for them
+    let (digits, suffix) = match digits.chars().last() {
+        Some(c @ ('i' | 'u')) => (&digits[..digits.len() - 1], Some(c)),
+        Some('f') if radix != 16 => (&digits[..digits.len() - 1], Some('f')),
+        _ => (digits, None),
+    };
+
+    let is_float = suffix == Some('f') || (radix == 10 && (digits.contains('.') || digits.contains('e')));
+    if is_float {
+        let value = digits.parse::<f64>().map_err(|e| format!("Invalid float literal {}: {}", number, e))?;
+        return Ok(Literal::Float(if negative { -value } else { value }));
+    }
+
+    let magnitude = u64::from_str_radix(digits, radix).map_err(|e| format!("Invalid integer literal {}: {}", number, e))?;
+    match suffix {
+        Some('u') if negative => Err(format!("Unsigned literal can't be negative: {}", number)),
+        Some('u') => Ok(Literal::UInt(magnitude)),
+        _ => {
+            let value = if negative { 0i64.checked_sub_unsigned(magnitude) } else { i64::try_from(magnitude).ok() };
+            value.map(Literal::Int).ok_or_else(|| format!("Integer literal out of range: {}", number))
+        },
+    }
+}
+
+/// This is one line of a do block before it is desugared.
+enum DoStatement {
+    Bind(String, Expression),
+    Run(Expression),
+}
+
+/// This turns the statements of a do block into a chain of `>>=` calls, working backwards from the last statement.
+/// `x <- action; rest` becomes `>>=(action, fn(x) { rest })` and a plain statement is bound to `_` the same way.
+/// A plain statement isn't turned into `>>` because both sides of it are evaluated first, and the rest of the block has to wait until the monad says it should run.
+/// Assignments stay in the block around the rest of it so that the lambdas after them can see the variable.
+fn desugar_do(mut statements: Vec<(usize, DoStatement)>) -> Result<Expression, String> {
+    let located = |line, expression| Expression::Located(line, Box::new(expression));
+    let lambda = |name: String, body| Expression::Lambda(vec![(name, None)], Box::new(body));
+
+    let mut result = match statements.pop() {
+        Some((line, DoStatement::Run(expression))) if !matches!(expression, Expression::Assign { .. }) => located(line, expression),
+        Some((line, _)) => return Err(format!("The last statement of the do block on line {} has to be an expression", line)),
+        None => return Err("A do block needs at least one statement".to_string()),
+    };
+    for (line, statement) in statements.into_iter().rev() {
+        result = match statement {
+            DoStatement::Bind(name, action) => Expression::Call(">>=".to_string(), vec![located(line, action), lambda(name, result)]),
+            DoStatement::Run(assignment @ Expression::Assign { .. }) => Expression::Block(vec![located(line, assignment), result]),
+            DoStatement::Run(action) => Expression::Call(">>=".to_string(), vec![located(line, action), lambda("_".to_string(), result)]),
+        };
+    }
+    Ok(result)
+}
+
+pub fn expression_parser() -> impl Parser<Token, Expression, Error = Simple<Token>> {
+    recursive(|expression| {
+
+        let literal = filter_map(|span, token| match token {
+            Token::Number(number) => number_literal(&number).map_err(|e| Simple::custom(span, e)),
+            // The lexer treats negative numbers as identifiers since they start with a symbol
+            Token::Identifier(number) if number.starts_with('-') && number[1..].starts_with(|c: char| c.is_ascii_digit()) => {
+                number_literal(&number).map_err(|e| Simple::custom(span, e))
+            },
+            Token::String(string) => Ok(Literal::String(string)),
+            Token::Char(c) => Ok(Literal::Char(c)),
+            Token::Unit => Ok(Literal::Unit),
+            _ => Err(Simple::custom(span, format!("Expected literal, found {:?}", token))),
+        })
+            .map(Expression::Literal)
+            .labelled("literal");
+
+        let identifier = filter_map(|span, token| match token {
+            Token::Identifier(name) => Ok(name),
+            _ => Err(Simple::custom(span, "Expected identifier".to_string())),
+        });
+
+        // The lexer keeps dots in identifiers, so `values...` is one token but `f(x)...` ends with a `...` token
+        let spread_variable = filter_map(|span, token| match token {
+            Token::Identifier(name) if name.len() > 3 && name.ends_with("...") => Ok(Expression::Variable(name[..name.len() - 3].to_string())),
+            _ => Err(Simple::custom(span, "Expected spread argument".to_string())),
+        });
+
+        let argument = spread_variable
+            .map(|values| Expression::Spread(Box::new(values)))
+            .or(expression.clone()
+                .then(just(Token::Identifier("...".to_string())).or_not())
+                .map(|(argument, spread)| match spread {
+                    Some(_) => Expression::Spread(Box::new(argument)),
+                    None => argument,
+                }));
+
+        let arguments = argument
+            .separated_by(just(Token::Comma))
+            .allow_trailing()
+            .delimited_by(just(Token::ParenLeft), just(Token::ParenRight));
+
+        let call = identifier
+            .then(arguments)
+            .map(|(name, arguments)| Expression::Call(name, arguments))
+            .labelled("function call");
+
+        let variable = identifier
+            .map(Expression::Variable)
+            .labelled("variable");
+
+        let list = expression.clone()
+            .separated_by(just(Token::Comma))
+            .allow_trailing()
+            .delimited_by(just(Token::BracketLeft), just(Token::BracketRight))
+            .map(Expression::List)
+            .labelled("list");
+
+        let unit = just(Token::ParenLeft)
+            .then(just(Token::ParenRight))
+            .to(Expression::Literal(Literal::Unit));
+
+        let tuple = expression.clone()
+            .separated_by(just(Token::Comma))
+            .at_least(2)
+            .allow_trailing()
+            .delimited_by(just(Token::ParenLeft), just(Token::ParenRight))
+            .map(Expression::Tuple)
+            .labelled("tuple");
+
+        let parenthesized = expression.clone()
+            .delimited_by(just(Token::ParenLeft), just(Token::ParenRight));
+
+        let assignment = identifier
+            .then(choice((
+                just(Token::Assignment).to(false),
+                just(Token::MutableAssignment).to(true),
+            )))
+            .then(expression.clone())
+            .map(|((name, mutable), value)| Expression::Assign { name, mutable, value: Box::new(value) })
+            .labelled("assignment");
+
+        // A block ending with a semicolon has the value of ()
+        let block = expression.clone()
+            .then(just(Token::Semicolon).or_not())
+            .repeated()
+            .delimited_by(just(Token::CurlyLeft), just(Token::CurlyRight))
+            .map(|statements| {
+                let returns_unit = statements.last().is_none_or(|(_, semicolon)| semicolon.is_some());
+                let mut statements: Vec<Expression> = statements.into_iter().map(|(statement, _)| statement).collect();
+                if returns_unit {
+                    statements.push(Expression::Literal(Literal::Unit));
+                }
+                Expression::Block(statements)
+            })
+            .labelled("block");
+
+        let lock = just(Token::Identifier("lock".to_string()))
+            .ignore_then(identifier
+                         .separated_by(just(Token::Comma))
+                         .at_least(1)
+                         .delimited_by(just(Token::ParenLeft), just(Token::ParenRight)))
+            .then(block.clone())
+            .map(|(names, body)| Expression::Lock(names, Box::new(body)))
+            .labelled("lock block");
+
+        let for_loop = just(Token::For)
+            .ignore_then(identifier)
+            .then_ignore(just(Token::In))
+            .then(expression.clone())
+            .then(block.clone())
+            .map(|((variable, iterable), body)| Expression::For { variable, iterable: Box::new(iterable), body: Box::new(body) })
+            .labelled("for loop");
+
+        let scope = just(Token::Identifier("scope".to_string()))
+            .ignore_then(block.clone())
+            .map(|body| Expression::Scope(Box::new(body)))
+            .labelled("scope block");
+
+        let atomically = just(Token::Identifier("atomically".to_string()))
+            .ignore_then(block.clone())
+            .map(|body| Expression::Atomically(Box::new(body)))
+            .labelled("atomically block");
+
+        let retry = just(Token::Identifier("retry".to_string()))
+            .to(Expression::Retry)
+            .labelled("retry");
+
+        let handler = just(Token::Function)
+            .ignore_then(identifier)
+            .then(function_argument_parser()
+                  .separated_by(just(Token::Comma))
+                  .allow_trailing()
+                  .delimited_by(just(Token::ParenLeft), just(Token::ParenRight)))
+            .then(block.clone())
+            .map(|((operation, args), body)| EffectHandler { operation, args, body: Box::new(body) });
+
+        let with = just(Token::With)
+            .ignore_then(handler
+                         .separated_by(just(Token::Comma))
+                         .at_least(1))
+            .then(block.clone())
+            .map(|(handlers, body)| Expression::With(handlers, Box::new(body)))
+            .labelled("with block");
+
+        // The span is the line of the statement when the tokens come from lexer_with_lines
+        let do_statement = identifier
+            .then_ignore(just(Token::Identifier("<-".to_string())))
+            .then(expression.clone())
+            .map(|(name, action)| DoStatement::Bind(name, action))
+            .or(expression.clone().map(DoStatement::Run))
+            .map_with_span(|statement, span: Range<usize>| (span.start, statement))
+            .then_ignore(just(Token::Semicolon).or_not());
+
+        let do_block = just(Token::Identifier("do".to_string()))
+            .ignore_then(do_statement
+                         .repeated()
+                         .delimited_by(just(Token::CurlyLeft), just(Token::CurlyRight)))
+            .try_map(|statements, span| desugar_do(statements).map_err(|error| Simple::custom(span, error)))
+            .labelled("do block");
+
+        choice((
+            literal,
+            for_loop,
+            with,
+            do_block,
+            lock,
+            scope,
+            atomically,
+            assignment,
+            call,
+            retry,
+            variable,
+            list,
+            unit,
+            tuple,
+            parenthesized,
+            block,
+        ))
+    })
+}
+
+
+#[cfg(test)]
+mod expression_parser_tests {
+    use super::*;
+    use crate::parser::lexer::{lexer, lexer_with_lines, end_of_lines};
+    use chumsky::Stream;
+
+    #[test]
+    fn test_number_literals() {
+        assert_eq!(number_literal("42"), Ok(Literal::Int(42)));
+        assert_eq!(number_literal("-42"), Ok(Literal::Int(-42)));
+        assert_eq!(number_literal("42u"), Ok(Literal::UInt(42)));
+        assert_eq!(number_literal("0xff"), Ok(Literal::Int(255)));
+        assert_eq!(number_literal("0b101u"), Ok(Literal::UInt(5)));
+        assert_eq!(number_literal("2.5e1"), Ok(Literal::Float(25.0)));
+        assert_eq!(number_literal("3f"), Ok(Literal::Float(3.0)));
+        assert!(number_literal("-1u").is_err(), "Negative unsigned literal should fail");
+    }
+
+    #[test]
+    fn test_call() {
+        let input = "f(1, \"hi\", [x, 'c'], (2, 3))";
+
+        let lexer_result = lexer(input);
+
+        if lexer_result.is_err() {
+            assert!(false,"Lexer error: {:?}", lexer_result.err());
+        }
+
+        let result = expression_parser().parse(lexer_result.unwrap());
+
+        if result.is_err() {
+            assert!(false,"Parser error: {:?}", result.err());
+        }
+
+        let expression = result.unwrap();
+
+        assert_eq!(expression, Expression::Call("f".to_string(), vec![
+            Expression::Literal(Literal::Int(1)),
+            Expression::Literal(Literal::String("hi".to_string())),
+            Expression::List(vec![Expression::Variable("x".to_string()), Expression::Literal(Literal::Char('c'))]),
+            Expression::Tuple(vec![Expression::Literal(Literal::Int(2)), Expression::Literal(Literal::Int(3))]),
+        ]), "Expression is not correct");
+    }
+
+    #[test]
+    fn test_negative_number() {
+        let input = "(-2)";
+
+        let lexer_result = lexer(input);
+
+        if lexer_result.is_err() {
+            assert!(false,"Lexer error: {:?}", lexer_result.err());
+        }
+
+        let result = expression_parser().parse(lexer_result.unwrap());
+
+        if result.is_err() {
+            assert!(false,"Parser error: {:?}", result.err());
+        }
+
+        assert_eq!(result.unwrap(), Expression::Literal(Literal::Int(-2)), "Expression is not correct");
+    }
+
+    #[test]
+    fn test_lock_block() {
+        let input = "lock(a, b) { x = a; b := f(x); x }";
+
+        let lexer_result = lexer(input);
+
+        if lexer_result.is_err() {
+            assert!(false,"Lexer error: {:?}", lexer_result.err());
+        }
+
+        let result = expression_parser().parse(lexer_result.unwrap());
+
+        if result.is_err() {
+            assert!(false,"Parser error: {:?}", result.err());
+        }
+
+        assert_eq!(result.unwrap(), Expression::Lock(vec!["a".to_string(), "b".to_string()], Box::new(Expression::Block(vec![
+            Expression::Assign { name: "x".to_string(), mutable: false, value: Box::new(Expression::Variable("a".to_string())) },
+            Expression::Assign { name: "b".to_string(), mutable: true, value: Box::new(Expression::Call("f".to_string(), vec![Expression::Variable("x".to_string())])) },
+            Expression::Variable("x".to_string()),
+        ]))), "Expression is not correct");
+    }
+
+    #[test]
+    fn test_block_ending_in_semicolon() {
+        let input = "{ f(); }";
+
+        let lexer_result = lexer(input);
+
+        if lexer_result.is_err() {
+            assert!(false,"Lexer error: {:?}", lexer_result.err());
+        }
+
+        let result = expression_parser().parse(lexer_result.unwrap());
+
+        if result.is_err() {
+            assert!(false,"Parser error: {:?}", result.err());
+        }
+
+        assert_eq!(result.unwrap(), Expression::Block(vec![
+            Expression::Call("f".to_string(), vec![]),
+            Expression::Literal(Literal::Unit),
+        ]), "Expression is not correct");
+    }
+
+    #[test]
+    fn test_for_loop() {
+        let input = "for x in jobs { f(x) }";
+
+        let lexer_result = lexer(input);
+
+        if lexer_result.is_err() {
+            assert!(false,"Lexer error: {:?}", lexer_result.err());
+        }
+
+        let result = expression_parser().parse(lexer_result.unwrap());
+
+        if result.is_err() {
+            assert!(false,"Parser error: {:?}", result.err());
+        }
+
+        assert_eq!(result.unwrap(), Expression::For {
+            variable: "x".to_string(),
+            iterable: Box::new(Expression::Variable("jobs".to_string())),
+            body: Box::new(Expression::Block(vec![Expression::Call("f".to_string(), vec![Expression::Variable("x".to_string())])])),
+        }, "Expression is not correct");
+    }
+
+    #[test]
+    fn test_scope_block() {
+        let input = "scope { f(); g() }";
+
+        let lexer_result = lexer(input);
+
+        if lexer_result.is_err() {
+            assert!(false,"Lexer error: {:?}", lexer_result.err());
+        }
+
+        let result = expression_parser().parse(lexer_result.unwrap());
+
+        if result.is_err() {
+            assert!(false,"Parser error: {:?}", result.err());
+        }
+
+        assert_eq!(result.unwrap(), Expression::Scope(Box::new(Expression::Block(vec![
+            Expression::Call("f".to_string(), vec![]),
+            Expression::Call("g".to_string(), vec![]),
+        ]))), "Expression is not correct");
+    }
+
+    #[test]
+    fn test_atomically_block() {
+        let input = "atomically { x = f(x); retry }";
+
+        let lexer_result = lexer(input);
+
+        if lexer_result.is_err() {
+            assert!(false,"Lexer error: {:?}", lexer_result.err());
+        }
+
+        let result = expression_parser().parse(lexer_result.unwrap());
+
+        if result.is_err() {
+            assert!(false,"Parser error: {:?}", result.err());
+        }
+
+        assert_eq!(result.unwrap(), Expression::Atomically(Box::new(Expression::Block(vec![
+            Expression::Assign { name: "x".to_string(), mutable: false, value: Box::new(Expression::Call("f".to_string(), vec![Expression::Variable("x".to_string())])) },
+            Expression::Retry,
+        ]))), "Expression is not correct");
+    }
+
+    #[test]
+    fn test_with_block() {
+        let input = "with fn throw-exn(e) { e }, fn log(message: String) { () } { f() }";
+
+        let lexer_result = lexer(input);
+
+        if lexer_result.is_err() {
+            assert!(false,"Lexer error: {:?}", lexer_result.err());
+        }
+
+        let result = expression_parser().parse(lexer_result.unwrap());
+
+        if result.is_err() {
+            assert!(false,"Parser error: {:?}", result.err());
+        }
+
+        assert_eq!(result.unwrap(), Expression::With(vec![
+            EffectHandler {
+                operation: "throw-exn".to_string(),
+                args: vec![("e".to_string(), None)],
+                body: Box::new(Expression::Block(vec![Expression::Variable("e".to_string())])),
+            },
+            EffectHandler {
+                operation: "log".to_string(),
+                args: vec![("message".to_string(), Some(Type::Single("String".to_string())))],
+                body: Box::new(Expression::Block(vec![Expression::Literal(Literal::Unit)])),
+            },
+        ], Box::new(Expression::Block(vec![Expression::Call("f".to_string(), vec![])]))), "Expression is not correct");
+    }
+
+    #[test]
+    fn test_do_block() {
+        let input = "do {\n    x <- read();\n    y = x\n    pure(y)\n}";
+
+        let lexer_result = lexer_with_lines(input);
+
+        if lexer_result.is_err() {
+            assert!(false,"Lexer error: {:?}", lexer_result.err());
+        }
+
+        let tokens = lexer_result.unwrap();
+        let eoi = end_of_lines(&tokens);
+        let result = expression_parser().parse(Stream::from_iter(eoi, tokens.into_iter()));
+
+        if result.is_err() {
+            assert!(false,"Parser error: {:?}", result.err());
+        }
+
+        let located = |line, expression| Expression::Located(line, Box::new(expression));
+        assert_eq!(result.unwrap(), Expression::Call(">>=".to_string(), vec![
+            located(2, Expression::Call("read".to_string(), vec![])),
+            Expression::Lambda(vec![("x".to_string(), None)], Box::new(Expression::Block(vec![
+                located(3, Expression::Assign { name: "y".to_string(), mutable: false, value: Box::new(Expression::Variable("x".to_string())) }),
+                located(4, Expression::Call("pure".to_string(), vec![Expression::Variable("y".to_string())])),
+            ]))),
+        ]), "Expression is not correct");
+
+        let result = expression_parser().then_ignore(end()).parse(lexer("do { x <- read() }").unwrap());
+        assert!(result.is_err(), "A do block ending in a bind should not parse");
+    }
+
+    #[test]
+    fn test_spread_arguments() {
+        let input = "f(a, values..., g(x)...)";
+
+        let lexer_result = lexer(input);
+
+        if lexer_result.is_err() {
+            assert!(false,"Lexer error: {:?}", lexer_result.err());
+        }
+
+        let result = expression_parser().parse(lexer_result.unwrap());
+
+        if result.is_err() {
+            assert!(false,"Parser error: {:?}", result.err());
+        }
+
+        assert_eq!(result.unwrap(), Expression::Call("f".to_string(), vec![
+            Expression::Variable("a".to_string()),
+            Expression::Spread(Box::new(Expression::Variable("values".to_string()))),
+            Expression::Spread(Box::new(Expression::Call("g".to_string(), vec![Expression::Variable("x".to_string())]))),
+        ]), "Expression is not correct");
+    }
+}
@@ -1,28 +1,43 @@
 use crate::interpreter::{Interpreter};
-use crate::parser::lexer::{lexer, Token};
+use crate::parser::lexer::{lexer_with_lines, end_of_lines, Token};
 
 use std::fs::File;
 use std::io::Read;
 use chumsky::prelude::*;
+use chumsky::Stream;
 
 use super::algabraic_type_parser::{TypeAlias, ProductType, SumType, type_alias_parser, product_type_parser, sum_type_parser};
 use super::type_class_parser::{type_class_definition_parser};
+use super::import_parser::{Import, import_parser};
+use super::global_parser::{GlobalVariable, global_variable_parser};
+use super::function_parser::function_definition_parser;
+use super::effect_parser::{Effect, effect_parser};
+use crate::types::Value;
 use crate::parser::type_class_parser::TypeClass;
 
-enum TopLevelStatement {
+#[derive(Clone)]
+pub(crate) enum TopLevelStatement {
     TypeClass(TypeClass),
     TypeAlias(TypeAlias),
     SumType(SumType),
     ProductType(ProductType),
+    Import(Import),
+    GlobalVariable(GlobalVariable),
+    Effect(Effect),
+    Function(String, Value),
 }
 
-fn module_parser() -> impl Parser<Token, Vec<TopLevelStatement>, Error = Simple<Token>> {
+pub(crate) fn module_parser() -> impl Parser<Token, Vec<TopLevelStatement>, Error = Simple<Token>> {
     
     choice((
+        import_parser().map(TopLevelStatement::Import),
         type_alias_parser().map(TopLevelStatement::TypeAlias),
         sum_type_parser().map(TopLevelStatement::SumType),
         product_type_parser().map(TopLevelStatement::ProductType),
         type_class_definition_parser().map(TopLevelStatement::TypeClass),
+        effect_parser().map(TopLevelStatement::Effect),
+        function_definition_parser().map(|(name, function)| TopLevelStatement::Function(name, function)),
+        global_variable_parser().map(TopLevelStatement::GlobalVariable),
     )).repeated()
 }
 
@@ -39,10 +54,18 @@ pub fn file_parser(file: &str, interpreter: &mut Interpreter) {
 }
 
 pub fn file_parser_helper(file_contents: &str, interpreter: &mut Interpreter) {
-    let tokens = lexer(file_contents).expect("Something went wrong lexing the file");
-    
-    let module = module_parser().parse(tokens).expect("Something went wrong parsing the file");
+    let tokens = lexer_with_lines(file_contents).expect("Something went wrong lexing the file");
+    let end = end_of_lines(&tokens);
+
+    let module = module_parser().parse(Stream::from_iter(end, tokens.into_iter())).expect("Something went wrong parsing the file");
+
+    register_statements(module, interpreter);
+}
 
+/// This adds everything declared in a module to the interpreter.
+/// Imports are skipped since they are resolved by the module loader before we get here.
+/// Global variables are evaluated here, so they can use anything declared before them.
+pub(crate) fn register_statements(module: Vec<TopLevelStatement>, interpreter: &mut Interpreter) {
     for statement in module {
         match statement {
             TopLevelStatement::TypeClass(type_class) => {
@@ -59,6 +82,16 @@ pub fn file_parser_helper(file_contents: &str, interpreter: &mut Interpreter) {
                 interpreter.add_type(product_type.name);
                 //TODO: Add constructors for product types
             },
+            TopLevelStatement::Import(_) => {},
+            TopLevelStatement::GlobalVariable(global) => {
+                interpreter.add_global_variable(global);
+            },
+            TopLevelStatement::Effect(effect) => {
+                interpreter.add_effect(effect);
+            },
+            TopLevelStatement::Function(name, function) => {
+                interpreter.add_function(&name, function);
+            },
         }
     }
 }
@@ -67,6 +100,8 @@ pub fn file_parser_helper(file_contents: &str, interpreter: &mut Interpreter) {
 #[cfg(test)]
 mod whole_file_parser {
     use super::*;
+    use std::collections::HashMap;
+    use std::thread;
 
     #[test]
     fn test_sum_type() {
@@ -102,19 +137,58 @@ mod whole_file_parser {
     fn test_type_class() {
         let mut interpreter = Interpreter::new();
         let file_contents = "class (Eq a) { fn (==)(a, a) -> Bool\n fn(!=)(a, a) -> Bool }";
-        file_parser_helper(file_contents, &mut interpreter);
         let valid_typeclasses = interpreter.get_type_classes();
-        assert_eq!(valid_typeclasses.read().unwrap().len(), 1);
+        let builtin_typeclasses = valid_typeclasses.read().unwrap().len();
+        file_parser_helper(file_contents, &mut interpreter);
+        assert_eq!(valid_typeclasses.read().unwrap().len(), builtin_typeclasses + 1);
     }
 
     #[test]
     fn test_multiple_statements() {
         let mut interpreter = Interpreter::new();
         let file_contents = "class (Eq a) { fn (==)(a, a) -> Bool\n fn(!=)(a, a) -> Bool }\nsum type (Maybe a) { Just(a), Nothing }\nproduct type Fixed { right: Int, left: UInt }\ntype String = (List Char)";
-        let module = file_parser_helper(file_contents, &mut interpreter);
         let valid_typeclasses = interpreter.get_type_classes();
-        assert_eq!(valid_typeclasses.read().unwrap().len(), 1);
+        let builtin_typeclasses = valid_typeclasses.read().unwrap().len();
+        let module = file_parser_helper(file_contents, &mut interpreter);
+        assert_eq!(valid_typeclasses.read().unwrap().len(), builtin_typeclasses + 1);
         let types = interpreter.get_valid_types();
         assert_eq!(types.read().unwrap().len(), 3);
     }
+
+    #[test]
+    fn test_global_variables() {
+        let mut interpreter = Interpreter::new();
+        let file_contents = "x = 2;\n@ThreadShared\nname = \"mil\";\n@ThreadMutable\ncounter := 0u;\n@ThreadLocal\nflags := [1, 2];";
+        file_parser_helper(file_contents, &mut interpreter);
+        let locals = HashMap::new();
+
+        assert!(matches!(interpreter.get_value("x", &locals).unwrap(), Some(Value::Int(2))), "x was not a thread local global");
+        assert!(matches!(interpreter.get_value("name", &locals).unwrap(), Some(Value::List(ref chars, _)) if chars.len() == 3), "name was not a thread shared global");
+        assert!(matches!(interpreter.get_value("counter", &locals).unwrap(), Some(Value::UInt(0))), "counter was not a thread mutable global");
+        assert!(matches!(interpreter.get_value("flags", &locals).unwrap(), Some(Value::List(ref values, _)) if values.len() == 2), "flags was not a thread local global");
+    }
+
+    #[test]
+    fn test_assign_mutable_globals() {
+        let mut interpreter = Interpreter::new();
+        let file_contents = "@ThreadMutable\ncounter := 0u;\ntotal := 1;";
+        file_parser_helper(file_contents, &mut interpreter);
+
+        let mut thread_interpreter = interpreter.new_for_thread();
+        thread::spawn(move || {
+            thread_interpreter.set_value("counter", &mut HashMap::new(), Value::UInt(5)).unwrap();
+        }).join().unwrap();
+        interpreter.set_value("total", &mut HashMap::new(), Value::Int(7)).unwrap();
+
+        assert!(matches!(interpreter.get_value("counter", &HashMap::new()).unwrap(), Some(Value::UInt(5))), "counter was not updated by the other thread");
+        assert!(matches!(interpreter.get_value("total", &HashMap::new()).unwrap(), Some(Value::Int(7))), "total was not updated");
+    }
+
+    #[test]
+    #[should_panic(expected = "Tried to assign to a shared global variable")]
+    fn test_assign_shared_global() {
+        let mut interpreter = Interpreter::new();
+        file_parser_helper("@ThreadShared\nname = 'a';", &mut interpreter);
+        interpreter.set_value("name", &mut HashMap::new(), Value::Char('b')).unwrap();
+    }
 }
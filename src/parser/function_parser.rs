@@ -2,8 +2,9 @@ use chumsky::prelude::*;
 use lazy_static::lazy_static;
 
 use crate::parser::lexer::Token;
-use crate::parser::type_parser::{type_parser, type_statement_parser};
-use crate::types::{Type, Value, TypeUtils};
+use crate::parser::type_parser::{type_parser, type_statement_parser, return_type_parser};
+use crate::types::{Type, Value, TypeUtils, is_varargs_type};
+use crate::parser::expression_parser::{Expression, expression_parser};
 
 use std::collections::HashMap;
 use std::sync::RwLock;
@@ -36,8 +37,7 @@ pub fn attribute_parser() -> impl Parser<Token, Vec<Attribute>, Error = Simple<T
     )
         .then(filter_map(|span, token| match token {
             Token::Number(num) => Ok(num),
-            Token::Identifier(name) => Ok(name),
-            _ => Err(Simple::custom(span, "Expected identifier or number")),
+            _ => Err(Simple::custom(span, "Expected number")),
         }).repeated())
         .map(|(name, values)| match name.as_str() {
             "Op-Ord" => Attribute::OperatorOrder(values[0].parse::<usize>().unwrap()),
@@ -207,7 +207,7 @@ pub fn infix_function_prototype_parser() -> impl Parser<Token, Result<Type, (Str
                 add_operator_associativity(name.clone(), Associativity::None)
             }
 
-            Ok(Value::Function(attributes, args.iter().map(|x| (String::new(), Some(x.clone()))).collect(), Vec::new(), return_type, HashMap::new(), String::new()).get_type())
+            Ok(Value::Function(attributes, args.iter().map(|x| (String::new(), Some(x.clone()))).collect(), Vec::new(), return_type, HashMap::new(), Expression::Block(Vec::new())).get_type())
             
         });
 
@@ -247,7 +247,7 @@ pub fn infix_function_prototype_parser() -> impl Parser<Token, Result<Type, (Str
                 add_operator_associativity(name.clone(), Associativity::None)
             }
 
-            Ok(Value::Function(attributes, args.iter().map(|x| (String::new(), Some(x.clone()))).collect(), effects, return_type, HashMap::new(), String::new()).get_type())
+            Ok(Value::Function(attributes, args.iter().map(|x| (String::new(), Some(x.clone()))).collect(), effects, return_type, HashMap::new(), Expression::Block(Vec::new())).get_type())
             
         });
 
@@ -255,7 +255,7 @@ pub fn infix_function_prototype_parser() -> impl Parser<Token, Result<Type, (Str
 
 }
 
-fn function_argument_parser() -> impl Parser<Token, (String, Option<Type>), Error = Simple<Token>> {
+pub(crate) fn function_argument_parser() -> impl Parser<Token, (String, Option<Type>), Error = Simple<Token>> {
 
     let typed_arg = filter_map(|span, token| match token {
         Token::Identifier(name) => Ok(name),
@@ -270,7 +270,117 @@ fn function_argument_parser() -> impl Parser<Token, (String, Option<Type>), Erro
 
     choice((typed_arg, untyped_arg))
 }
-    
+
+/// This parses a named function like `fn add1(x: Int) -> Int { add(x, 1) }`.
+/// Effects go between the arguments and the arrow: `fn read(path: String) io, exn -> String { ... }`.
+pub fn function_definition_parser() -> impl Parser<Token, (String, Value), Error = Simple<Token>> {
+
+    let effects = type_parser()
+        .separated_by(just(Token::Comma));
+
+    attribute_parser()
+        .then_ignore(just(Token::Function))
+        .then(filter_map(|span, token| match token {
+            Token::Identifier(name) => Ok(name),
+            _ => Err(Simple::custom(span, "Expected identifier".to_string())),
+        }))
+        .then(function_argument_parser()
+              .separated_by(just(Token::Comma))
+              .allow_trailing()
+              .delimited_by(just(Token::ParenLeft), just(Token::ParenRight))
+              .try_map(|args: Vec<(String, Option<Type>)>, span| {
+                  // Everything after a `...` parameter would be collected into it, so nothing can come after it
+                  match args.iter().position(|(_, the_type)| the_type.as_ref().is_some_and(is_varargs_type)) {
+                      Some(index) if index + 1 < args.len() => Err(Simple::custom(span, format!("The ... parameter {} has to be the last parameter", args[index].0))),
+                      _ => Ok(args),
+                  }
+              }))
+        .then(effects)
+        // A function without a return type gives back ()
+        .then(just(Token::FunctionReturn).ignore_then(return_type_parser()).or_not())
+        .then(expression_parser().try_map(|body, span| match body {
+            Expression::Block(_) => Ok(body),
+            _ => Err(Simple::custom(span, "Expected function body")),
+        }))
+        .map(|(((((attributes, name), args), effects), return_type), body)| {
+            (name, Value::Function(attributes, args, effects, return_type.unwrap_or(Type::Unit), HashMap::new(), body))
+        })
+        .labelled("function definition")
+}
+
+#[cfg(test)]
+mod function_definition_parser_tests {
+    use super::*;
+    use crate::parser::lexer::lexer;
+
+    #[test]
+    fn test_function_definition() {
+        let input = "@ThreadSpawn\nfn add1(x: Int, y) io, exn -> Int { add(x, 1) }";
+
+        let tokens = lexer(input);
+
+        if tokens.is_err() {
+            panic!("Lexer error: {:?}", tokens.err());
+        }
+
+        let result = function_definition_parser().parse(tokens.unwrap());
+
+        if result.is_err() {
+            panic!("Parser error: {:?}", result.err());
+        }
+
+        let (name, function) = result.unwrap();
+
+        assert_eq!(name, "add1".to_string(), "Incorrect name");
+        match function {
+            Value::Function(attributes, args, effects, return_type, _, body) => {
+                assert_eq!(attributes, vec![Attribute::ThreadSpawn], "Incorrect attributes");
+                assert_eq!(args, vec![("x".to_string(), Some(Type::Single("Int".to_string()))), ("y".to_string(), None)], "Incorrect arguments");
+                assert_eq!(effects, vec![Type::Single("io".to_string()), Type::Single("exn".to_string())], "Incorrect effects");
+                assert_eq!(return_type, Type::Single("Int".to_string()), "Incorrect return type");
+                assert!(matches!(body, Expression::Block(_)), "Body is not a block");
+            },
+            _ => panic!("Not a function"),
+        }
+    }
+
+    #[test]
+    fn test_varargs_parameter() {
+        let tokens = lexer("fn log(prefix: String, values: ...) -> () { () }");
+
+        if tokens.is_err() {
+            panic!("Lexer error: {:?}", tokens.err());
+        }
+
+        match function_definition_parser().parse(tokens.unwrap()) {
+            Ok((_, Value::Function(_, args, ..))) => assert_eq!(args[1], ("values".to_string(), Some(Type::Single("...".to_string()))), "Incorrect ... parameter"),
+            other => panic!("Parser error: {:?}", other),
+        }
+
+        let tokens = lexer("fn log(values: ..., prefix: String) -> () { () }").unwrap();
+
+        assert!(function_definition_parser().parse(tokens).is_err(), "A ... parameter that isn't last should not parse");
+    }
+
+    #[test]
+    fn test_return_type_defaults_to_unit() {
+        let tokens = lexer("fn main() Console { println(\"Hello, World!\"); }");
+
+        if tokens.is_err() {
+            panic!("Lexer error: {:?}", tokens.err());
+        }
+
+        match function_definition_parser().parse(tokens.unwrap()) {
+            Ok((name, Value::Function(_, _, effects, return_type, _, _))) => {
+                assert_eq!(name, "main".to_string(), "Incorrect name");
+                assert_eq!(effects, vec![Type::Single("Console".to_string())], "Incorrect effects");
+                assert_eq!(return_type, Type::Unit, "A function without a return type should give back ()");
+            },
+            other => panic!("Parser error: {:?}", other),
+        }
+    }
+}
+
 
 /*pub fn infix_function_parser() -> impl Parser<Token, Result<Type, (String, Value)>, Error = Simple<Token>> {
 
@@ -0,0 +1,93 @@
+use chumsky::prelude::*;
+
+use crate::parser::lexer::Token;
+use crate::parser::function_parser::{Attribute, attribute_parser};
+use crate::parser::type_parser::type_statement_parser;
+use crate::parser::expression_parser::{Expression, expression_parser};
+use crate::types::Type;
+
+
+/// This represents a global variable declaration.
+/// The attributes decide which table the variable ends up in and mutable is true if it was declared with `:=`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalVariable {
+    pub attributes: Vec<Attribute>,
+    pub name: String,
+    pub the_type: Option<Type>,
+    pub mutable: bool,
+    pub value: Expression,
+}
+
+
+pub fn global_variable_parser() -> impl Parser<Token, GlobalVariable, Error = Simple<Token>> {
+
+    let assignment = choice((
+        just(Token::Assignment).to(false),
+        just(Token::MutableAssignment).to(true),
+    ));
+
+    attribute_parser()
+        .then(filter_map(|span, token| match token {
+            Token::Identifier(name) => Ok(name),
+            _ => Err(Simple::custom(span, "Expected identifier".to_string())),
+        }))
+        .then(type_statement_parser().or_not())
+        .then(assignment)
+        .then(expression_parser())
+        .then_ignore(just(Token::Semicolon).or_not())
+        .map(|((((attributes, name), the_type), mutable), value)| GlobalVariable { attributes, name, the_type, mutable, value })
+        .labelled("global variable")
+}
+
+
+#[cfg(test)]
+mod global_variable_parser_tests {
+    use super::*;
+    use crate::parser::lexer::lexer;
+    use crate::parser::expression_parser::Literal;
+
+    #[test]
+    fn test_immutable_global() {
+        let input = "x = 2;";
+
+        let lexer_result = lexer(input);
+
+        if lexer_result.is_err() {
+            assert!(false,"Lexer error: {:?}", lexer_result.err());
+        }
+
+        let result = global_variable_parser().parse(lexer_result.unwrap());
+
+        if result.is_err() {
+            assert!(false,"Parser error: {:?}", result.err());
+        }
+
+        let global = result.unwrap();
+
+        assert_eq!(global, GlobalVariable { attributes: vec![], name: "x".to_string(), the_type: None, mutable: false, value: Expression::Literal(Literal::Int(2)) }, "Global is not correct");
+    }
+
+    #[test]
+    fn test_attributed_mutable_global() {
+        let input = "@ThreadMutable\ncounter : UInt := 0u;";
+
+        let lexer_result = lexer(input);
+
+        if lexer_result.is_err() {
+            assert!(false,"Lexer error: {:?}", lexer_result.err());
+        }
+
+        let result = global_variable_parser().parse(lexer_result.unwrap());
+
+        if result.is_err() {
+            assert!(false,"Parser error: {:?}", result.err());
+        }
+
+        let global = result.unwrap();
+
+        assert_eq!(global.attributes, vec![Attribute::ThreadMutable], "Attributes are not correct");
+        assert_eq!(global.name, "counter".to_string(), "Name is not correct");
+        assert_eq!(global.the_type, Some(Type::Single("UInt".to_string())), "Type is not correct");
+        assert!(global.mutable, "Global should be mutable");
+    }
+}
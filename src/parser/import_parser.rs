@@ -0,0 +1,114 @@
+use chumsky::prelude::*;
+
+use crate::parser::lexer::Token;
+
+
+/// This represents an import statement.
+/// The path is the list of names separated by `::` and the items are the names listed in the braces if there are any.
+/// `import std::IO;` has no items and `import std::IO::{open, File};` has the items `open` and `File`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    pub path: Vec<String>,
+    pub items: Vec<String>,
+}
+
+impl Import {
+    pub fn module_name(&self) -> String {
+        self.path.join("::")
+    }
+}
+
+
+pub fn import_parser() -> impl Parser<Token, Import, Error = Simple<Token>> {
+
+    let identifier = filter_map(|span, token| match token {
+        Token::Identifier(name) => Ok(name),
+        _ => Err(Simple::custom(span, "Expected identifier".to_string())),
+    });
+
+    let items = identifier
+        .separated_by(just(Token::Comma))
+        .allow_trailing()
+        .delimited_by(just(Token::CurlyLeft), just(Token::CurlyRight))
+        .labelled("import items");
+
+    just(Token::Import)
+        .ignore_then(identifier.separated_by(just(Token::Namespace)).at_least(1))
+        .then(just(Token::Namespace).ignore_then(items).or_not())
+        .then_ignore(just(Token::Semicolon))
+        .map(|(path, items)| Import { path, items: items.unwrap_or_default() })
+        .labelled("import")
+}
+
+
+#[cfg(test)]
+mod import_parser_tests {
+    use super::*;
+    use crate::parser::lexer::lexer;
+
+    #[test]
+    fn test_simple_import() {
+        let input = "import std;";
+
+        let lexer_result = lexer(input);
+
+        if lexer_result.is_err() {
+            assert!(false,"Lexer error: {:?}", lexer_result.err());
+        }
+
+        let result = import_parser().parse(lexer_result.unwrap());
+
+        if result.is_err() {
+            assert!(false,"Parser error: {:?}", result.err());
+        }
+
+        let import = result.unwrap();
+
+        assert_eq!(import.path, vec!["std".to_string()], "Path is not correct");
+        assert!(import.items.is_empty(), "There should be no items");
+    }
+
+    #[test]
+    fn test_nested_import() {
+        let input = "import std::IO::open;";
+
+        let lexer_result = lexer(input);
+
+        if lexer_result.is_err() {
+            assert!(false,"Lexer error: {:?}", lexer_result.err());
+        }
+
+        let result = import_parser().parse(lexer_result.unwrap());
+
+        if result.is_err() {
+            assert!(false,"Parser error: {:?}", result.err());
+        }
+
+        let import = result.unwrap();
+
+        assert_eq!(import.module_name(), "std::IO::open".to_string(), "Path is not correct");
+        assert!(import.items.is_empty(), "There should be no items");
+    }
+
+    #[test]
+    fn test_import_items() {
+        let input = "import std::IO::{open, File};";
+
+        let lexer_result = lexer(input);
+
+        if lexer_result.is_err() {
+            assert!(false,"Lexer error: {:?}", lexer_result.err());
+        }
+
+        let result = import_parser().parse(lexer_result.unwrap());
+
+        if result.is_err() {
+            assert!(false,"Parser error: {:?}", result.err());
+        }
+
+        let import = result.unwrap();
+
+        assert_eq!(import.module_name(), "std::IO".to_string(), "Path is not correct");
+        assert_eq!(import.items, vec!["open".to_string(), "File".to_string()], "Items are not correct");
+    }
+}
@@ -1,6 +1,7 @@
 use chumsky::prelude::*;
 
 use std::fmt;
+use std::ops::Range;
 
 
 //TODO: Change String to &str
@@ -571,7 +572,6 @@ fn strings() -> impl Parser<char, Token, Error = Simple<char>> {
     let string = just('"')
         .ignore_then(string_char.repeated())
         .then_ignore(just('"'))
-        .then_ignore(end())
         .map(|s| Token::String(s.iter().collect()));
     
 
@@ -646,7 +646,6 @@ fn chars() -> impl Parser<char, Token, Error = Simple<char>> {
     let char_ = just('\'')
         .ignore_then(possible_char)
         .then_ignore(just('\''))
-        .then_ignore(end())
         .map(|s| Token::Char(s));
     
 
@@ -1029,54 +1028,101 @@ pub fn tokenizer() -> impl Parser<char, Vec<Token>, Error = Simple<char>> {
 
 
 
+/// This is a token with the part of the input it came from.
+pub type SpannedToken = (Token, Range<usize>);
+
+/// This is the same as `tokenizer` but keeps where in the input each token came from.
+fn spanned_tokenizer() -> impl Parser<char, Vec<SpannedToken>, Error = Simple<char>> {
+
+    let token = choice((
+        keywords(),
+        symbols(),
+        identifiers(),
+        operators(),
+        literals(),
+        comments(),
+    ));
+
+    token.map_with_span(|token, span| (token, span)).padded().repeated().then_ignore(end())
+}
+
 pub fn lexer(input: &str) -> Result<Vec<Token>, Vec<Simple<char>>> {
-    let result = tokenizer().parse(input)?;
+    Ok(spanned_lexer(input)?.into_iter().map(|(token, _)| token).collect())
+}
+
+/// This lexes like `lexer` but gives every token the line it is on as its span.
+/// Parsing these instead of bare tokens lets parsers and their errors tell which line something came from.
+pub fn lexer_with_lines(input: &str) -> Result<Vec<SpannedToken>, Vec<Simple<char>>> {
+    let mut lines = Vec::with_capacity(input.len() + 1);
+    let mut line = 1;
+    for c in input.chars() {
+        lines.push(line);
+        if c == '\n' {
+            line += 1;
+        }
+    }
+    lines.push(line);
+
+    Ok(spanned_lexer(input)?.into_iter().map(|(token, span)| {
+        let line = lines[span.start];
+        (token, line..line + 1)
+    }).collect())
+}
+
+/// This is the end of the input for a stream made from `lexer_with_lines`.
+pub fn end_of_lines(tokens: &[SpannedToken]) -> Range<usize> {
+    let line = tokens.last().map_or(1, |(_, span)| span.start);
+    line..line + 1
+}
+
+fn spanned_lexer(input: &str) -> Result<Vec<SpannedToken>, Vec<Simple<char>>> {
+    let result = spanned_tokenizer().parse(input)?;
 
     // This merges all whitespace tokens into one
     let mut new_result = Vec::new();
-    for token in result {
+    for (token, span) in result {
+        let mut push = |token| new_result.push((token, span.clone()));
 
         if token == Token::Identifier(":=".to_string()) {
-            new_result.push(Token::MutableAssignment);
+            push(Token::MutableAssignment);
         }
         else if token == Token::Identifier("::".to_string()) {
-            new_result.push(Token::Namespace);
+            push(Token::Namespace);
         }
         else if token == Token::Identifier("->".to_string()) {
-            new_result.push(Token::FunctionReturn);
+            push(Token::FunctionReturn);
         }
         else if token == Token::Identifier("=>".to_string()) {
-            new_result.push(Token::MatchArm);
+            push(Token::MatchArm);
         }
         else if token == Token::Identifier(".".to_string()) {
-            new_result.push(Token::Period);
+            push(Token::Period);
         }
         else if token == Token::Identifier("=".to_string()) {
-            new_result.push(Token::Assignment);
+            push(Token::Assignment);
         }
         else if token == Token::Identifier("fn".to_string()) {
-            new_result.push(Token::Function);
+            push(Token::Function);
         }
         else {
             match token {
                 Token::Identifier(s) => {
                     if s.starts_with("&") && s.len() > 1 && s[1..].chars().all(|c| c.is_alphanumeric()) {
-                        new_result.push(Token::Reference);
+                        push(Token::Reference);
                         //We should probably check if the identifier is other keywords here just in case
                         if s[1..] == *"fn" {
-                            new_result.push(Token::Function);
+                            push(Token::Function);
                         }
                         else {
-                            new_result.push(Token::Identifier(s[1..].to_string()));
+                            push(Token::Identifier(s[1..].to_string()));
                         }
                     }
                     else {
-                        new_result.push(Token::Identifier(s));
+                        push(Token::Identifier(s));
                     }
                 },
-                _ => new_result.push(token),
+                _ => push(token),
             }
-            //new_result.push(token);
         }
 
         
@@ -1116,6 +1162,20 @@ mod lexer_tests {
 
         assert_eq!(tokens, vec![Token::Class, Token::Identifier("Monad".to_string()), Token::Identifier("m".to_string()), Token::CurlyLeft, Token::Function, Token::ParenLeft, Token::Identifier(">>=".to_string()), Token::ParenRight, Token::ParenLeft, Token::Identifier("m".to_string()), Token::Identifier("a".to_string()), Token::Comma, Token::Function, Token::ParenLeft, Token::Identifier("a".to_string()), Token::ParenRight, Token::FunctionReturn, Token::Identifier("m".to_string()), Token::Identifier("b".to_string()), Token::ParenRight, Token::FunctionReturn, Token::Identifier("m".to_string()), Token::Identifier("b".to_string()), Token::CurlyRight], "Token not type class");
     }
+
+    #[test]
+    fn test_literal_arguments() {
+        let result = lexer("f(\"hi\", 'c')");
+
+        if result.is_err() {
+            eprintln!("{:?}", result);
+            assert!(false, "Error parsing literal arguments");
+        }
+
+        let tokens = result.unwrap();
+
+        assert_eq!(tokens, vec![Token::Identifier("f".to_string()), Token::ParenLeft, Token::String("hi".to_string()), Token::Comma, Token::Char('c'), Token::ParenRight], "Tokens not literal arguments");
+    }
     
     /*
 
@@ -5,6 +5,11 @@ pub mod type_class_parser;
 pub mod algabraic_type_parser;
 pub mod file_parser;
 pub mod function_parser;
+pub mod import_parser;
+pub mod expression_parser;
+pub mod global_parser;
+pub mod effect_parser;
+pub mod module_loader;
 
 
 
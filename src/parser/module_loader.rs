@@ -0,0 +1,311 @@
+use chumsky::prelude::*;
+use chumsky::Stream;
+
+use crate::interpreter::Interpreter;
+use crate::parser::lexer::{lexer_with_lines, end_of_lines, Token};
+use crate::parser::file_parser::{TopLevelStatement, module_parser, register_statements};
+use crate::parser::import_parser::Import;
+use crate::thread_pool::ThreadPool;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+
+/// The file extension that modules are stored under.
+/// `import std::IO;` will look for `std/IO.mil` relative to the directory of the entry file.
+pub const MODULE_EXTENSION: &str = "mil";
+
+#[derive(Debug)]
+pub enum ModuleError {
+    NotFound(String),
+    Io(String, io::Error),
+    Lex(String, Vec<Simple<char>>),
+    Parse(String, Vec<Simple<Token>>),
+    ImportCycle(Vec<String>),
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModuleError::NotFound(name) => write!(f, "Module not found: {}", name),
+            ModuleError::Io(name, error) => write!(f, "Unable to read module {}: {}", name, error),
+            ModuleError::Lex(name, errors) => write!(f, "Unable to lex module {}: {:?}", name, errors),
+            ModuleError::Parse(name, errors) => write!(f, "Unable to parse module {}: {:?}", name, errors),
+            ModuleError::ImportCycle(cycle) => write!(f, "Import cycle detected: {}", cycle.join(" -> ")),
+        }
+    }
+}
+
+/// A module that has been lexed and parsed on a worker thread.
+/// The dependencies are the names of the modules it imports, in the order they were imported.
+struct ParsedModule {
+    statements: Vec<TopLevelStatement>,
+    dependencies: Vec<String>,
+}
+
+fn module_path(root: &Path, name: &str) -> PathBuf {
+    root.join(name.split("::").collect::<PathBuf>()).with_extension(MODULE_EXTENSION)
+}
+
+/// This figures out which module an import refers to.
+/// `import std::IO::open;` can either be the module `std::IO::open` or the function `open` in `std::IO` so we check for both.
+fn resolve_import(root: &Path, import: &Import) -> Result<String, ModuleError> {
+    let name = import.module_name();
+    if module_path(root, &name).is_file() {
+        return Ok(name);
+    }
+    if import.items.is_empty() && import.path.len() > 1 {
+        let parent = import.path[..import.path.len() - 1].join("::");
+        if module_path(root, &parent).is_file() {
+            return Ok(parent);
+        }
+    }
+    Err(ModuleError::NotFound(name))
+}
+
+fn parse_module(root: &Path, name: &str, path: &Path) -> Result<ParsedModule, ModuleError> {
+    let contents = fs::read_to_string(path).map_err(|error| ModuleError::Io(name.to_string(), error))?;
+
+    let tokens = lexer_with_lines(&contents).map_err(|errors| ModuleError::Lex(name.to_string(), errors))?;
+    let tokens: Vec<_> = tokens.into_iter().filter(|(token, _)| !matches!(token, Token::Comment(_))).collect();
+    let eoi = end_of_lines(&tokens);
+
+    let statements = module_parser().then_ignore(end()).parse(Stream::from_iter(eoi, tokens.into_iter())).map_err(|errors| ModuleError::Parse(name.to_string(), errors))?;
+
+    let mut dependencies = Vec::new();
+    for statement in statements.iter() {
+        if let TopLevelStatement::Import(import) = statement {
+            let dependency = resolve_import(root, import)?;
+            if !dependencies.contains(&dependency) {
+                dependencies.push(dependency);
+            }
+        }
+    }
+
+    Ok(ParsedModule { statements, dependencies })
+}
+
+#[derive(PartialEq)]
+enum Mark {
+    Visiting,
+    Done,
+}
+
+/// This does a depth first search over the import graph so that every module comes after the modules it imports.
+/// The stack holds the chain of imports we are currently following so that we can report the whole cycle if we find one.
+fn visit(name: &str, modules: &HashMap<String, ParsedModule>, marks: &mut HashMap<String, Mark>, stack: &mut Vec<String>, order: &mut Vec<String>) -> Result<(), ModuleError> {
+    match marks.get(name) {
+        Some(Mark::Done) => return Ok(()),
+        Some(Mark::Visiting) => {
+            let start = stack.iter().position(|module| module == name).expect("Module being visited is not on the stack");
+            let mut cycle = stack[start..].to_vec();
+            cycle.push(name.to_string());
+            return Err(ModuleError::ImportCycle(cycle));
+        },
+        None => {},
+    }
+
+    marks.insert(name.to_string(), Mark::Visiting);
+    stack.push(name.to_string());
+    for dependency in modules[name].dependencies.iter() {
+        visit(dependency, modules, marks, stack, order)?;
+    }
+    stack.pop();
+    marks.insert(name.to_string(), Mark::Done);
+    order.push(name.to_string());
+    Ok(())
+}
+
+fn topological_order(entry: &str, modules: &HashMap<String, ParsedModule>) -> Result<Vec<String>, ModuleError> {
+    let mut marks = HashMap::new();
+    let mut stack = Vec::new();
+    let mut order = Vec::new();
+    visit(entry, modules, &mut marks, &mut stack, &mut order)?;
+    Ok(order)
+}
+
+/// This loads the entry file and everything it imports.
+/// The entry file is read from the path it was given, whatever its extension, and the modules it imports are found next to it.
+/// Every module is lexed and parsed as a task on `pool` as soon as we find out that it is needed, so no more run at once than the pool has workers.
+/// The modules are returned in topological order, so a module always comes after the modules it imports.
+pub(crate) fn load_modules(entry: &Path, pool: &ThreadPool) -> Result<Vec<(String, Vec<TopLevelStatement>)>, ModuleError> {
+    let root = entry.parent().unwrap_or(Path::new("")).to_path_buf();
+    let entry_name = entry.file_stem()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| ModuleError::NotFound(entry.display().to_string()))?
+        .to_string();
+    if !entry.is_file() {
+        return Err(ModuleError::NotFound(entry.display().to_string()));
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let spawn_task = |name: String, path: PathBuf| {
+        let sender = sender.clone();
+        let root = root.clone();
+        pool.spawn(move || {
+            let result = parse_module(&root, &name, &path);
+            // The receiver is only gone if another module already failed, so there is nobody left to tell
+            let _ = sender.send((name, result));
+        });
+    };
+
+    let mut requested = HashSet::new();
+    let mut modules = HashMap::new();
+    requested.insert(entry_name.clone());
+    spawn_task(entry_name.clone(), entry.to_path_buf());
+    let mut outstanding = 1;
+
+    while outstanding > 0 {
+        let (name, result) = receiver.recv().expect("Module loader task hung up");
+        outstanding -= 1;
+        let module = result?;
+        for dependency in module.dependencies.iter() {
+            if requested.insert(dependency.clone()) {
+                spawn_task(dependency.clone(), module_path(&root, dependency));
+                outstanding += 1;
+            }
+        }
+        modules.insert(name, module);
+    }
+
+    let order = topological_order(&entry_name, &modules)?;
+
+    Ok(order.into_iter().map(|name| {
+        let module = modules.remove(&name).expect("Module was not loaded");
+        (name, module.statements)
+    }).collect())
+}
+
+/// This loads a program and all of its imports into the interpreter.
+pub fn load_program(entry: &str, interpreter: &mut Interpreter) -> Result<(), ModuleError> {
+    for (_, statements) in load_modules(Path::new(entry), interpreter.thread_pool())? {
+        register_statements(statements, interpreter);
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod module_loader_tests {
+    use super::*;
+
+    fn create_modules(test_name: &str, modules: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("module_loader_{}_{}", test_name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (name, contents) in modules {
+            let path = module_path(&root, name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn test_dependency_order() {
+        let root = create_modules("order", &[
+            ("main", "import shapes;\nimport util::strings;\ntype Name = String"),
+            ("shapes", "import util::strings;\nproduct type Point { x: Int, y: Int }"),
+            ("util/strings", "type String = (List Char)"),
+        ]);
+
+        let result = load_modules(&module_path(&root, "main"), &ThreadPool::new(2));
+
+        if result.is_err() {
+            assert!(false, "Loader error: {}", result.err().unwrap());
+        }
+
+        let names: Vec<String> = result.unwrap().into_iter().map(|(name, _)| name).collect();
+
+        assert_eq!(names, vec!["util::strings".to_string(), "shapes".to_string(), "main".to_string()], "Modules are not in topological order");
+    }
+
+    #[test]
+    fn test_import_function_from_module() {
+        let root = create_modules("item", &[
+            ("main", "import util::open;"),
+            ("util", "type Path = String"),
+        ]);
+
+        let result = load_modules(&module_path(&root, "main"), &ThreadPool::new(2));
+
+        if result.is_err() {
+            assert!(false, "Loader error: {}", result.err().unwrap());
+        }
+
+        let names: Vec<String> = result.unwrap().into_iter().map(|(name, _)| name).collect();
+
+        assert_eq!(names, vec!["util".to_string(), "main".to_string()], "Import did not resolve to the parent module");
+    }
+
+    #[test]
+    fn test_import_cycle() {
+        let root = create_modules("cycle", &[
+            ("main", "import a;"),
+            ("a", "import b;"),
+            ("b", "import c;"),
+            ("c", "import a;"),
+        ]);
+
+        match load_modules(&module_path(&root, "main"), &ThreadPool::new(2)) {
+            Err(ModuleError::ImportCycle(cycle)) => {
+                assert_eq!(cycle, vec!["a".to_string(), "b".to_string(), "c".to_string(), "a".to_string()], "Cycle path is not correct");
+            },
+            Err(error) => assert!(false, "Wrong error: {}", error),
+            Ok(_) => assert!(false, "Import cycle was not detected"),
+        }
+    }
+
+    #[test]
+    fn test_missing_module() {
+        let root = create_modules("missing", &[
+            ("main", "import nowhere;"),
+        ]);
+
+        match load_modules(&module_path(&root, "main"), &ThreadPool::new(2)) {
+            Err(ModuleError::NotFound(name)) => assert_eq!(name, "nowhere".to_string()),
+            Err(error) => assert!(false, "Wrong error: {}", error),
+            Ok(_) => assert!(false, "Missing module was not reported"),
+        }
+    }
+
+    #[test]
+    fn test_entry_path_is_used_as_given() {
+        let root = create_modules("entry", &[
+            ("shapes", "import util;\nproduct type Point { x: Int, y: Int }"),
+            ("util", "type Name = String"),
+        ]);
+        let entry = root.join("program.txt");
+        fs::write(&entry, "import shapes;\nimport util;").unwrap();
+
+        // One worker has to get through every module on its own
+        let names: Vec<String> = match load_modules(&entry, &ThreadPool::new(1)) {
+            Ok(modules) => modules.into_iter().map(|(name, _)| name).collect(),
+            Err(error) => panic!("Loader error: {}", error),
+        };
+
+        assert_eq!(names, vec!["util".to_string(), "shapes".to_string(), "program".to_string()], "The entry file was not loaded from its own path");
+        assert!(matches!(load_modules(&root.join("program"), &ThreadPool::new(1)), Err(ModuleError::NotFound(_))), "The entry file was found under a different extension");
+    }
+
+    #[test]
+    fn test_load_program() {
+        let root = create_modules("program", &[
+            ("main", "import types;\nsum type (Maybe a) { Just(a), Nothing }"),
+            ("types", "product type Fixed { right: Int, left: UInt }"),
+        ]);
+        let mut interpreter = Interpreter::new();
+
+        let result = load_program(module_path(&root, "main").to_str().unwrap(), &mut interpreter);
+
+        if result.is_err() {
+            assert!(false, "Loader error: {}", result.err().unwrap());
+        }
+
+        let types = interpreter.get_valid_types();
+        assert_eq!(types.read().unwrap().len(), 2);
+    }
+}
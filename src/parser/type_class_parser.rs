@@ -5,6 +5,7 @@ use std::collections::HashMap;
 use crate::parser::lexer::{Token};
 use crate::parser::type_parser::{type_parser};
 use crate::types::{Type, Value, TypeUtils};
+use crate::parser::expression_parser::Expression;
 
 #[derive(Debug, Clone, )]
 pub struct TypeClass {
@@ -28,7 +29,7 @@ pub fn function_prototype_parser() -> impl Parser<Token, Result<Type,(String,Val
         .then_ignore(just(Token::FunctionReturn))
         .then(type_parser())
         .map(|(((_, name), args), return_type)| {
-            Value::Function(vec![], args.iter().map(|x| (String::new(), Some(x.clone()))).collect(), Vec::new(), return_type, HashMap::new(), String::new())
+            Value::Function(vec![], args.iter().map(|x| (String::new(), Some(x.clone()))).collect(), Vec::new(), return_type, HashMap::new(), Expression::Block(Vec::new()))
         });
 
 
@@ -48,7 +49,7 @@ pub fn function_prototype_parser() -> impl Parser<Token, Result<Type,(String,Val
         .then_ignore(just(Token::FunctionReturn))
         .then(type_parser())
         .map(|(((_, name), args), return_type)| {
-            Value::Function(vec![], args.iter().map(|x| (String::new(), Some(x.clone()))).collect(), Vec::new(), return_type, HashMap::new(), String::new())
+            Value::Function(vec![], args.iter().map(|x| (String::new(), Some(x.clone()))).collect(), Vec::new(), return_type, HashMap::new(), Expression::Block(Vec::new()))
         });
 
 
@@ -72,7 +73,7 @@ pub fn function_prototype_parser() -> impl Parser<Token, Result<Type,(String,Val
         .then_ignore(just(Token::FunctionReturn))
         .then(type_parser())
         .map(|(((_, name), args), return_type)| {
-            Value::Function(vec![], args.iter().map(|x| (String::new(), Some(x.clone()))).collect(), Vec::new(), return_type, HashMap::new(), String::new())
+            Value::Function(vec![], args.iter().map(|x| (String::new(), Some(x.clone()))).collect(), Vec::new(), return_type, HashMap::new(), Expression::Block(Vec::new()))
         });
 
 
@@ -90,7 +91,7 @@ pub fn function_prototype_parser() -> impl Parser<Token, Result<Type,(String,Val
         .then_ignore(just(Token::FunctionReturn))
         .then(type_parser())
         .map(|(((_, name), args), return_type)| {
-            Value::Function(vec![], args.iter().map(|x| (String::new(), Some(x.clone()))).collect(), Vec::new(), return_type, HashMap::new(), String::new())
+            Value::Function(vec![], args.iter().map(|x| (String::new(), Some(x.clone()))).collect(), Vec::new(), return_type, HashMap::new(), Expression::Block(Vec::new()))
         });
 
     let normal_function = choice((
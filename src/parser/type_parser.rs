@@ -441,6 +441,18 @@ mod type_parser_tests {
 }
 
 
+/// This parses the return type of a function definition, where a type list doesn't need parentheses.
+/// `fn main() -> IO () { ... }` returns `(IO ())`, which is safe since the body always starts with a curly brace.
+pub fn return_type_parser() -> impl Parser<Token, Type, Error = Simple<Token>> {
+    type_parser()
+        .repeated()
+        .at_least(1)
+        .map(|types: Vec<Type>| match types.as_slice() {
+            [single] => single.clone(),
+            _ => Type::TypeList{name: Box::new(types[0].clone()), parameters: types[1..].to_owned()},
+        })
+}
+
 pub fn type_statement_parser() -> impl Parser<Token, Type, Error = Simple<Token>> {
 
     just(Token::Colon).ignore_then(type_parser())
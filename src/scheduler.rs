@@ -0,0 +1,277 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+
+#[derive(Debug, Default)]
+struct SchedulerState {
+    random: u64,
+    next_task: usize,
+    tasks: Vec<usize>,// the tasks that haven't finished, in the order they were spawned
+    blocked: HashSet<usize>,// tasks that are waiting for something that hasn't happened yet
+    running: Option<usize>,
+    trace: Vec<usize>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl SchedulerState {
+    /// This is SplitMix64, which is small and gives the same numbers on every platform.
+    fn next_random(&mut self) -> u64 {
+        self.random = self.random.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.random;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// This hands the turn to a task that isn't blocked, or returns false if every task is blocked.
+    fn switch(&mut self) -> bool {
+        let runnable: Vec<usize> = self.tasks.iter().copied().filter(|task| !self.blocked.contains(task)).collect();
+        if runnable.is_empty() {
+            return false;
+        }
+        let next = runnable[(self.next_random() % runnable.len() as u64) as usize];
+        self.running = Some(next);
+        self.trace.push(next);
+        true
+    }
+}
+
+thread_local! {
+    // The scheduler that runs the current thread and the task the thread is
+    static CURRENT: RefCell<Option<(Arc<Scheduler>, usize)>> = const { RefCell::new(None) };
+}
+
+/// This runs every script thread one at a time so that a run can be replayed.
+/// Each thread is a real thread, but only the one holding the turn may run and the turn only changes at yield points:
+/// taking a lock, waiting for a promise, and sending or receiving on a channel.
+/// Who gets the turn next is picked by a random number generator seeded with `seed`, so the same seed always gives the same interleaving.
+/// Deadlines still use the clock, so a program that depends on timeouts can run differently with the same seed.
+#[derive(Debug)]
+pub struct Scheduler {
+    seed: u64,
+    state: Mutex<SchedulerState>,
+    turn: Condvar,
+}
+
+impl Scheduler {
+    pub fn new(seed: u64) -> Arc<Scheduler> {
+        Arc::new(Scheduler {
+            seed,
+            state: Mutex::new(SchedulerState { random: seed, ..SchedulerState::default() }),
+            turn: Condvar::new(),
+        })
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// This is every task that got the turn, in order.
+    pub fn trace(&self) -> Vec<usize> {
+        self.state().trace.clone()
+    }
+
+    fn state(&self) -> MutexGuard<'_, SchedulerState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn wait_for_turn<'a>(&self, mut state: MutexGuard<'a, SchedulerState>, task: usize) -> MutexGuard<'a, SchedulerState> {
+        while state.running != Some(task) {
+            state = self.turn.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        state
+    }
+
+    fn register(&self, state: &mut SchedulerState) -> usize {
+        let task = state.next_task;
+        state.next_task += 1;
+        state.tasks.push(task);
+        task
+    }
+
+    /// This runs `main` as the first task and then keeps giving the turn away until every other task has finished.
+    pub fn run_main<T>(self: &Arc<Self>, main: impl FnOnce() -> T) -> T {
+        let task = {
+            let mut state = self.state();
+            let task = self.register(&mut state);
+            state.running = Some(task);
+            state.trace.push(task);
+            task
+        };
+        CURRENT.with(|current| *current.borrow_mut() = Some((self.clone(), task)));
+
+        let result = main();
+        while self.state().tasks.len() > 1 {
+            block();
+        }
+
+        self.exit(task);
+        let handles: Vec<_> = self.state().handles.drain(..).collect();
+        for handle in handles {
+            let _ = handle.join();
+        }
+        result
+    }
+
+    /// This starts `work` on its own thread, which waits for the turn before it does anything.
+    /// Only a thread run by this scheduler can spawn onto it, since otherwise nobody could ever hand the new thread the turn.
+    pub fn spawn(self: &Arc<Self>, work: impl FnOnce() + Send + 'static) {
+        if !is_scheduled() {
+            panic!("Only a program started with start_program can spawn threads in deterministic mode");
+        }
+        let mut state = self.state();
+        let task = self.register(&mut state);
+        let scheduler = self.clone();
+        let handle = thread::Builder::new()
+            .name(format!("mil-task-{}", task))
+            .spawn(move || {
+                CURRENT.with(|current| *current.borrow_mut() = Some((scheduler.clone(), task)));
+                drop(scheduler.wait_for_turn(scheduler.state(), task));
+                // Tasks report their own panics, this just makes sure the turn is passed on
+                let _ = panic::catch_unwind(AssertUnwindSafe(work));
+                scheduler.exit(task);
+            })
+            .expect("Unable to start a task thread");
+        state.handles.push(handle);
+    }
+
+    fn exit(&self, task: usize) {
+        CURRENT.with(|current| *current.borrow_mut() = None);
+        let mut state = self.state();
+        state.tasks.retain(|other| *other != task);
+        // A finished task has fulfilled its promise, so anyone waiting on it gets to look again
+        state.blocked.clear();
+        if state.running == Some(task) {
+            state.running = None;
+            state.switch();
+        }
+        self.turn.notify_all();
+    }
+}
+
+fn current() -> Option<(Arc<Scheduler>, usize)> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+pub fn is_scheduled() -> bool {
+    CURRENT.with(|current| current.borrow().is_some())
+}
+
+/// This lets the scheduler pick who runs next, which might be the current thread again.
+pub fn yield_now() {
+    if let Some((scheduler, task)) = current() {
+        let mut state = scheduler.state();
+        state.switch();
+        scheduler.turn.notify_all();
+        drop(scheduler.wait_for_turn(state, task));
+    }
+}
+
+/// This gives the turn away until another thread makes progress.
+/// If every thread is blocked then nothing can ever happen again, so we panic with the seed to replay it with.
+pub fn block() {
+    if let Some((scheduler, task)) = current() {
+        let mut state = scheduler.state();
+        state.blocked.insert(task);
+        if !state.switch() {
+            state.blocked.remove(&task);
+            drop(state);
+            panic!("Every thread is blocked, the deterministic scheduler seed was {}", scheduler.seed);
+        }
+        scheduler.turn.notify_all();
+        drop(scheduler.wait_for_turn(state, task));
+    }
+}
+
+/// This tells the scheduler that something a blocked thread might be waiting for has happened, like a lock being released.
+pub fn progress() {
+    if let Some((scheduler, _)) = current() {
+        scheduler.state().blocked.clear();
+    }
+}
+
+/// This is how a scheduled thread waits for something.
+/// `attempt` is tried every time the thread gets the turn and the thread blocks until it succeeds or the deadline passes.
+/// It returns `None` if the deadline passed.
+pub fn wait_scheduled<T>(deadline: Option<Instant>, mut attempt: impl FnMut() -> Option<T>) -> Option<T> {
+    yield_now();
+    loop {
+        if let Some(result) = attempt() {
+            return Some(result);
+        }
+        match deadline {
+            Some(deadline) if Instant::now() >= deadline => return None,
+            // Time passing counts as progress so a thread with a deadline never blocks
+            Some(_) => yield_now(),
+            None => block(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod scheduler_tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::parser::file_parser::file_parser_helper;
+    use crate::types::Value;
+
+    use std::collections::HashMap;
+
+    const PROGRAM: &str = "@Atomic\nlast := 0u;\n\
+                           @ThreadShared\nevents = channel();\n\
+                           @ThreadSpawn\nfn work(n: UInt) -> UInt { send(events, n); send(events, n); swap(last, n) }\n\
+                           fn main() -> UInt { scope { work(1u); work(2u); work(3u); } 0u }";
+
+    fn run(seed: u64) -> (Vec<usize>, u64) {
+        let mut interpreter = Interpreter::with_worker_count(2);
+        interpreter.set_deterministic_seed(Some(seed));
+        file_parser_helper(PROGRAM, &mut interpreter);
+        interpreter.start_program().unwrap();
+
+        let trace = interpreter.scheduler().expect("Scheduler was not set").trace();
+        match interpreter.get_value("last", &HashMap::new()).unwrap() {
+            Some(Value::Atomic(last)) => match last.load() {
+                Value::UInt(last) => (trace, last),
+                other => panic!("last held {:?}", other),
+            },
+            other => panic!("last was {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_same_seed_same_interleaving() {
+        for seed in [1, 42, 1234] {
+            assert_eq!(run(seed), run(seed), "Seed {} gave two different runs", seed);
+        }
+    }
+
+    #[test]
+    fn test_seeds_change_interleaving() {
+        let traces: HashSet<_> = (0..10).map(|seed| run(seed).0).collect();
+
+        assert!(traces.len() > 1, "Every seed gave the same interleaving");
+    }
+
+    #[test]
+    fn test_blocked_threads_report_seed() {
+        let mut interpreter = Interpreter::with_worker_count(2);
+        interpreter.set_deterministic_seed(Some(7));
+        file_parser_helper("@ThreadShared\nnever = channel();\n\
+                            @ThreadSpawn\nfn stuck() -> UInt { recv(never) }\n\
+                            fn main() -> UInt { stuck() }", &mut interpreter);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| interpreter.start_program()));
+
+        let message = match result {
+            Ok(Err(error)) => error.to_string(),
+            Ok(Ok(())) => panic!("Program should not have finished"),
+            Err(payload) => payload.downcast_ref::<String>().cloned().unwrap_or_default(),
+        };
+        assert!(message.contains("seed was 7"), "Deadlock did not name the seed: {}", message);
+    }
+}
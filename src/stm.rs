@@ -0,0 +1,59 @@
+use crate::sync::{GlobalMutex, wait_for_global_write};
+use crate::types::Value;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+
+/// This is the log of an `atomically { ... }` block.
+/// Reads remember the version of the global they saw and writes are kept here until the block commits.
+/// Committing locks every global in the log in name order, checks that nothing that was read has changed, and then writes.
+#[derive(Debug, Clone, Default)]
+pub struct Transaction {
+    reads: BTreeMap<String, (Arc<GlobalMutex>, u64)>,
+    writes: BTreeMap<String, Value>,
+}
+
+impl Transaction {
+    pub fn new() -> Transaction {
+        Transaction::default()
+    }
+
+    /// This is the value the transaction wrote to a global, which it has to see instead of the committed one.
+    pub fn written(&self, name: &str) -> Option<Value> {
+        self.writes.get(name).cloned()
+    }
+
+    pub fn write(&mut self, name: &str, value: Value) {
+        self.writes.insert(name.to_string(), value);
+    }
+
+    /// This remembers the version of a global the first time it is read.
+    /// It returns false if anything read so far has changed, since then the block has seen two different states of the program.
+    pub fn record_read(&mut self, name: &str, mutex: Arc<GlobalMutex>, version: u64) -> bool {
+        self.reads.entry(name.to_string()).or_insert((mutex, version));
+        self.is_consistent()
+    }
+
+    /// This checks that no global the transaction read has been written since.
+    pub fn is_consistent(&self) -> bool {
+        self.reads.values().all(|(mutex, version)| mutex.version() == *version)
+    }
+
+    /// This is every global the transaction touched, in the order their locks are taken when committing.
+    pub fn globals(&self) -> BTreeSet<String> {
+        self.reads.keys().chain(self.writes.keys()).cloned().collect()
+    }
+
+    pub fn into_writes(self) -> BTreeMap<String, Value> {
+        self.writes
+    }
+
+    /// This blocks until one of the globals the transaction read is written by someone else.
+    pub fn wait_for_change(&self) {
+        if self.reads.is_empty() {
+            panic!("Used retry in an atomically block that hasn't read any Thread-Mutable globals so it would wait forever");
+        }
+        wait_for_global_write(|| !self.is_consistent());
+    }
+}
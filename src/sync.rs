@@ -0,0 +1,704 @@
+use crate::interpreter::{Variable, RuntimeError};
+use crate::deadlock::DeadlockReport;
+use crate::scheduler;
+use crate::thread_pool::{notify_progress, wait_helping};
+use crate::types::{Type, Value, TypeUtils};
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LockError {
+    Poisoned,
+    TimedOut,
+    Deadlock(DeadlockReport),
+}
+
+#[derive(Debug)]
+struct LockState {
+    owner: Option<ThreadId>,
+    depth: usize,
+    poisoned: bool,
+}
+
+/// This is the lock that protects a `@ThreadMutable` global.
+/// A thread waiting for the lock sleeps on a condition variable instead of spinning.
+/// The lock is reentrant so that a thread inside of a `lock(x) { ... }` block can still read and assign `x`.
+/// If a thread panics while holding the lock then the lock is poisoned and every later attempt to take it fails.
+/// The version goes up on every write so that an `atomically` block can tell if a global changed after it read it.
+/// Owners are threads rather than tasks, which works because a worker holding a lock never runs other tasks while it waits (see `wait_helping`).
+#[derive(Debug)]
+pub struct GlobalMutex {
+    version: AtomicU64,
+    state: Mutex<LockState>,
+    released: Condvar,
+    variable: Mutex<Variable>,
+}
+
+impl GlobalMutex {
+    pub fn new(variable: Variable) -> GlobalMutex {
+        GlobalMutex {
+            state: Mutex::new(LockState { owner: None, depth: 0, poisoned: false }),
+            released: Condvar::new(),
+            variable: Mutex::new(variable),
+            version: AtomicU64::new(0),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, LockState> {
+        // The state is only ever held for a few instructions so it can't be poisoned by the program
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// This takes the lock if it is free or already ours.
+    fn try_take(&self, state: &mut LockState, me: ThreadId) -> Option<Result<(), LockError>> {
+        if state.poisoned {
+            return Some(Err(LockError::Poisoned));
+        }
+        match state.owner {
+            None => {
+                state.owner = Some(me);
+                state.depth = 1;
+                Some(Ok(()))
+            },
+            Some(owner) if owner == me => {
+                state.depth += 1;
+                Some(Ok(()))
+            },
+            Some(_) => None,
+        }
+    }
+
+    /// This blocks until the lock is free or until the timeout runs out.
+    pub fn lock(self: &Arc<Self>, timeout: Option<Duration>) -> Result<GlobalGuard, LockError> {
+        let me = thread::current().id();
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        if scheduler::is_scheduled() {
+            return match scheduler::wait_scheduled(deadline, || self.try_take(&mut self.state(), me)) {
+                Some(result) => result.map(|_| GlobalGuard::new(self.clone())),
+                None => Err(LockError::TimedOut),
+            };
+        }
+        let mut state = self.state();
+        loop {
+            if let Some(result) = self.try_take(&mut state, me) {
+                return result.map(|_| GlobalGuard::new(self.clone()));
+            }
+            state = match deadline {
+                None => self.released.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(LockError::TimedOut);
+                    }
+                    self.released.wait_timeout(state, deadline - now).unwrap_or_else(|poisoned| poisoned.into_inner()).0
+                },
+            };
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    /// This is the thread holding the lock right now, if any.
+    pub fn owner(&self) -> Option<ThreadId> {
+        self.state().owner
+    }
+
+    fn unlock(&self) {
+        let mut state = self.state();
+        if thread::panicking() {
+            state.poisoned = true;
+        }
+        state.depth -= 1;
+        if state.depth == 0 {
+            state.owner = None;
+            self.released.notify_all();
+            drop(state);
+            scheduler::progress();
+        }
+    }
+}
+
+thread_local! {
+    // How many guards the current thread holds, where taking a lock again counts again
+    static GUARDS_HELD: Cell<usize> = const { Cell::new(0) };
+}
+
+/// This is true while the current thread is inside of the lock of any `@ThreadMutable` global.
+pub fn holds_global_lock() -> bool {
+    GUARDS_HELD.with(|held| held.get() > 0)
+}
+
+/// This holds a `GlobalMutex` until it is dropped.
+pub struct GlobalGuard {
+    mutex: Arc<GlobalMutex>,
+}
+
+impl GlobalGuard {
+    fn new(mutex: Arc<GlobalMutex>) -> GlobalGuard {
+        GUARDS_HELD.with(|held| held.set(held.get() + 1));
+        GlobalGuard { mutex }
+    }
+
+    pub fn variable(&self) -> MutexGuard<'_, Variable> {
+        // Only the owner of the lock can get here so this never waits
+        self.mutex.variable.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// This writes the global and bumps its version, waking up every transaction that is waiting for a global to change.
+    pub fn replace_value(&self, value: Value) {
+        self.variable().replace_value(value);
+        self.mutex.version.fetch_add(1, Ordering::SeqCst);
+        notify_progress();
+    }
+}
+
+/// This blocks until `changed` is true, checking it again after every write to a `@ThreadMutable` global.
+/// It is how a transaction that used `retry` waits for one of the globals it read.
+pub fn wait_for_global_write(changed: impl Fn() -> bool) {
+    wait_helping(None, || changed().then_some(())).expect("Waiting without a deadline never times out");
+}
+
+impl Drop for GlobalGuard {
+    fn drop(&mut self) {
+        GUARDS_HELD.with(|held| held.set(held.get() - 1));
+        self.mutex.unlock();
+    }
+}
+
+/// This is the value a `@ThreadSpawn` function hands back before it has finished.
+/// The worker fills it in exactly once and every clone waits on the same result, so a promise can be read by as many threads as we want.
+/// If the worker panics then the promise holds the runtime error instead of a value.
+#[derive(Debug, Clone)]
+pub struct Promise {
+    state: Arc<PromiseState>,
+}
+
+#[derive(Debug)]
+struct PromiseState {
+    result: Mutex<Option<Result<Value, RuntimeError>>>,
+    fulfilled: Condvar,
+}
+
+impl Promise {
+    pub fn new() -> Promise {
+        Promise {
+            state: Arc::new(PromiseState {
+                result: Mutex::new(None),
+                fulfilled: Condvar::new(),
+            }),
+        }
+    }
+
+    fn result(&self) -> MutexGuard<'_, Option<Result<Value, RuntimeError>>> {
+        self.state.result.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Only the first result is kept, so a promise can never change once someone has read it.
+    pub fn fulfil(&self, result: Result<Value, RuntimeError>) {
+        let mut slot = self.result();
+        if slot.is_none() {
+            *slot = Some(result);
+            self.state.fulfilled.notify_all();
+            drop(slot);
+            scheduler::progress();
+            notify_progress();
+        }
+    }
+
+    /// This blocks until the worker has finished.
+    pub fn wait(&self) -> Result<Value, RuntimeError> {
+        if scheduler::is_scheduled() {
+            return scheduler::wait_scheduled(None, || self.result().clone()).expect("Waiting without a deadline never times out");
+        }
+        let mut slot = self.result();
+        loop {
+            if let Some(result) = slot.as_ref() {
+                return result.clone();
+            }
+            slot = self.state.fulfilled.wait(slot).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    /// This is like `wait` but gives up after the timeout.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<Result<Value, RuntimeError>> {
+        let deadline = Instant::now() + timeout;
+        if scheduler::is_scheduled() {
+            return scheduler::wait_scheduled(Some(deadline), || self.result().clone());
+        }
+        let mut slot = self.result();
+        loop {
+            if let Some(result) = slot.as_ref() {
+                return Some(result.clone());
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            slot = self.state.fulfilled.wait_timeout(slot, deadline - now).unwrap_or_else(|poisoned| poisoned.into_inner()).0;
+        }
+    }
+
+    /// This is the result if the worker has finished, without waiting for it.
+    pub fn try_result(&self) -> Option<Result<Value, RuntimeError>> {
+        self.result().clone()
+    }
+
+    pub fn is_fulfilled(&self) -> bool {
+        self.result().is_some()
+    }
+}
+
+impl Default for Promise {
+    fn default() -> Self {
+        Promise::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelError {
+    Closed,
+    TimedOut,
+    WrongType(Type),//The channel only holds values of this type
+}
+
+#[derive(Debug)]
+struct ChannelQueue {
+    values: VecDeque<Value>,
+    capacity: Option<usize>,
+    element_type: Option<Type>,
+    closed: bool,
+}
+
+#[derive(Debug)]
+struct ChannelState {
+    queue: Mutex<ChannelQueue>,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+/// This is a queue of values that threads use to talk to each other.
+/// A bounded channel makes senders wait while it is full and an unbounded one never does.
+/// Once a channel is closed nothing else can be sent, but the values that are already in it can still be received.
+/// The type of the values is either given when the channel is made or fixed by the first value that is sent.
+#[derive(Debug, Clone)]
+pub struct Channel {
+    state: Arc<ChannelState>,
+}
+
+impl Channel {
+    pub fn new(capacity: Option<usize>, element_type: Option<Type>) -> Channel {
+        Channel {
+            state: Arc::new(ChannelState {
+                queue: Mutex::new(ChannelQueue {
+                    values: VecDeque::new(),
+                    capacity: capacity.map(|capacity| capacity.max(1)),
+                    element_type,
+                    closed: false,
+                }),
+                not_empty: Condvar::new(),
+                not_full: Condvar::new(),
+            }),
+        }
+    }
+
+    fn queue(&self) -> MutexGuard<'_, ChannelQueue> {
+        self.state.queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// This is the type of the values in the channel, which is a type variable until something has been sent.
+    pub fn element_type(&self) -> Type {
+        self.queue().element_type.clone().unwrap_or(Type::Single("a".to_string()))
+    }
+
+    /// This waits for a deadline on one of the condition variables, or forever if there is no deadline.
+    /// It returns `None` once the deadline has passed.
+    fn wait<'a>(condvar: &Condvar, queue: MutexGuard<'a, ChannelQueue>, deadline: Option<Instant>) -> Option<MutexGuard<'a, ChannelQueue>> {
+        match deadline {
+            None => Some(condvar.wait(queue).unwrap_or_else(|poisoned| poisoned.into_inner())),
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return None;
+                }
+                Some(condvar.wait_timeout(queue, deadline - now).unwrap_or_else(|poisoned| poisoned.into_inner()).0)
+            },
+        }
+    }
+
+    /// This takes the value and puts it in the channel if there is room, and otherwise leaves it where it is.
+    fn try_send(&self, queue: &mut ChannelQueue, value: &mut Option<Value>) -> Option<Result<(), ChannelError>> {
+        if queue.closed {
+            return Some(Err(ChannelError::Closed));
+        }
+        let value_type = value.as_ref().expect("Value was already sent").get_type();
+        match queue.element_type {
+            Some(ref element_type) if *element_type != value_type => return Some(Err(ChannelError::WrongType(element_type.clone()))),
+            _ => {},
+        }
+        if queue.capacity.is_some_and(|capacity| queue.values.len() >= capacity) {
+            return None;
+        }
+        queue.element_type = Some(value_type);
+        queue.values.push_back(value.take().expect("Value was already sent"));
+        self.state.not_empty.notify_one();
+        notify_progress();
+        Some(Ok(()))
+    }
+
+    /// This takes the oldest value out of the channel if there is one.
+    fn try_recv(&self, queue: &mut ChannelQueue) -> Option<Result<Value, ChannelError>> {
+        if let Some(value) = queue.values.pop_front() {
+            self.state.not_full.notify_one();
+            notify_progress();
+            return Some(Ok(value));
+        }
+        queue.closed.then_some(Err(ChannelError::Closed))
+    }
+
+    /// This is `send` without waiting, which leaves the value where it is if the channel is full.
+    pub fn poll_send(&self, value: &mut Option<Value>) -> Option<Result<(), ChannelError>> {
+        let result = self.try_send(&mut self.queue(), value);
+        if result.is_some() {
+            scheduler::progress();
+        }
+        result
+    }
+
+    /// This is `recv` without waiting, which gives `None` if the channel is empty but still open.
+    pub fn poll_recv(&self) -> Option<Result<Value, ChannelError>> {
+        let result = self.try_recv(&mut self.queue());
+        if result.is_some() {
+            scheduler::progress();
+        }
+        result
+    }
+
+    pub fn send(&self, value: Value, timeout: Option<Duration>) -> Result<(), ChannelError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        if scheduler::is_scheduled() {
+            let mut value = Some(value);
+            let result = scheduler::wait_scheduled(deadline, || self.try_send(&mut self.queue(), &mut value));
+            scheduler::progress();
+            return result.unwrap_or(Err(ChannelError::TimedOut));
+        }
+        let mut value = Some(value);
+        let mut queue = self.queue();
+        loop {
+            if let Some(result) = self.try_send(&mut queue, &mut value) {
+                return result;
+            }
+            queue = Channel::wait(&self.state.not_full, queue, deadline).ok_or(ChannelError::TimedOut)?;
+        }
+    }
+
+    /// This takes the oldest value out of the channel.
+    /// It only fails with `Closed` once the channel is closed and empty.
+    pub fn recv(&self, timeout: Option<Duration>) -> Result<Value, ChannelError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        if scheduler::is_scheduled() {
+            let result = scheduler::wait_scheduled(deadline, || self.try_recv(&mut self.queue()));
+            scheduler::progress();
+            return result.unwrap_or(Err(ChannelError::TimedOut));
+        }
+        let mut queue = self.queue();
+        loop {
+            if let Some(result) = self.try_recv(&mut queue) {
+                return result;
+            }
+            queue = Channel::wait(&self.state.not_empty, queue, deadline).ok_or(ChannelError::TimedOut)?;
+        }
+    }
+
+    pub fn close(&self) {
+        self.queue().closed = true;
+        self.state.not_empty.notify_all();
+        self.state.not_full.notify_all();
+        scheduler::progress();
+        notify_progress();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.queue().closed
+    }
+}
+
+static NEXT_ACTOR: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug)]
+struct ActorState {
+    id: usize,
+    supervisors: Mutex<Vec<Actor>>,
+}
+
+/// This is the address of an actor.
+/// Messages go into the actor's mailbox and the actor handles them one at a time on its own thread.
+/// Supervisors are the actors that get told when this one fails.
+#[derive(Debug, Clone)]
+pub struct Actor {
+    mailbox: Channel,
+    state: Arc<ActorState>,
+}
+
+impl Actor {
+    pub fn new() -> Actor {
+        Actor {
+            mailbox: Channel::new(None, None),
+            state: Arc::new(ActorState {
+                id: NEXT_ACTOR.fetch_add(1, Ordering::SeqCst),
+                supervisors: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.state.id
+    }
+
+    pub fn mailbox(&self) -> &Channel {
+        &self.mailbox
+    }
+
+    /// This is the type of the messages the actor has been sent, which is a type variable until it gets one.
+    pub fn message_type(&self) -> Type {
+        self.mailbox.element_type()
+    }
+
+    /// Sending to an actor that has stopped does nothing, the same as a letter to an empty house.
+    /// A message of the wrong type is refused.
+    pub fn tell(&self, message: Value) -> Result<(), RuntimeError> {
+        let message_type = message.get_type();
+        match self.mailbox.send(message, None) {
+            Err(ChannelError::WrongType(element_type)) => Err(RuntimeError::ChannelTypeMismatch(element_type, message_type)),
+            _ => Ok(()),
+        }
+    }
+
+    /// The actor finishes the messages it already has and then stops.
+    pub fn stop(&self) {
+        self.mailbox.close();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.mailbox.is_closed()
+    }
+
+    pub fn add_supervisor(&self, supervisor: Actor) {
+        self.state.supervisors.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(supervisor);
+    }
+
+    pub fn supervisors(&self) -> Vec<Actor> {
+        self.state.supervisors.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+}
+
+impl Default for Actor {
+    fn default() -> Self {
+        Actor::new()
+    }
+}
+
+/// This is a flag that tasks check to find out if they should stop early.
+/// Nothing is stopped by force, a task has to look at the token itself.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// This keeps track of every task spawned inside of a `scope { ... }` block.
+/// Tasks spawned by those tasks are added to the same scope so the block can wait for all of them.
+#[derive(Debug, Clone, Default)]
+pub struct TaskScope {
+    tasks: Arc<Mutex<Vec<Promise>>>,
+}
+
+impl TaskScope {
+    pub fn new() -> TaskScope {
+        TaskScope::default()
+    }
+
+    pub fn add(&self, promise: Promise) {
+        self.tasks.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(promise);
+    }
+
+    /// This hands back the tasks that have been added since the last time this was called.
+    pub fn take_tasks(&self) -> Vec<Promise> {
+        std::mem::take(&mut *self.tasks.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+}
+
+
+#[cfg(test)]
+mod global_mutex_tests {
+    use super::*;
+    use crate::types::Value;
+
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_reentrant_lock() {
+        let mutex = Arc::new(GlobalMutex::new(Variable::new(Value::Int(1))));
+
+        let outer = mutex.lock(None).unwrap();
+        let inner = mutex.lock(Some(Duration::from_millis(10)));
+
+        assert!(inner.is_ok(), "The owner of a lock should be able to take it again");
+        inner.unwrap().variable().replace_value(Value::Int(2));
+        assert!(matches!(outer.variable().get_immutable(), Value::Int(2)), "Value was not updated");
+    }
+
+    #[test]
+    fn test_lock_timeout() {
+        let mutex = Arc::new(GlobalMutex::new(Variable::new(Value::Int(1))));
+        let (locked, wait_for_lock) = mpsc::channel();
+        let (finished, wait_for_finish) = mpsc::channel::<()>();
+
+        let holder = {
+            let mutex = mutex.clone();
+            thread::spawn(move || {
+                let _guard = mutex.lock(None).unwrap();
+                locked.send(()).unwrap();
+                let _ = wait_for_finish.recv();
+            })
+        };
+        wait_for_lock.recv().unwrap();
+
+        let result = mutex.lock(Some(Duration::from_millis(20)));
+        assert!(matches!(result, Err(LockError::TimedOut)), "Lock should have timed out");
+
+        finished.send(()).unwrap();
+        holder.join().unwrap();
+        assert!(mutex.lock(Some(Duration::from_millis(20))).is_ok(), "Lock should be free after the holder finished");
+    }
+
+    #[test]
+    fn test_waiter_wakes_up() {
+        let mutex = Arc::new(GlobalMutex::new(Variable::new(Value::Int(0))));
+        let guard = mutex.lock(None).unwrap();
+
+        let waiter = {
+            let mutex = mutex.clone();
+            thread::spawn(move || {
+                let guard = mutex.lock(None).unwrap();
+                let value = guard.variable().get_immutable();
+                value
+            })
+        };
+        thread::sleep(Duration::from_millis(20));
+        guard.variable().replace_value(Value::Int(5));
+        drop(guard);
+
+        assert!(matches!(waiter.join().unwrap(), Value::Int(5)), "Waiter did not see the value written by the holder");
+    }
+
+    #[test]
+    fn test_poisoned_lock() {
+        let mutex = Arc::new(GlobalMutex::new(Variable::new(Value::Int(1))));
+
+        let result = {
+            let mutex = mutex.clone();
+            thread::spawn(move || {
+                let _guard = mutex.lock(None).unwrap();
+                panic!("Thread died while holding the lock");
+            }).join()
+        };
+
+        assert!(result.is_err(), "Thread should have panicked");
+        assert!(matches!(mutex.lock(None), Err(LockError::Poisoned)), "Lock should be poisoned");
+    }
+}
+
+
+#[cfg(test)]
+mod promise_tests {
+    use super::*;
+
+    #[test]
+    fn test_many_readers() {
+        let promise = Promise::new();
+
+        let readers: Vec<_> = (0..4).map(|_| {
+            let promise = promise.clone();
+            thread::spawn(move || promise.wait())
+        }).collect();
+        thread::sleep(Duration::from_millis(20));
+        promise.fulfil(Ok(Value::Int(3)));
+        promise.fulfil(Ok(Value::Int(4)));
+
+        for reader in readers {
+            assert!(matches!(reader.join().unwrap(), Ok(Value::Int(3))), "Reader did not see the first result");
+        }
+        assert!(promise.is_fulfilled(), "Promise should be fulfilled");
+    }
+}
+
+
+#[cfg(test)]
+mod channel_tests {
+    use super::*;
+
+    #[test]
+    fn test_bounded_channel_blocks_sender() {
+        let channel = Channel::new(Some(1), None);
+
+        channel.send(Value::Int(1), None).unwrap();
+        assert_eq!(channel.send(Value::Int(2), Some(Duration::from_millis(20))), Err(ChannelError::TimedOut), "Send on a full channel should wait");
+
+        let receiver = {
+            let channel = channel.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                channel.recv(None)
+            })
+        };
+        channel.send(Value::Int(2), None).unwrap();
+
+        assert!(matches!(receiver.join().unwrap(), Ok(Value::Int(1))), "Values came out of order");
+        assert!(matches!(channel.recv(None), Ok(Value::Int(2))), "Second value was lost");
+    }
+
+    #[test]
+    fn test_closed_channel_drains() {
+        let channel = Channel::new(None, None);
+        channel.send(Value::Char('a'), None).unwrap();
+        channel.close();
+
+        assert_eq!(channel.send(Value::Char('b'), None), Err(ChannelError::Closed), "Send on a closed channel should fail");
+        assert!(matches!(channel.recv(None), Ok(Value::Char('a'))), "Values sent before closing should still arrive");
+        assert!(matches!(channel.recv(None), Err(ChannelError::Closed)), "Empty closed channel should report that it is closed");
+        assert!(matches!(channel.recv(Some(Duration::ZERO)), Err(ChannelError::Closed)), "Empty closed channel should report that it is closed");
+    }
+
+    #[test]
+    fn test_channel_element_type() {
+        let channel = Channel::new(None, None);
+        let typed = Channel::new(None, Some(Type::Single("Char".to_string())));
+        channel.send(Value::Int(1), None).unwrap();
+        typed.close();
+
+        assert_eq!(channel.send(Value::Char('a'), None), Err(ChannelError::WrongType(Type::Single("Int".to_string()))));
+        assert_eq!(typed.send(Value::Int(1), None), Err(ChannelError::Closed));
+        assert_eq!(typed.element_type(), Type::Single("Char".to_string()), "Sending on a closed channel changed its type");
+    }
+}
@@ -0,0 +1,390 @@
+use crate::scheduler;
+use crate::sync::holds_global_lock;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+
+/// The environment variable that sets how many workers the pool can have.
+pub const WORKER_COUNT_VARIABLE: &str = "MIL_THREADS";
+
+type Task = Box<dyn FnOnce() + Send + 'static>;
+
+#[derive(Debug, Default)]
+struct PoolState {
+    queued: usize,     // tasks sitting in a queue that nobody has claimed yet
+    outstanding: usize,// tasks that have been spawned and haven't finished
+    spawned: usize,
+    blocked: usize,    // workers waiting inside of a global lock, which don't count against the maximum
+    shutdown: bool,
+}
+
+struct Shared {
+    injector: Mutex<VecDeque<Task>>,
+    queues: Vec<Mutex<VecDeque<Task>>>,
+    state: Mutex<PoolState>,
+    work_available: Condvar,
+    idle: Condvar,
+    max_workers: usize,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+}
+
+thread_local! {
+    // Which pool the current thread works for so that tasks it spawns go onto its own queue
+    static WORKER: RefCell<Option<(Arc<Shared>, usize)>> = const { RefCell::new(None) };
+}
+
+// This counts everything that `wait_helping` might be waiting for, so that a waiter can sleep until the count changes
+static PROGRESS: Mutex<u64> = Mutex::new(0);
+static PROGRESSED: Condvar = Condvar::new();
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // Tasks run outside of every lock so nothing can panic while one of these is held
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// This wakes up every thread in `wait_helping` so that it checks again whether what it waits for is ready.
+/// It has to be called after the change it announces, like a promise being fulfilled, a channel being used, a global being written, or a task being queued.
+pub fn notify_progress() {
+    *lock(&PROGRESS) += 1;
+    PROGRESSED.notify_all();
+}
+
+impl Shared {
+    /// Every queued task has to be claimed before it is taken so that a worker never goes looking for a task that isn't there.
+    fn claim(&self) -> bool {
+        let mut state = lock(&self.state);
+        if state.queued > 0 {
+            state.queued -= 1;
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    /// This finds a claimed task.
+    /// Workers take the newest task from their own queue, and otherwise the oldest task from the injector or from another worker.
+    /// Workers started in place of blocked ones have no queue of their own.
+    fn take(&self, index: Option<usize>) -> Task {
+        loop {
+            if let Some(task) = index.and_then(|index| self.queues.get(index)).and_then(|queue| lock(queue).pop_back()) {
+                return task;
+            }
+            if let Some(task) = lock(&self.injector).pop_front() {
+                return task;
+            }
+            let start = index.map_or(0, |index| index + 1);
+            for offset in 0..self.queues.len() {
+                let victim = (start + offset) % self.queues.len();
+                if let Some(task) = lock(&self.queues[victim]).pop_front() {
+                    return task;
+                }
+            }
+            // Someone pushed the task we claimed but it isn't visible to us yet
+            thread::yield_now();
+        }
+    }
+
+    /// This starts another worker if there are more tasks than workers and fewer running workers than the maximum.
+    /// A blocked worker doesn't count as running, so that the tasks it is waiting for still get a thread.
+    fn start_worker_if_needed(self: &Arc<Self>, state: &mut PoolState) {
+        if state.spawned - state.blocked >= self.max_workers || state.spawned >= state.outstanding {
+            return;
+        }
+        let shared = self.clone();
+        let index = state.spawned;
+        state.spawned += 1;
+        let handle = thread::Builder::new()
+            .name(format!("mil-worker-{}", index))
+            .spawn(move || worker_loop(shared, index))
+            .expect("Unable to start a worker thread");
+        lock(&self.handles).push(handle);
+    }
+
+    fn run(&self, task: Task) {
+        // Tasks report their own panics, this just keeps the worker alive
+        let _ = panic::catch_unwind(AssertUnwindSafe(task));
+        let mut state = lock(&self.state);
+        state.outstanding -= 1;
+        if state.outstanding == 0 {
+            self.idle.notify_all();
+        }
+    }
+}
+
+fn worker_loop(shared: Arc<Shared>, index: usize) {
+    WORKER.with(|worker| *worker.borrow_mut() = Some((shared.clone(), index)));
+    loop {
+        {
+            let mut state = lock(&shared.state);
+            while state.queued == 0 && !state.shutdown {
+                state = shared.work_available.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner());
+            }
+            if state.queued == 0 {
+                break;
+            }
+            state.queued -= 1;
+        }
+        let task = shared.take(Some(index));
+        shared.run(task);
+    }
+    WORKER.with(|worker| *worker.borrow_mut() = None);
+}
+
+/// This is the pool that `@ThreadSpawn` functions run on.
+/// Workers are only started when there is work for them, up to the maximum, and each one has its own queue that the others steal from when they run dry.
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+}
+
+impl std::fmt::Debug for ThreadPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ThreadPool").field("max_workers", &self.shared.max_workers).finish()
+    }
+}
+
+impl ThreadPool {
+    pub fn new(max_workers: usize) -> ThreadPool {
+        let max_workers = max_workers.max(1);
+        ThreadPool {
+            shared: Arc::new(Shared {
+                injector: Mutex::new(VecDeque::new()),
+                queues: (0..max_workers).map(|_| Mutex::new(VecDeque::new())).collect(),
+                state: Mutex::new(PoolState::default()),
+                work_available: Condvar::new(),
+                idle: Condvar::new(),
+                max_workers,
+                handles: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// This picks the pool size from `MIL_THREADS` and falls back to the number of cores.
+    pub fn default_worker_count() -> usize {
+        env::var(WORKER_COUNT_VARIABLE).ok()
+            .and_then(|count| count.trim().parse::<usize>().ok())
+            .filter(|count| *count > 0)
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |count| count.get()))
+    }
+
+    pub fn max_workers(&self) -> usize {
+        self.shared.max_workers
+    }
+
+    pub fn spawn(&self, task: impl FnOnce() + Send + 'static) {
+        if lock(&self.shared.state).shutdown {
+            panic!("Tried to spawn a task on a thread pool that has been shut down");
+        }
+        let task: Task = Box::new(task);
+        let own_queue = WORKER.with(|worker| match worker.borrow().as_ref() {
+            Some((shared, index)) if Arc::ptr_eq(shared, &self.shared) => Some(*index),
+            _ => None,
+        });
+        match own_queue.and_then(|index| self.shared.queues.get(index)) {
+            Some(queue) => lock(queue).push_back(task),
+            None => lock(&self.shared.injector).push_back(task),
+        }
+
+        let mut state = lock(&self.shared.state);
+        state.queued += 1;
+        state.outstanding += 1;
+        self.shared.start_worker_if_needed(&mut state);
+        self.shared.work_available.notify_one();
+        drop(state);
+        // Workers waiting on something else can run the task in the meantime
+        notify_progress();
+    }
+
+    /// This blocks until every task that has been spawned has finished, including the tasks that those tasks spawned.
+    pub fn wait_for_idle(&self) {
+        let mut state = lock(&self.shared.state);
+        while state.outstanding > 0 {
+            state = self.shared.idle.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    /// This lets the outstanding work finish and then stops the workers.
+    /// It can't be called from inside of a task since it waits for every task to finish.
+    pub fn shutdown(&self) {
+        self.wait_for_idle();
+        lock(&self.shared.state).shutdown = true;
+        self.shared.work_available.notify_all();
+        let handles: Vec<_> = lock(&self.shared.handles).drain(..).collect();
+        for handle in handles {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // The last interpreter can be dropped on a worker so we can't join here, the workers finish the queue and leave by themselves
+        lock(&self.shared.state).shutdown = true;
+        self.shared.work_available.notify_all();
+    }
+}
+
+fn current_worker() -> Option<(Arc<Shared>, usize)> {
+    WORKER.with(|worker| worker.borrow().as_ref().map(|(shared, index)| (shared.clone(), *index)))
+}
+
+/// This runs one queued task if the current thread is a worker and there is a task to run.
+/// A worker that is waiting on a promise calls this so that it keeps the pool moving instead of blocking a worker that the promise might need.
+/// A worker inside of a global lock never does, since the task would run inside of that lock as well.
+pub fn help_with_pending_task() -> bool {
+    if holds_global_lock() {
+        return false;
+    }
+    match current_worker() {
+        Some((shared, index)) if shared.claim() => {
+            let task = shared.take(Some(index));
+            shared.run(task);
+            true
+        },
+        _ => false,
+    }
+}
+
+pub fn is_worker_thread() -> bool {
+    WORKER.with(|worker| worker.borrow().is_some())
+}
+
+/// This waits for something that another task might be responsible for, like a promise or a message, and returns `None` if the deadline passes first.
+/// `ready` must not block, and it is checked again every time `notify_progress` is called.
+/// Workers run queued tasks while they wait so that the pool can't stall on itself.
+/// A worker inside of a global lock only sleeps like any other thread instead, and the pool starts a worker in its place while it waits.
+pub fn wait_helping<T>(deadline: Option<Instant>, mut ready: impl FnMut() -> Option<T>) -> Option<T> {
+    if scheduler::is_scheduled() {
+        return scheduler::wait_scheduled(deadline, ready);
+    }
+    let helping = is_worker_thread() && !holds_global_lock();
+    let _blocked = if helping { None } else { current_worker().map(|(shared, _)| BlockedWorker::new(shared)) };
+    loop {
+        // Reading the count first means that a change after we checked always wakes us up
+        let seen = *lock(&PROGRESS);
+        if let Some(result) = ready() {
+            return Some(result);
+        }
+        if helping && help_with_pending_task() {
+            continue;
+        }
+        let mut progress = lock(&PROGRESS);
+        while *progress == seen {
+            progress = match deadline {
+                None => PROGRESSED.wait(progress).unwrap_or_else(|poisoned| poisoned.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    PROGRESSED.wait_timeout(progress, deadline - now).unwrap_or_else(|poisoned| poisoned.into_inner()).0
+                },
+            };
+        }
+    }
+}
+
+/// This marks a worker as blocked for as long as it is alive.
+struct BlockedWorker {
+    shared: Arc<Shared>,
+}
+
+impl BlockedWorker {
+    fn new(shared: Arc<Shared>) -> BlockedWorker {
+        let mut state = lock(&shared.state);
+        state.blocked += 1;
+        shared.start_worker_if_needed(&mut state);
+        drop(state);
+        BlockedWorker { shared }
+    }
+}
+
+impl Drop for BlockedWorker {
+    fn drop(&mut self) {
+        lock(&self.shared.state).blocked -= 1;
+    }
+}
+
+
+#[cfg(test)]
+mod thread_pool_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_many_tasks_few_threads() {
+        let pool = ThreadPool::new(4);
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..10_000 {
+            let counter = counter.clone();
+            pool.spawn(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        pool.shutdown();
+
+        assert_eq!(counter.load(Ordering::SeqCst), 10_000, "Some tasks did not run");
+        assert!(lock(&pool.shared.state).spawned <= 4, "Pool started more workers than it is allowed");
+    }
+
+    #[test]
+    fn test_nested_tasks_are_joined() {
+        let pool = Arc::new(ThreadPool::new(2));
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..10 {
+            let inner_pool = pool.clone();
+            let counter = counter.clone();
+            pool.spawn(move || {
+                for _ in 0..10 {
+                    let counter = counter.clone();
+                    inner_pool.spawn(move || {
+                        thread::sleep(Duration::from_millis(1));
+                        counter.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            });
+        }
+        pool.shutdown();
+
+        assert_eq!(counter.load(Ordering::SeqCst), 100, "Shutdown did not wait for nested tasks");
+    }
+
+    #[test]
+    fn test_waiting_wakes_on_progress() {
+        let ready = Arc::new(AtomicUsize::new(0));
+        let setter = ready.clone();
+
+        let waker = thread::spawn(move || {
+            setter.store(1, Ordering::SeqCst);
+            notify_progress();
+        });
+        let woken = wait_helping(None, || (ready.load(Ordering::SeqCst) == 1).then_some(true));
+        let timed_out = wait_helping(Some(Instant::now() + Duration::from_millis(10)), || None::<()>);
+        waker.join().unwrap();
+
+        assert_eq!(woken, Some(true), "Waiting did not see the change it was told about");
+        assert_eq!(timed_out, None, "Waiting past the deadline did not give up");
+    }
+
+    #[test]
+    fn test_worker_survives_panic() {
+        let pool = ThreadPool::new(1);
+        let (sender, receiver) = mpsc::channel();
+
+        pool.spawn(|| panic!("Task failed"));
+        pool.spawn(move || sender.send(is_worker_thread()).unwrap());
+
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(true), "Worker did not keep running after a panic");
+        pool.shutdown();
+    }
+}
@@ -1,19 +1,20 @@
 
 use crate::parser::function_parser::Attribute;
+use crate::parser::expression_parser::Expression;
+use crate::sync::{Promise, Channel, CancelToken, Actor};
+use crate::builtins::file::FileHandle;
+use crate::interpreter::NativeFunction;
 
 use std::collections::HashMap;
-use std::sync::{Arc,RwLock};
+use std::sync::{Arc,Mutex,MutexGuard};
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicU8, Ordering};
 use std::fmt;
-use std::cell::{RefCell, RefMut, Ref};
-use std::rc::Rc;
 use std::cmp::PartialEq;
-use std::thread::JoinHandle;
+use std::hash::{Hash, Hasher};
 
-use core::marker::{Sync,Send};
 
 
-
-#[derive(Debug, Clone,Eq, Hash)]
+#[derive(Debug, Clone,Eq)]
 pub enum Type {
     TypeList {
         name: Box<Type>,
@@ -40,9 +41,71 @@ impl Type {
     }
 }
 
+/// Strings are lists of characters, so `String` is the same type as `List Char`.
+fn is_string_type(the_type: &Type) -> bool {
+    match the_type {
+        Type::TypeList{name, parameters} => **name == Type::Single("List".to_string()) && matches!(parameters.as_slice(), [Type::Single(element)] if element == "Char"),
+        _ => false,
+    }
+}
+
+/// This looks through references and aliases and gives `String`, `()` and H-Lists the one name each of them has when matching instances.
+fn normalised(the_type: &Type) -> Type {
+    match the_type {
+        Type::Ref(inner) | Type::Alias(inner, _) => normalised(inner),
+        Type::Unit => Type::Tuple(Vec::new()),
+        Type::Single(name) if name == "String" => Type::TypeList{name: Box::new(Type::Single("List".to_string())), parameters: vec![Type::Single("Char".to_string())]},
+        Type::Single(name) if is_hlist_name(name) => Type::Single("H-List".to_string()),
+        other => other.clone(),
+    }
+}
+
+fn is_type_variable(name: &str) -> bool {
+    name.chars().count() == 1
+}
+
+fn match_type(pattern: &Type, the_type: &Type, bindings: &mut HashMap<String, Type>) -> bool {
+    let match_all = |patterns: &[Type], types: &[Type], bindings: &mut HashMap<String, Type>| {
+        patterns.len() == types.len() && patterns.iter().zip(types).all(|(pattern, the_type)| match_type(pattern, the_type, bindings))
+    };
+    match (normalised(pattern), normalised(the_type)) {
+        (Type::Single(any), _) | (_, Type::Single(any)) if any == "Any" => true,
+        (Type::Single(variable), the_type) if is_type_variable(&variable) => match bindings.get(&variable) {
+            Some(bound) => *bound == the_type,
+            None => {
+                bindings.insert(variable, the_type);
+                true
+            },
+        },
+        (Type::Single(a), Type::Single(b)) => a == b,
+        (Type::TypeList{name: a, parameters: b}, Type::TypeList{name: c, parameters: d}) => match_type(&a, &c, bindings) && match_all(&b, &d, bindings),
+        (Type::Tuple(a), Type::Tuple(b)) => match_all(&a, &b, bindings),
+        (Type::Function{parameters: a, return_type: b, ..}, Type::Function{parameters: c, return_type: d, ..}) => match_all(&a, &c, bindings) && match_type(&b, &d, bindings),
+        _ => false,
+    }
+}
+
+/// This matches the type of a typeclass instance against the type of a value, where a one letter name in the instance is a type variable.
+/// It gives back how many type variables were bound, so that the most specific instance can be picked, or `None` if the value doesn't fit.
+pub fn match_instance(instance: &Type, the_type: &Type) -> Option<usize> {
+    let mut bindings = HashMap::new();
+    match_type(instance, the_type, &mut bindings).then_some(bindings.len())
+}
+
+/// `...` is the type of a variadic parameter, which is an H-List of every argument that didn't get a parameter of its own.
+pub fn is_varargs_type(the_type: &Type) -> bool {
+    matches!(the_type, Type::Single(name) if name == "...")
+}
+
+fn is_hlist_name(name: &str) -> bool {
+    name == "..." || name == "H-List"
+}
+
 impl PartialEq for Type {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
+            (Type::Single(any), _) | (_, Type::Single(any)) if any == "Any" => true,
+            (Type::Single(a), Type::Single(b)) if is_hlist_name(a) && is_hlist_name(b) => true,
             (Type::Single(a), Type::Single(b)) => {
                 if a == "Any" || b == "Any" {
                     true
@@ -58,12 +121,14 @@ impl PartialEq for Type {
             (Type::Function{parameters: a, effects: b, return_type: c}, Type::Function{parameters: d, effects: e, return_type: f}) => a == d && b == e && c == f,
             (Type::TypeList{name: a, parameters: b}, Type::TypeList{name: c, parameters: d}) => a == c && b == d,
             (Type::Unit, Type::Unit) => true,
+            (Type::Unit, Type::Tuple(types)) | (Type::Tuple(types), Type::Unit) => types.is_empty(),
             (Type::Ref(a), Type::Ref(b)) => a == b,
             (Type::Ref(a), b) => **a == *b,
             (a, Type::Ref(b)) => *a == **b,
             (Type::Alias(a, b), Type::Alias(c, d)) => a == c && b == d,
             (Type::Alias(a, b), c) => **a == *c,
             (a, Type::Alias(b, c)) => *a == **b,
+            (Type::Single(name), list) | (list, Type::Single(name)) if name == "String" => is_string_type(list),
             _ => false,
         }
     }
@@ -72,6 +137,14 @@ impl PartialEq for Type {
         !self.eq(other)
     }
 }
+
+/// `Any` is equal to types of every shape, so the only hash that agrees with `==` is one that is the same for every type.
+/// Maps keyed by types are small, so they just compare their keys one by one, which also lets `String` find `List Char` and `()` find the empty tuple.
+impl Hash for Type {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u8(0);
+    }
+}
     
 
 
@@ -224,17 +297,18 @@ pub enum Value {
     Float(f64),
     Char(char),
     Byte(u8),
-    List(Vec<Value>, Type),
+    List(Arc<Vec<Value>>, Type),//Lists are immutable so every copy of a list shares its elements
     //Vector(Rc<RefCell<[Value]>>, Type),
     Tuple(Vec<Value>),
+    HList(Vec<Value>),//Heterogeneous list, its elements don't have to share a type so they are never checked against each other
     Function(Vec<Attribute>,//Attributes
         Vec<(String, Option<Type>)>,//Mapping of variable to type
              Vec<Type>,//TODO: add in effects
              Type,//Return type
              HashMap<String, Value>,//Mapping of variable to value. This allows us to have higher order functions
-             String,//Function body
-    ),// TODO: add in function body
-    Promise(Arc<RwLock<JoinHandle<Value>>>, Type,),//Return Value from a multi-threaded function
+             Expression,//Function body
+    ),
+    Promise(Promise, Type,),//Return Value from a multi-threaded function
     Algebraic {
         agb_type: AlgebraicType,
         types: Vec<Type>,
@@ -247,6 +321,36 @@ pub enum Value {
         value: Box<Value>,
     },
     Ref(ValRef),
+    Atomic(AtomicValue),//Global number that is shared between threads without a lock
+    Channel(Channel),//Queue for sending values between threads
+    CancelToken(CancelToken),//Flag for asking tasks to stop
+    Actor(Actor),//Address of an actor's mailbox
+    IO(Arc<IoAction>, Type),//An action that only happens when it is run, Type is the type of what it makes
+    File(FileHandle),//Handle to a file opened with open
+}
+
+/// This is the action inside of an `IO` value, which is built up with `pure`, `>>=` and `>>`.
+/// Building it does nothing, the interpreter only runs it when main returns it or when a function that doesn't give back IO finishes.
+/// An action used as a statement is chained in front of what its block gives back.
+#[derive(Debug, Clone)]
+pub enum IoAction {
+    Pure(Value),
+    Native(NativeFunction, Vec<Value>),//A built-in function that gets called with these arguments when the action runs
+    Bind(Arc<IoAction>, Value),//The function gets what the action made and gives back the action to run next
+    Then(Arc<IoAction>, Arc<IoAction>),
+    Located(usize, Arc<IoAction>),//The line of the do block statement the action came from
+}
+
+impl IoAction {
+    pub fn is_sendable(&self) -> bool {
+        match self {
+            IoAction::Pure(value) => value.is_sendable(),
+            IoAction::Native(_, arguments) => arguments.iter().all(|value| value.is_sendable()),
+            IoAction::Bind(action, function) => action.is_sendable() && function.is_sendable(),
+            IoAction::Then(first, second) => first.is_sendable() && second.is_sendable(),
+            IoAction::Located(_, action) => action.is_sendable(),
+        }
+    }
 }
 
 impl Value {
@@ -260,7 +364,7 @@ impl Value {
     pub fn set_value(&mut self, value: Value) {
         match self {
             Value::Ref(r) => {
-                *r.value.borrow_mut() = value;
+                *r.borrow_mut() = value;
             },
             _ => {},
         }
@@ -275,6 +379,7 @@ impl Value {
             Value::Ref(r) => {
                 r.borrow().clone()
             },
+            Value::Atomic(a) => a.load(),
             _ => self.clone(),
         }
     }
@@ -289,27 +394,143 @@ impl Value {
     }
 }
 
-unsafe impl Sync for ValRef {}
-unsafe impl Send for ValRef {}
+impl Value {
+    /// This checks if a value can be handed to another thread.
+    /// Mutable references belong to the thread that made them so any value that contains one has to stay where it is.
+    pub fn is_sendable(&self) -> bool {
+        match self {
+            Value::Ref(_) => false,
+            Value::List(values, _) => values.iter().all(|value| value.is_sendable()),
+            Value::Tuple(values) | Value::HList(values) => values.iter().all(|value| value.is_sendable()),
+            Value::Function(_, _, _, _, captured, _) => captured.values().all(|value| value.is_sendable()),
+            Value::Algebraic{values, ..} => values.values().all(|value| value.is_sendable()),
+            Value::Alias{value, ..} => value.is_sendable(),
+            Value::IO(action, _) => action.is_sendable(),
+            _ => true,
+        }
+    }
+}
 
+/// This is a mutable cell made with `:=`.
+/// The cell is behind a mutex so that sharing a Value between threads is always safe,
+/// but the interpreter never lets a reference leave the thread that made it so the mutex is never contended.
 #[derive(Debug,Clone)]
 pub struct ValRef {
-    pub value: Rc<RefCell<Value>>,
+    value: Arc<Mutex<Value>>,
 }
 
 impl ValRef {
     pub fn new(value: Value) -> Self {
         ValRef {
-            value: Rc::new(RefCell::new(value)),
+            value: Arc::new(Mutex::new(value)),
+        }
+    }
+
+    pub fn borrow(&self) -> MutexGuard<'_, Value> {
+        // A panic while the cell is held can't leave a Value half written so we don't care about poisoning here
+        self.value.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn borrow_mut(&self) -> MutexGuard<'_, Value> {
+        self.borrow()
+    }
+}
+
+/// This is the storage for a global marked with `@Atomic`.
+/// Only numbers can be atomic, and the Arc lets every thread share the same number without a mutex.
+/// All operations use sequentially consistent ordering and arithmetic wraps around on overflow.
+#[derive(Debug, Clone)]
+pub enum AtomicValue {
+    Int(Arc<AtomicI64>),
+    UInt(Arc<AtomicU64>),
+    Byte(Arc<AtomicU8>),
+}
+
+impl AtomicValue {
+    pub fn new(value: Value) -> Option<Self> {
+        match value {
+            Value::Int(i) => Some(AtomicValue::Int(Arc::new(AtomicI64::new(i)))),
+            Value::UInt(i) => Some(AtomicValue::UInt(Arc::new(AtomicU64::new(i)))),
+            Value::Byte(i) => Some(AtomicValue::Byte(Arc::new(AtomicU8::new(i)))),
+            _ => None,
+        }
+    }
+
+    pub fn load(&self) -> Value {
+        match self {
+            AtomicValue::Int(a) => Value::Int(a.load(Ordering::SeqCst)),
+            AtomicValue::UInt(a) => Value::UInt(a.load(Ordering::SeqCst)),
+            AtomicValue::Byte(a) => Value::Byte(a.load(Ordering::SeqCst)),
+        }
+    }
+
+    /// This converts a number into the type of the atomic.
+    /// We allow any number type as long as it fits since there is no way to write a Byte literal.
+    fn operand(&self, value: &Value) -> i128 {
+        let (number, min, max) = match (self, value.get_immutable()) {
+            (AtomicValue::Int(_), Value::Int(i)) => (i as i128, i64::MIN as i128, i64::MAX as i128),
+            (AtomicValue::Int(_), Value::UInt(i)) => (i as i128, i64::MIN as i128, i64::MAX as i128),
+            (AtomicValue::Int(_), Value::Byte(i)) => (i as i128, i64::MIN as i128, i64::MAX as i128),
+            (AtomicValue::UInt(_), Value::Int(i)) => (i as i128, 0, u64::MAX as i128),
+            (AtomicValue::UInt(_), Value::UInt(i)) => (i as i128, 0, u64::MAX as i128),
+            (AtomicValue::UInt(_), Value::Byte(i)) => (i as i128, 0, u64::MAX as i128),
+            (AtomicValue::Byte(_), Value::Int(i)) => (i as i128, 0, u8::MAX as i128),
+            (AtomicValue::Byte(_), Value::UInt(i)) => (i as i128, 0, u8::MAX as i128),
+            (AtomicValue::Byte(_), Value::Byte(i)) => (i as i128, 0, u8::MAX as i128),
+            (_, other) => panic!("Tried to use a value of type {} with an atomic {}", other.get_type(), self.load().get_type()),
+        };
+        if number < min || number > max {
+            panic!("Value {} does not fit in an atomic {}", number, self.load().get_type());
+        }
+        number
+    }
+
+    pub fn store(&self, value: &Value) {
+        let value = self.operand(value);
+        match self {
+            AtomicValue::Int(a) => a.store(value as i64, Ordering::SeqCst),
+            AtomicValue::UInt(a) => a.store(value as u64, Ordering::SeqCst),
+            AtomicValue::Byte(a) => a.store(value as u8, Ordering::SeqCst),
         }
     }
 
-    pub fn borrow(&self) -> Ref<Value> {
-        self.value.borrow()
+    pub fn swap(&self, value: &Value) -> Value {
+        let value = self.operand(value);
+        match self {
+            AtomicValue::Int(a) => Value::Int(a.swap(value as i64, Ordering::SeqCst)),
+            AtomicValue::UInt(a) => Value::UInt(a.swap(value as u64, Ordering::SeqCst)),
+            AtomicValue::Byte(a) => Value::Byte(a.swap(value as u8, Ordering::SeqCst)),
+        }
     }
 
-    pub fn borrow_mut(&self) -> RefMut<Value> {
-        self.value.borrow_mut()
+    pub fn fetch_add(&self, value: &Value) -> Value {
+        let value = self.operand(value);
+        match self {
+            AtomicValue::Int(a) => Value::Int(a.fetch_add(value as i64, Ordering::SeqCst)),
+            AtomicValue::UInt(a) => Value::UInt(a.fetch_add(value as u64, Ordering::SeqCst)),
+            AtomicValue::Byte(a) => Value::Byte(a.fetch_add(value as u8, Ordering::SeqCst)),
+        }
+    }
+
+    pub fn fetch_sub(&self, value: &Value) -> Value {
+        let value = self.operand(value);
+        match self {
+            AtomicValue::Int(a) => Value::Int(a.fetch_sub(value as i64, Ordering::SeqCst)),
+            AtomicValue::UInt(a) => Value::UInt(a.fetch_sub(value as u64, Ordering::SeqCst)),
+            AtomicValue::Byte(a) => Value::Byte(a.fetch_sub(value as u8, Ordering::SeqCst)),
+        }
+    }
+
+    /// This stores the new value if the atomic holds the current value.
+    /// The value that was in the atomic is returned either way so the exchange succeeded if it matches current.
+    pub fn compare_exchange(&self, current: &Value, new: &Value) -> Value {
+        let current = self.operand(current);
+        let new = self.operand(new);
+        match self {
+            AtomicValue::Int(a) => Value::Int(a.compare_exchange(current as i64, new as i64, Ordering::SeqCst, Ordering::SeqCst).unwrap_or_else(|v| v)),
+            AtomicValue::UInt(a) => Value::UInt(a.compare_exchange(current as u64, new as u64, Ordering::SeqCst, Ordering::SeqCst).unwrap_or_else(|v| v)),
+            AtomicValue::Byte(a) => Value::Byte(a.compare_exchange(current as u8, new as u8, Ordering::SeqCst, Ordering::SeqCst).unwrap_or_else(|v| v)),
+        }
     }
 }
 
@@ -324,18 +545,69 @@ impl Clone for Value {
             Value::List(i, t) => Value::List(i.clone(), t.clone()),
             //Value::Vector(i, t) => Value::Vector(i.clone(), t.clone()),
             Value::Tuple(i) => Value::Tuple(i.clone()),
+            Value::HList(i) => Value::HList(i.clone()),
             Value::Function(a, b, c, d, e, f) => Value::Function(a.clone(), b.clone(), c.clone(), d.clone(), e.clone(), f.clone()),
-            Value::Promise(_, _) => panic!("Cannot clone a promise"),
+            Value::Promise(p, t) => Value::Promise(p.clone(), t.clone()),
             Value::Algebraic{agb_type, types, name, values} => Value::Algebraic{agb_type: agb_type.clone(), types: types.clone(), name: name.clone(), values: values.clone()},
             Value::Alias{parent, name, value} => Value::Alias{parent: parent.clone(), name: name.clone(), value: value.clone()},
-            Value::Ref(_) => panic!("Cannot clone a reference"),
+            Value::Ref(r) => Value::Ref(r.clone()),
+            Value::Atomic(a) => Value::Atomic(a.clone()),
+            Value::Channel(c) => Value::Channel(c.clone()),
+            Value::CancelToken(t) => Value::CancelToken(t.clone()),
+            Value::Actor(a) => Value::Actor(a.clone()),
+            Value::IO(a, t) => Value::IO(a.clone(), t.clone()),
+            Value::File(f) => Value::File(f.clone()),
         }
    }
 }
 
 impl Value {
-    pub fn create_promise(handle: Arc<RwLock<JoinHandle<Value>>>, the_type: Type) -> Value {
-        Value::Promise(handle, the_type)
+    pub fn create_promise(promise: Promise, the_type: Type) -> Value {
+        Value::Promise(promise, the_type)
+    }
+
+    /// This makes the `Just` case of a `Maybe`.
+    pub fn just(value: Value) -> Value {
+        let the_type = value.get_type();
+        Value::Algebraic{agb_type: AlgebraicType::Sum, types: vec![the_type], name: "Maybe".to_string(), values: HashMap::from([(Type::Single("Just".to_string()), value)])}
+    }
+
+    /// This makes the `Nothing` case of a `Maybe`.
+    pub fn nothing(the_type: Type) -> Value {
+        Value::Algebraic{agb_type: AlgebraicType::Sum, types: vec![the_type], name: "Maybe".to_string(), values: HashMap::from([(Type::Single("Nothing".to_string()), Value::Tuple(Vec::new()))])}
+    }
+
+    /// This makes a `Bool`, which is a sum type with the cases `True` and `False`.
+    pub fn bool(value: bool) -> Value {
+        let name = if value { "True" } else { "False" };
+        Value::Algebraic{agb_type: AlgebraicType::Sum, types: Vec::new(), name: "Bool".to_string(), values: HashMap::from([(Type::Single(name.to_string()), Value::Tuple(Vec::new()))])}
+    }
+
+    /// This makes an `IO` action that makes the value without doing anything else.
+    pub fn pure_io(value: Value) -> Value {
+        let the_type = value.get_type();
+        Value::IO(Arc::new(IoAction::Pure(value)), the_type)
+    }
+
+    /// This makes an `IO` action that calls a built-in function when it runs.
+    pub fn native_io(function: NativeFunction, arguments: Vec<Value>, the_type: Type) -> Value {
+        Value::IO(Arc::new(IoAction::Native(function, arguments)), the_type)
+    }
+
+    /// This makes a `String`, which is a list of characters.
+    pub fn string(string: &str) -> Value {
+        Value::List(Arc::new(string.chars().map(Value::Char).collect()), Type::Single("Char".to_string()))
+    }
+
+    /// This gives back the text of a string, which is a list of characters.
+    pub fn as_string(&self) -> Option<String> {
+        match self {
+            Value::List(values, _) => values.iter().map(|value| match value {
+                Value::Char(c) => Some(*c),
+                _ => None,
+            }).collect(),
+            _ => None,
+        }
     }
 
     pub fn create_reference(&self) -> Value {
@@ -357,12 +629,21 @@ impl TypeUtils for Value {
             Value::Byte(_) => Type::Single("Byte".to_string()),
             Value::List(_, t) => Type::TypeList{name: Box::new(Type::Single("List".to_string())), parameters: vec![t.get_type()]},
             //Value::Vector(_, t) => Type::TypeList{name: Box::new(Type::Single("Vector".to_string())), parameters: vec![t.get_type()]},
+            Value::Tuple(values) if values.is_empty() => Type::Unit,
             Value::Tuple(values) => Type::Tuple(values.iter().map(|v| v.get_type()).collect()),
             Value::Function(_,parameters, effects, return_type, _, _) => Type::Function{parameters: parameters.iter().map(|(_, t)| t.get_type()).collect(), effects: effects.clone(), return_type: Box::new(return_type.get_type())},
             Value::Promise(_, t) => Type::TypeList{name: Box::new(Type::Single("Promise".to_string())), parameters: vec![t.get_type()]},
+            Value::Algebraic{types, name, ..} if types.is_empty() => Type::Single(name.clone()),
             Value::Algebraic{agb_type, types, name, values} => Type::TypeList{ name: Box::new(Type::Single(name.clone())), parameters: types.iter().map(|t| t.get_type()).collect()},
             Value::Alias{parent, name, value} => name.get_type(),
-            Value::Ref(i) => i.value.borrow().get_type(),
+            Value::Ref(i) => i.borrow().get_type(),
+            Value::Atomic(a) => a.load().get_type(),
+            Value::Channel(c) => Type::TypeList{name: Box::new(Type::Single("Channel".to_string())), parameters: vec![c.element_type()]},
+            Value::CancelToken(_) => Type::Single("CancelToken".to_string()),
+            Value::File(_) => Type::Single("File".to_string()),
+            Value::HList(_) => Type::Single("H-List".to_string()),
+            Value::Actor(a) => Type::TypeList{name: Box::new(Type::Single("Actor".to_string())), parameters: vec![a.message_type()]},
+            Value::IO(_, t) => Type::TypeList{name: Box::new(Type::Single("IO".to_string())), parameters: vec![t.clone()]},
         }
     }
 